@@ -0,0 +1,3 @@
+fn main() -> std::process::ExitCode {
+    notes_classic_lib::cli::run_cli()
+}
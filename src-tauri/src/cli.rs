@@ -0,0 +1,319 @@
+use crate::db;
+use crate::services::prelude::*;
+use crate::services::*;
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "Usage: notes-classic-cli [--data-dir DIR] [--settings-dir DIR] <command> [options]
+
+The library is opened with the app's settings (notebook layout, search
+tokenizer, language) from --settings-dir, or from the portable settings folder.
+
+Commands:
+  export <dest-dir> [--archive] [--base PACKAGE]
+                                         Write a Notes Classic export package, or
+                                         only the changes since PACKAGE
+  import <package> [<incremental>...] [--no-backup] [--merge] [--dry-run]
+                                         Replace the library with an export package
+                                         and apply incremental packages in order, or
+                                         merge the package into the library
+  search <query> [--notebook ID] [--sort relevance|updated|created|title]
+                                         Search notes (supports tag:, notebook:,
+                                         created:, -term, \"phrase\" ...)
+  list-notebooks                         List notebooks and stacks
+  add-note --title TITLE [--notebook ID] [--file PATH]
+                                         Create a note from a file or stdin
+  backup                                 Copy the database and files to data/backups
+  vacuum                                 Compact the database and search index
+  reindex                                Re-extract the searchable text of every note
+";
+
+const VALUE_OPTIONS: &[&str] = &[
+    "--data-dir",
+    "--settings-dir",
+    "--notebook",
+    "--title",
+    "--file",
+    "--base",
+    "--sort",
+];
+
+struct CliArgs {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl CliArgs {
+    fn parse(raw: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = CliArgs {
+            positional: Vec::new(),
+            options: HashMap::new(),
+            flags: Vec::new(),
+        };
+        let mut raw = raw.peekable();
+        while let Some(arg) = raw.next() {
+            if let Some((key, value)) = arg.split_once('=').filter(|_| arg.starts_with("--")) {
+                args.options.insert(key.to_string(), value.to_string());
+            } else if VALUE_OPTIONS.contains(&arg.as_str()) {
+                let value = raw
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))?;
+                args.options.insert(arg, value);
+            } else if arg.starts_with("--") {
+                args.flags.push(arg);
+            } else {
+                args.positional.push(arg);
+            }
+        }
+        Ok(args)
+    }
+    fn option(&self, key: &str) -> Option<&str> {
+        self.options.get(key).map(|v| v.as_str())
+    }
+    fn option_i64(&self, key: &str) -> Result<Option<i64>, String> {
+        self.option(key)
+            .map(|v| v.parse::<i64>().map_err(|_| format!("Invalid {}", key)))
+            .transpose()
+    }
+    fn flag(&self, key: &str) -> bool {
+        self.flags.iter().any(|f| f == key)
+    }
+    fn arg(&self, index: usize, name: &str) -> Result<&str, String> {
+        self.positional
+            .get(index)
+            .map(|v| v.as_str())
+            .ok_or_else(|| format!("Missing {}\n\n{}", name, USAGE))
+    }
+}
+
+pub fn run_cli() -> ExitCode {
+    run_cli_with(std::env::args().skip(1))
+}
+
+/// Runs one CLI invocation; `raw` holds the arguments after the program name.
+/// Usage errors exit with 2 and failed commands with 1.
+pub fn run_cli_with(raw: impl IntoIterator<Item = String>) -> ExitCode {
+    let args = match CliArgs::parse(raw.into_iter()) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::from(2);
+        }
+    };
+    if args.positional.is_empty() || args.flag("--help") {
+        print!("{}", USAGE);
+        return if args.positional.is_empty() && !args.flag("--help") {
+            ExitCode::from(2)
+        } else {
+            ExitCode::SUCCESS
+        };
+    }
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(run_command(&args)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
+
+async fn run_command(args: &CliArgs) -> Result<(), String> {
+    let data_dir = match args.option("--data-dir") {
+        Some(dir) => {
+            let dir = PathBuf::from(dir);
+            ensure_dir_writable(&dir)?;
+            dir
+        }
+        None => resolve_portable_paths()?.0,
+    };
+    let settings_dir = match args.option("--settings-dir") {
+        Some(dir) => PathBuf::from(dir),
+        None => resolve_portable_paths()?.1,
+    };
+    let command = args.arg(0, "command")?;
+    if !matches!(
+        command,
+        "export"
+            | "import"
+            | "search"
+            | "list-notebooks"
+            | "add-note"
+            | "backup"
+            | "vacuum"
+            | "reindex"
+    ) {
+        return Err(format!("Unknown command: {}\n\n{}", command, USAGE));
+    }
+    if command == "import" && args.flag("--dry-run") {
+        // Pre-flight only reads the package; the library is not opened.
+        let manifest_path = PathBuf::from(args.arg(1, "manifest path")?);
+        let incremental_paths: Vec<PathBuf> =
+            args.positional[2..].iter().map(PathBuf::from).collect();
+        let report = preflight_notes_classic_chain(&manifest_path, &incremental_paths)?;
+        return print_json(&report);
+    }
+    let pool = open_library(&data_dir, &settings_dir).await?;
+    let repo = SqliteRepository { pool: pool.clone() };
+    let result = match command {
+        "export" => {
+            let dest = PathBuf::from(args.arg(1, "destination folder")?);
+            fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+            let options = ExportOptions {
+                archive: args.flag("--archive"),
+                base: args.option("--base").map(PathBuf::from),
+            };
+            let report =
+                export_notes_classic_package(&pool, &data_dir, &dest, &options, &report_progress)
+                    .await?;
+            print_json(&report)
+        }
+        "import" => {
+            let manifest_path = PathBuf::from(args.arg(1, "manifest path")?);
+            let backup_dir = if args.flag("--no-backup") {
+                None
+            } else {
+                checkpoint_database(&pool).await?;
+                let backup_dir = create_storage_backup(&data_dir, "cli-import")?;
+                eprintln!("backup: {}", backup_dir.to_string_lossy());
+                Some(backup_dir)
+            };
+            let incremental_paths: Vec<PathBuf> =
+                args.positional[2..].iter().map(PathBuf::from).collect();
+            let report = if args.flag("--merge") {
+                if !incremental_paths.is_empty() {
+                    return Err("Incremental packages cannot be merged".to_string());
+                }
+                merge_notes_classic_package(
+                    &pool,
+                    &data_dir,
+                    &manifest_path,
+                    backup_dir.as_deref(),
+                    &report_progress,
+                )
+                .await?
+            } else {
+                import_notes_classic_chain(
+                    &pool,
+                    &data_dir,
+                    &manifest_path,
+                    &incremental_paths,
+                    backup_dir.as_deref(),
+                    &report_progress,
+                )
+                .await?
+            };
+            print_json(&report)
+        }
+        "search" => {
+            let query = args.positional[1..].join(" ");
+            if query.trim().is_empty() {
+                return Err(format!("Missing query\n\n{}", USAGE));
+            }
+            let notes = repo
+                .search_notes(
+                    &query,
+                    args.option_i64("--notebook")?,
+                    args.option("--sort").unwrap_or_default().parse()?,
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut out = std::io::stdout().lock();
+            for note in notes {
+                let _ = writeln!(out, "{}\t{}", note.id, note.title);
+            }
+            Ok(())
+        }
+        "list-notebooks" => {
+            let notebooks = repo.get_notebooks().await.map_err(|e| e.to_string())?;
+            let mut out = std::io::stdout().lock();
+            for notebook in notebooks {
+                let parent = notebook
+                    .parent_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default();
+                let _ = writeln!(out, "{}\t{}\t{}", notebook.id, parent, notebook.name);
+            }
+            Ok(())
+        }
+        "add-note" => {
+            let title = args
+                .option("--title")
+                .ok_or_else(|| format!("Missing --title\n\n{}", USAGE))?;
+            let content = match args.option("--file") {
+                Some(path) => fs::read_to_string(path).map_err(|e| e.to_string())?,
+                None => {
+                    let mut content = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut content)
+                        .map_err(|e| e.to_string())?;
+                    content
+                }
+            };
+            let id = repo
+                .create_note(title, &content, args.option_i64("--notebook")?, &data_dir)
+                .await
+                .map_err(|e| e.to_string())?;
+            println!("{}", id);
+            Ok(())
+        }
+        "backup" => {
+            checkpoint_database(&pool).await?;
+            let backup_dir = create_storage_backup(&data_dir, "cli-backup")?;
+            println!("{}", backup_dir.to_string_lossy());
+            Ok(())
+        }
+        "vacuum" => {
+            let db_path = data_dir.join("notes.db");
+            let before = fs::metadata(&db_path).map(|m| m.len()).unwrap_or(0);
+            db::optimize_fts_tables(&pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            sqlx::query("VACUUM")
+                .execute(&pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            checkpoint_database(&pool).await?;
+            let after = fs::metadata(&db_path).map(|m| m.len()).unwrap_or(0);
+            println!("{} -> {} bytes", before, after);
+            Ok(())
+        }
+        "reindex" => {
+            let count = repo.reindex_notes_text().await.map_err(|e| e.to_string())?;
+            println!("{} notes reindexed", count);
+            Ok(())
+        }
+        _ => unreachable!(),
+    };
+    pool.close().await;
+    result
+}
+
+fn report_progress(event: PackageProgress) {
+    if event.state == "done" {
+        eprintln!("{}: {}/{}", event.stage, event.current, event.total);
+    }
+}
+
+async fn checkpoint_database(pool: &SqlitePool) -> Result<(), String> {
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,30 @@
+use std::fmt;
+
+/// Failure of a repository call that can refuse a request or fail outside the
+/// database, so callers can tell those cases apart from query errors.
+#[derive(Debug)]
+pub enum RepoError {
+    /// The request breaks a rule of the library, e.g. moving a notebook into
+    /// one of its own notebooks.
+    Invalid(String),
+    /// Storing a copy of a file failed.
+    Storage(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::Invalid(message) | RepoError::Storage(message) => f.write_str(message),
+            RepoError::Database(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {}
+
+impl From<sqlx::Error> for RepoError {
+    fn from(err: sqlx::Error) -> Self {
+        RepoError::Database(err)
+    }
+}
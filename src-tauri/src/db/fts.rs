@@ -0,0 +1,246 @@
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnection, SqlitePool};
+
+/// Tokenizer used for the full-text indexes, chosen with the
+/// `searchTokenizer` setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FtsTokenizer {
+    /// Word search with Unicode case folding and accents removed.
+    #[default]
+    Unicode61,
+    /// Substring search on three-character sequences; also works for text
+    /// without spaces between words (CJK).
+    Trigram,
+}
+
+impl FtsTokenizer {
+    fn options(self) -> &'static str {
+        match self {
+            FtsTokenizer::Unicode61 => "unicode61 remove_diacritics 2",
+            FtsTokenizer::Trigram => "trigram case_sensitive 0",
+        }
+    }
+    /// Reads the tokenizer back from the `CREATE VIRTUAL TABLE` statement.
+    /// Tables created before tokenizers were configurable report `None`.
+    fn from_table_sql(sql: &str) -> Option<Self> {
+        if sql.contains(FtsTokenizer::Trigram.options()) {
+            Some(FtsTokenizer::Trigram)
+        } else if sql.contains(FtsTokenizer::Unicode61.options()) {
+            Some(FtsTokenizer::Unicode61)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::str::FromStr for FtsTokenizer {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "" | "unicode61" => Ok(FtsTokenizer::Unicode61),
+            "trigram" => Ok(FtsTokenizer::Trigram),
+            other => Err(format!("Unknown search tokenizer: {}", other)),
+        }
+    }
+}
+
+/// `ё` is not a diacritic variant of `е` for unicode61, so both the index and
+/// the query fold it explicitly.
+pub fn fold_search_text(text: &str) -> String {
+    text.replace('ё', "е").replace('Ё', "Е")
+}
+fn fold_sql(column: &str) -> String {
+    format!("replace(replace({}, 'ё', 'е'), 'Ё', 'Е')", column)
+}
+
+pub async fn current_fts_tokenizer(
+    conn: &mut SqliteConnection,
+) -> Result<Option<FtsTokenizer>, sqlx::Error> {
+    let sql: Option<(String,)> =
+        sqlx::query_as("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'notes_fts'")
+            .fetch_optional(&mut *conn)
+            .await?;
+    Ok(sql.and_then(|(sql,)| FtsTokenizer::from_table_sql(&sql)))
+}
+
+/// An FTS5 index over an external content table. `columns` are folded with
+/// [`fold_search_text`]; a `stems` column is appended when the content table
+/// has one.
+struct FtsIndex {
+    table: &'static str,
+    content: &'static str,
+    rowid: &'static str,
+    columns: &'static [&'static str],
+}
+
+const FTS_INDEXES: &[FtsIndex] = &[
+    FtsIndex {
+        table: "notes_fts",
+        content: "notes_text",
+        rowid: "note_id",
+        columns: &["title", "plain_text"],
+    },
+    FtsIndex {
+        table: "ocr_fts",
+        content: "ocr_text",
+        rowid: "file_id",
+        columns: &["text"],
+    },
+    FtsIndex {
+        table: "attachment_fts",
+        content: "attachment_text",
+        rowid: "attachment_id",
+        columns: &["text"],
+    },
+];
+
+/// Drops and recreates the FTS tables and their sync triggers with
+/// `tokenizer`, then reindexes every content row.
+pub async fn rebuild_fts_tables(
+    conn: &mut SqliteConnection,
+    tokenizer: FtsTokenizer,
+) -> Result<(), sqlx::Error> {
+    for index in FTS_INDEXES {
+        for suffix in ["ai", "ad", "au"] {
+            sqlx::query(&format!(
+                "DROP TRIGGER IF EXISTS {}_{}",
+                index.content, suffix
+            ))
+            .execute(&mut *conn)
+            .await?;
+        }
+        sqlx::query(&format!("DROP TABLE IF EXISTS {}", index.table))
+            .execute(&mut *conn)
+            .await?;
+    }
+    for index in FTS_INDEXES {
+        create_fts_index(&mut *conn, index, tokenizer).await?;
+    }
+    Ok(())
+}
+
+async fn create_fts_index(
+    conn: &mut SqliteConnection,
+    index: &FtsIndex,
+    tokenizer: FtsTokenizer,
+) -> Result<(), sqlx::Error> {
+    // Content tables and their stem columns appear in later migrations;
+    // earlier migrations rebuild the index without them.
+    let (content,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(index.content)
+            .fetch_one(&mut *conn)
+            .await?;
+    if content == 0 {
+        return Ok(());
+    }
+    let (stems,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = 'stems'")
+            .bind(index.content)
+            .fetch_one(&mut *conn)
+            .await?;
+    let stems = stems > 0;
+    let mut columns = index.columns.join(", ");
+    if stems {
+        columns.push_str(", stems");
+    }
+    let values = |row: &str| {
+        let mut values: Vec<String> = index
+            .columns
+            .iter()
+            .map(|column| fold_sql(&format!("{}{}", row, column)))
+            .collect();
+        if stems {
+            values.push(format!("{}stems", row));
+        }
+        values.join(", ")
+    };
+    let (table, content, rowid) = (index.table, index.content, index.rowid);
+
+    sqlx::query(&format!(
+        "CREATE VIRTUAL TABLE {table}
+         USING fts5({columns}, content='{content}', content_rowid='{rowid}', tokenize='{}')",
+        tokenizer.options()
+    ))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(&format!(
+        "CREATE TRIGGER {content}_ai AFTER INSERT ON {content} BEGIN
+            INSERT INTO {table}(rowid, {columns}) VALUES (new.{rowid}, {new});
+         END;",
+        new = values("new.")
+    ))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(&format!(
+        "CREATE TRIGGER {content}_ad AFTER DELETE ON {content} BEGIN
+            INSERT INTO {table}({table}, rowid, {columns}) VALUES ('delete', old.{rowid}, {old});
+         END;",
+        old = values("old.")
+    ))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(&format!(
+        "CREATE TRIGGER {content}_au AFTER UPDATE ON {content} BEGIN
+            INSERT INTO {table}({table}, rowid, {columns}) VALUES ('delete', old.{rowid}, {old});
+            INSERT INTO {table}(rowid, {columns}) VALUES (new.{rowid}, {new});
+         END;",
+        old = values("old."),
+        new = values("new.")
+    ))
+    .execute(&mut *conn)
+    .await?;
+    // 'rebuild' would index the raw content rows; insert folded text instead.
+    sqlx::query(&format!(
+        "INSERT INTO {table}(rowid, {columns}) SELECT {rowid}, {} FROM {content}",
+        values("")
+    ))
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Merges the segments of every full-text index, stems included, so
+/// searches read fewer b-trees. Indexes not created yet are skipped.
+pub async fn optimize_fts_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    for index in FTS_INDEXES {
+        let (exists,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+                .bind(index.table)
+                .fetch_one(pool)
+                .await?;
+        if exists == 0 {
+            continue;
+        }
+        sqlx::query(&format!(
+            "INSERT INTO {table}({table}) VALUES('optimize')",
+            table = index.table
+        ))
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Rebuilds the search index when it was built with a different tokenizer.
+/// Returns whether a rebuild happened.
+pub async fn ensure_fts_tokenizer(
+    pool: &SqlitePool,
+    tokenizer: FtsTokenizer,
+) -> Result<bool, String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    if current_fts_tokenizer(&mut tx)
+        .await
+        .map_err(|e| e.to_string())?
+        == Some(tokenizer)
+    {
+        return Ok(false);
+    }
+    rebuild_fts_tables(&mut tx, tokenizer)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(true)
+}
@@ -1,25 +1,121 @@
+use crate::db::fts::{
+    current_fts_tokenizer, ensure_fts_tokenizer, rebuild_fts_tables, FtsTokenizer,
+};
+use crate::db::models::NotebookLayout;
 use crate::db::utils::strip_html;
-use sqlx::sqlite::SqlitePool;
+use sqlx::sqlite::{SqliteConnection, SqlitePool};
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-const SCHEMA_VERSION: i64 = 5;
+struct Migration {
+    version: i64,
+    name: &'static str,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "base_schema",
+    },
+    Migration {
+        version: 4,
+        name: "legacy_columns",
+    },
+    Migration {
+        version: 5,
+        name: "note_history",
+    },
+    Migration {
+        version: 6,
+        name: "note_revisions",
+    },
+    Migration {
+        version: 7,
+        name: "flatten_notebook_stacks",
+    },
+    Migration {
+        version: 8,
+        name: "note_file_scheme",
+    },
+    Migration {
+        version: 9,
+        name: "notes_text_backfill",
+    },
+    Migration {
+        version: 10,
+        name: "saved_searches",
+    },
+    Migration {
+        version: 11,
+        name: "fts_unicode_tokenizer",
+    },
+    Migration {
+        version: 12,
+        name: "search_stems",
+    },
+    Migration {
+        version: 13,
+        name: "notes_text_html_extraction",
+    },
+    Migration {
+        version: 14,
+        name: "attachment_text",
+    },
+    Migration {
+        version: 15,
+        name: "notebook_trash",
+    },
+    Migration {
+        version: 16,
+        name: "note_templates",
+    },
+];
+
+const SCHEMA_VERSION: i64 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Library settings that one-time migrations depend on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DbOptions {
+    pub layout: NotebookLayout,
+    pub tokenizer: FtsTokenizer,
+}
 
-async fn migrate_note_file_scheme(pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+async fn apply_migration(
+    conn: &mut SqliteConnection,
+    version: i64,
+    options: DbOptions,
+) -> Result<(), sqlx::Error> {
+    match version {
+        1 => create_base_schema(conn).await,
+        4 => add_legacy_columns(conn).await,
+        5 => create_history_table(conn).await,
+        6 => create_revisions_table(conn).await,
+        7 => flatten_notebook_stacks(conn).await,
+        8 => migrate_note_file_scheme(conn).await,
+        9 => backfill_notes_text(conn).await,
+        10 => create_saved_searches_table(conn).await,
+        11 => rebuild_fts_tables(conn, options.tokenizer).await,
+        12 => add_search_stems(conn).await,
+        13 => reextract_notes_text(conn).await,
+        14 => create_attachment_text_table(conn).await,
+        15 => create_notebook_trash(conn).await,
+        16 => create_note_templates_table(conn).await,
+        _ => Ok(()),
+    }
+}
+
+async fn migrate_note_file_scheme(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
     let rows: Vec<(i64, String)> =
         sqlx::query_as("SELECT id, content FROM notes WHERE content LIKE '%notes-file://files/%'")
-            .fetch_all(pool)
+            .fetch_all(&mut *conn)
             .await?;
-    if rows.is_empty() {
-        return Ok(false);
-    }
     for (id, content) in rows {
         let updated = content.replace("notes-file://files/", "files/");
         sqlx::query("UPDATE notes SET content = ? WHERE id = ?")
             .bind(&updated)
             .bind(id)
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
         let plain = strip_html(&updated);
         sqlx::query(
@@ -29,22 +125,26 @@ async fn migrate_note_file_scheme(pool: &SqlitePool) -> Result<bool, sqlx::Error
         )
         .bind(plain)
         .bind(id)
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
     }
-    Ok(true)
+    Ok(())
 }
 
-async fn table_exists(pool: &SqlitePool, name: &str) -> Result<bool, sqlx::Error> {
+async fn table_exists(conn: &mut SqliteConnection, name: &str) -> Result<bool, sqlx::Error> {
     let row: Option<(String,)> =
         sqlx::query_as("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
             .bind(name)
-            .fetch_optional(pool)
+            .fetch_optional(&mut *conn)
             .await?;
     Ok(row.is_some())
 }
 
-async fn column_exists(pool: &SqlitePool, table: &str, column: &str) -> Result<bool, sqlx::Error> {
+async fn column_exists(
+    conn: &mut SqliteConnection,
+    table: &str,
+    column: &str,
+) -> Result<bool, sqlx::Error> {
     let table = table.replace('\'', "''");
     let query = format!(
         "SELECT name FROM pragma_table_info('{}') WHERE name = ?",
@@ -52,39 +152,84 @@ async fn column_exists(pool: &SqlitePool, table: &str, column: &str) -> Result<b
     );
     let row: Option<(String,)> = sqlx::query_as(&query)
         .bind(column)
-        .fetch_optional(pool)
+        .fetch_optional(&mut *conn)
         .await?;
     Ok(row.is_some())
 }
 
-async fn ensure_schema_version(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+async fn ensure_schema_version(conn: &mut SqliteConnection) -> Result<i64, sqlx::Error> {
     sqlx::query("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)")
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
     let existing: Option<(i64,)> = sqlx::query_as("SELECT version FROM schema_version LIMIT 1")
-        .fetch_optional(pool)
+        .fetch_optional(&mut *conn)
         .await?;
     if let Some((version,)) = existing {
         return Ok(version);
     }
-    let has_notes = table_exists(pool, "notes").await?;
+    let has_notes = table_exists(&mut *conn, "notes").await?;
     let initial = if has_notes { 1 } else { 0 };
     sqlx::query("INSERT INTO schema_version (version) VALUES (?)")
         .bind(initial)
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
     Ok(initial)
 }
 
-async fn set_schema_version(pool: &SqlitePool, version: i64) -> Result<(), sqlx::Error> {
+async fn record_migration(
+    conn: &mut SqliteConnection,
+    migration: &Migration,
+) -> Result<(), sqlx::Error> {
     sqlx::query("UPDATE schema_version SET version = ?")
-        .bind(version)
-        .execute(pool)
+        .bind(migration.version)
+        .execute(&mut *conn)
         .await?;
+    sqlx::query(
+        "INSERT INTO schema_migrations (version, name, applied_at)
+         VALUES (?, ?, ?)
+         ON CONFLICT(version) DO UPDATE SET name = excluded.name, applied_at = excluded.applied_at",
+    )
+    .bind(migration.version)
+    .bind(migration.name)
+    .bind(chrono::Utc::now().timestamp())
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn backup_before_migration(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    from_version: i64,
+) -> Result<(), String> {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let backup_dir = data_dir.join("backups").join(format!(
+        "pre-migration-v{}-v{}-{}",
+        from_version, SCHEMA_VERSION, timestamp
+    ));
+    fs::create_dir_all(&backup_dir).map_err(|e| e.to_string())?;
+    let target = backup_dir.join("notes.db");
+    let target = target
+        .to_str()
+        .ok_or_else(|| "Path is not valid UTF-8".to_string())?;
+    sqlx::query("VACUUM INTO ?")
+        .bind(target)
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
     Ok(())
 }
 
-async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+async fn create_base_schema(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS notebooks (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -97,7 +242,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             FOREIGN KEY(parent_id) REFERENCES notebooks(id) ON DELETE CASCADE
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -119,7 +264,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             FOREIGN KEY(notebook_id) REFERENCES notebooks(id) ON DELETE SET NULL
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -130,14 +275,14 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             FOREIGN KEY(note_id) REFERENCES notes(id) ON DELETE CASCADE
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
         "CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts
          USING fts5(title, plain_text, content='notes_text', content_rowid='note_id')",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -148,7 +293,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             last_error TEXT
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -160,7 +305,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             FOREIGN KEY(file_id) REFERENCES ocr_files(id) ON DELETE CASCADE
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -173,14 +318,14 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             FOREIGN KEY(file_id) REFERENCES ocr_files(id) ON DELETE CASCADE
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
         "CREATE VIRTUAL TABLE IF NOT EXISTS ocr_fts
          USING fts5(text, content='ocr_text', content_rowid='file_id')",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -188,7 +333,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             INSERT INTO ocr_fts(rowid, text) VALUES (new.file_id, new.text);
          END;",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -196,7 +341,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             INSERT INTO ocr_fts(ocr_fts, rowid, text) VALUES ('delete', old.file_id, old.text);
          END;",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -205,7 +350,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             INSERT INTO ocr_fts(rowid, text) VALUES (new.file_id, new.text);
          END;",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -213,7 +358,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             INSERT INTO notes_fts(rowid, title, plain_text) VALUES (new.note_id, new.title, new.plain_text);
          END;",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -221,7 +366,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             INSERT INTO notes_fts(notes_fts, rowid, title, plain_text) VALUES ('delete', old.note_id, old.title, old.plain_text);
          END;",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -230,20 +375,20 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             INSERT INTO notes_fts(rowid, title, plain_text) VALUES (new.note_id, new.title, new.plain_text);
          END;",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_notes_notebook_id ON notes(notebook_id)")
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_notes_updated_at ON notes(updated_at)")
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
-    if column_exists(pool, "notes", "deleted_at").await? {
+    if column_exists(&mut *conn, "notes", "deleted_at").await? {
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_notes_deleted_at ON notes(deleted_at)")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
 
@@ -258,11 +403,11 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             FOREIGN KEY(parent_id) REFERENCES tags(id) ON DELETE CASCADE
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_tags_parent_name ON tags(parent_id, name)")
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
     sqlx::query(
@@ -274,7 +419,7 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
@@ -296,19 +441,17 @@ async fn create_schema_v3(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             FOREIGN KEY(note_id) REFERENCES notes(id) ON DELETE CASCADE
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_attachments_note_id ON attachments(note_id)")
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
-    create_history_table(pool).await?;
-
     Ok(())
 }
 
-async fn create_history_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+async fn create_history_table(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS note_history (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -321,144 +464,312 @@ async fn create_history_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
             stack_name TEXT
         )",
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_note_history_opened_at ON note_history(opened_at)")
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_note_history_note_id ON note_history(note_id)")
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
     Ok(())
 }
 
-async fn migrate_to_v4(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    create_schema_v3(pool).await?;
+async fn create_revisions_table(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS note_revisions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            content_size INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY(note_id) REFERENCES notes(id) ON DELETE CASCADE
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_note_revisions_note_id ON note_revisions(note_id, created_at)",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS note_revision_files (
+            revision_id INTEGER NOT NULL,
+            file_id INTEGER NOT NULL,
+            PRIMARY KEY(revision_id, file_id),
+            FOREIGN KEY(revision_id) REFERENCES note_revisions(id) ON DELETE CASCADE,
+            FOREIGN KEY(file_id) REFERENCES ocr_files(id) ON DELETE CASCADE
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_note_revision_files_file_id ON note_revision_files(file_id)",
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn create_saved_searches_table(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS saved_searches (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            query TEXT NOT NULL,
+            notebook_id INTEGER,
+            sort TEXT NOT NULL DEFAULT 'relevance',
+            sort_order INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Stemmed copies of note and OCR text, indexed as an extra FTS column.
+/// `stems_lang` is NULL while a row still needs stemming.
+async fn add_search_stems(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS search_meta (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    for table in ["notes_text", "ocr_text"] {
+        if !column_exists(&mut *conn, table, "stems").await? {
+            sqlx::query(&format!(
+                "ALTER TABLE {} ADD COLUMN stems TEXT NOT NULL DEFAULT ''",
+                table
+            ))
+            .execute(&mut *conn)
+            .await?;
+        }
+        if !column_exists(&mut *conn, table, "stems_lang").await? {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN stems_lang TEXT", table))
+                .execute(&mut *conn)
+                .await?;
+        }
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS idx_{}_stems_lang ON {}(stems_lang)",
+            table, table
+        ))
+        .execute(&mut *conn)
+        .await?;
+    }
+    // Text rewritten without new stems (imports, OCR updates) is marked stale.
+    sqlx::query(
+        "CREATE TRIGGER IF NOT EXISTS notes_text_stems_stale
+         AFTER UPDATE OF title, plain_text ON notes_text WHEN new.stems IS old.stems BEGIN
+            UPDATE notes_text SET stems_lang = NULL WHERE note_id = new.note_id;
+         END;",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE TRIGGER IF NOT EXISTS ocr_text_stems_stale
+         AFTER UPDATE OF text ON ocr_text WHEN new.stems IS old.stems BEGIN
+            UPDATE ocr_text SET stems_lang = NULL WHERE file_id = new.file_id;
+         END;",
+    )
+    .execute(&mut *conn)
+    .await?;
+    let tokenizer = current_fts_tokenizer(&mut *conn).await?.unwrap_or_default();
+    rebuild_fts_tables(conn, tokenizer).await
+}
+
+/// Text extracted from attached documents. A row with `error` set records a
+/// failed extraction so the file is not retried until it changes.
+async fn create_attachment_text_table(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS attachment_text (
+            attachment_id INTEGER PRIMARY KEY,
+            text TEXT NOT NULL DEFAULT '',
+            error TEXT,
+            updated_at INTEGER NOT NULL,
+            stems TEXT NOT NULL DEFAULT '',
+            stems_lang TEXT,
+            FOREIGN KEY(attachment_id) REFERENCES attachments(id) ON DELETE CASCADE
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_attachment_text_stems_lang ON attachment_text(stems_lang)",
+    )
+    .execute(&mut *conn)
+    .await?;
+    // A removed or replaced file drops its text; a replacement is extracted again.
+    sqlx::query(
+        "CREATE TRIGGER IF NOT EXISTS attachments_clear_text_ad
+         AFTER DELETE ON attachments BEGIN
+            DELETE FROM attachment_text WHERE attachment_id = old.id;
+         END;",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE TRIGGER IF NOT EXISTS attachments_clear_text_au
+         AFTER UPDATE OF local_path ON attachments WHEN new.local_path IS NOT old.local_path BEGIN
+            DELETE FROM attachment_text WHERE attachment_id = new.id;
+         END;",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE TRIGGER IF NOT EXISTS attachment_text_stems_stale
+         AFTER UPDATE OF text ON attachment_text WHEN new.stems IS old.stems BEGIN
+            UPDATE attachment_text SET stems_lang = NULL WHERE attachment_id = new.attachment_id;
+         END;",
+    )
+    .execute(&mut *conn)
+    .await?;
+    let tokenizer = current_fts_tokenizer(&mut *conn).await?.unwrap_or_default();
+    rebuild_fts_tables(conn, tokenizer).await
+}
+
+/// Deleted notebooks and stacks move here so the rest of the schema never sees
+/// them. `trash_root_id` is the notebook or stack the user deleted; its child
+/// notebooks share it, as do the notes trashed along with it.
+async fn create_notebook_trash(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS trashed_notebooks (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            parent_id INTEGER,
+            notebook_type TEXT NOT NULL,
+            sort_order INTEGER NOT NULL DEFAULT 0,
+            external_id TEXT,
+            deleted_at INTEGER NOT NULL,
+            trash_root_id INTEGER NOT NULL
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_trashed_notebooks_root ON trashed_notebooks(trash_root_id)",
+    )
+    .execute(&mut *conn)
+    .await?;
+    if !column_exists(&mut *conn, "notes", "deleted_with_notebook_id").await? {
+        sqlx::query("ALTER TABLE notes ADD COLUMN deleted_with_notebook_id INTEGER")
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Templates keep `notebook_id` without a foreign key, like saved searches,
+/// and fall back to no notebook once it is gone.
+async fn create_note_templates_table(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS note_templates (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            title TEXT NOT NULL DEFAULT '',
+            content TEXT NOT NULL DEFAULT '',
+            notebook_id INTEGER,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS note_template_tags (
+            template_id INTEGER NOT NULL,
+            tag_id INTEGER NOT NULL,
+            PRIMARY KEY(template_id, tag_id),
+            FOREIGN KEY(template_id) REFERENCES note_templates(id) ON DELETE CASCADE,
+            FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
 
-    if !column_exists(pool, "notes", "sync_status").await? {
+async fn add_legacy_columns(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    if !column_exists(&mut *conn, "notes", "sync_status").await? {
         sqlx::query("ALTER TABLE notes ADD COLUMN sync_status INTEGER DEFAULT 0")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "notes", "remote_id").await? {
+    if !column_exists(&mut *conn, "notes", "remote_id").await? {
         sqlx::query("ALTER TABLE notes ADD COLUMN remote_id TEXT")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "notes", "external_id").await? {
+    if !column_exists(&mut *conn, "notes", "external_id").await? {
         sqlx::query("ALTER TABLE notes ADD COLUMN external_id TEXT")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "notes", "meta").await? {
+    if !column_exists(&mut *conn, "notes", "meta").await? {
         sqlx::query("ALTER TABLE notes ADD COLUMN meta TEXT")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "notes", "content_hash").await? {
+    if !column_exists(&mut *conn, "notes", "content_hash").await? {
         sqlx::query("ALTER TABLE notes ADD COLUMN content_hash TEXT")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "notes", "content_size").await? {
+    if !column_exists(&mut *conn, "notes", "content_size").await? {
         sqlx::query("ALTER TABLE notes ADD COLUMN content_size INTEGER")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "notes", "deleted_at").await? {
+    if !column_exists(&mut *conn, "notes", "deleted_at").await? {
         sqlx::query("ALTER TABLE notes ADD COLUMN deleted_at INTEGER")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "notes", "deleted_from_notebook_id").await? {
+    if !column_exists(&mut *conn, "notes", "deleted_from_notebook_id").await? {
         sqlx::query("ALTER TABLE notes ADD COLUMN deleted_from_notebook_id INTEGER")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "notebooks", "external_id").await? {
+    if !column_exists(&mut *conn, "notebooks", "external_id").await? {
         sqlx::query("ALTER TABLE notebooks ADD COLUMN external_id TEXT")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "tags", "external_id").await? {
+    if !column_exists(&mut *conn, "tags", "external_id").await? {
         sqlx::query("ALTER TABLE tags ADD COLUMN external_id TEXT")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "ocr_files", "attempts_left").await? {
+    if !column_exists(&mut *conn, "ocr_files", "attempts_left").await? {
         sqlx::query("ALTER TABLE ocr_files ADD COLUMN attempts_left INTEGER NOT NULL DEFAULT 3")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
-    if !column_exists(pool, "ocr_files", "last_error").await? {
+    if !column_exists(&mut *conn, "ocr_files", "last_error").await? {
         sqlx::query("ALTER TABLE ocr_files ADD COLUMN last_error TEXT")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
     }
 
-    Ok(())
-}
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_notes_deleted_at ON notes(deleted_at)")
+        .execute(&mut *conn)
+        .await?;
 
-async fn migrate_to_v5(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    migrate_to_v4(pool).await?;
-    create_history_table(pool).await?;
     Ok(())
 }
 
-pub async fn init_db(data_dir: &Path) -> Result<SqlitePool, String> {
-    if !data_dir.exists() {
-        fs::create_dir_all(data_dir).map_err(|e| e.to_string())?;
-    }
-    let db_path = data_dir.join("notes.db");
-    if !db_path.exists() {
-        fs::File::create(&db_path).map_err(|e| e.to_string())?;
-    }
-
-    let db_url = format!(
-        "sqlite:{}",
-        db_path
-            .to_str()
-            .ok_or_else(|| "Path is not valid UTF-8".to_string())?
-    );
-    let pool = SqlitePool::connect(&db_url)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("PRAGMA foreign_keys = ON")
-        .execute(&pool)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("PRAGMA journal_mode = WAL")
-        .execute(&pool)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("PRAGMA synchronous = NORMAL")
-        .execute(&pool)
-        .await
-        .map_err(|e| e.to_string())?;
-    let version = ensure_schema_version(&pool)
-        .await
-        .map_err(|e| e.to_string())?;
-    if version == 0 {
-        create_schema_v3(&pool).await.map_err(|e| e.to_string())?;
-        set_schema_version(&pool, SCHEMA_VERSION)
-            .await
-            .map_err(|e| e.to_string())?;
-    } else if version < SCHEMA_VERSION {
-        migrate_to_v5(&pool).await.map_err(|e| e.to_string())?;
-        set_schema_version(&pool, SCHEMA_VERSION)
-            .await
-            .map_err(|e| e.to_string())?;
-    }
-    create_schema_v3(&pool).await.map_err(|e| e.to_string())?;
-    let _ = migrate_note_file_scheme(&pool)
-        .await
-        .map_err(|e| e.to_string())?;
-
+async fn flatten_notebook_stacks(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
     let mut structure_changed = false;
     let rows: Vec<(i64, Option<i64>)> = sqlx::query_as("SELECT id, parent_id FROM notebooks")
-        .fetch_all(&pool)
-        .await
-        .map_err(|e| e.to_string())?;
+        .fetch_all(&mut *conn)
+        .await?;
     let mut parent_map = HashMap::new();
     for (id, parent_id) in &rows {
         parent_map.insert(*id, *parent_id);
@@ -475,18 +786,16 @@ pub async fn init_db(data_dir: &Path) -> Result<SqlitePool, String> {
         if parent_id.is_none() {
             sqlx::query("UPDATE notebooks SET notebook_type = 'stack' WHERE id = ?")
                 .bind(id)
-                .execute(&pool)
-                .await
-                .map_err(|e| e.to_string())?;
+                .execute(&mut *conn)
+                .await?;
         } else {
             sqlx::query(
                 "UPDATE notebooks SET notebook_type = 'notebook', parent_id = ? WHERE id = ?",
             )
             .bind(root_id)
             .bind(id)
-            .execute(&pool)
-            .await
-            .map_err(|e| e.to_string())?;
+            .execute(&mut *conn)
+            .await?;
             if parent_id != Some(root_id) {
                 structure_changed = true;
             }
@@ -496,65 +805,187 @@ pub async fn init_db(data_dir: &Path) -> Result<SqlitePool, String> {
     if structure_changed {
         let parents: Vec<(Option<i64>,)> =
             sqlx::query_as("SELECT DISTINCT parent_id FROM notebooks")
-                .fetch_all(&pool)
-                .await
-                .map_err(|e| e.to_string())?;
+                .fetch_all(&mut *conn)
+                .await?;
         for (parent_id,) in parents {
             let ids: Vec<(i64,)> = if let Some(pid) = parent_id {
                 sqlx::query_as("SELECT id FROM notebooks WHERE parent_id = ? ORDER BY name ASC, created_at ASC")
                     .bind(pid)
-                    .fetch_all(&pool)
-                    .await
-                    .map_err(|e| e.to_string())?
+                    .fetch_all(&mut *conn)
+                    .await?
             } else {
                 sqlx::query_as("SELECT id FROM notebooks WHERE parent_id IS NULL ORDER BY name ASC, created_at ASC")
-                    .fetch_all(&pool)
-                    .await
-                    .map_err(|e| e.to_string())?
+                    .fetch_all(&mut *conn)
+                    .await?
             };
             for (index, (id,)) in ids.iter().enumerate() {
                 sqlx::query("UPDATE notebooks SET sort_order = ? WHERE id = ?")
                     .bind(index as i64)
                     .bind(id)
-                    .execute(&pool)
-                    .await
-                    .map_err(|e| e.to_string())?;
+                    .execute(&mut *conn)
+                    .await?;
             }
         }
     }
+    Ok(())
+}
 
-    let text_count: Option<(i64,)> = sqlx::query_as("SELECT COUNT(*) FROM notes_text")
-        .fetch_optional(&pool)
+/// Flattens the notebook tree again when a notebook sits below another
+/// notebook, which only the `Nested` layout allows.
+async fn flatten_nested_notebooks(pool: &SqlitePool) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let nested: Option<(i64,)> = sqlx::query_as(
+        "SELECT n.id FROM notebooks n
+         JOIN notebooks p ON p.id = n.parent_id
+         WHERE p.parent_id IS NOT NULL
+         LIMIT 1",
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    if nested.is_none() {
+        return Ok(());
+    }
+    flatten_notebook_stacks(&mut tx)
         .await
         .map_err(|e| e.to_string())?;
-    let notes_count: Option<(i64,)> = sqlx::query_as("SELECT COUNT(*) FROM notes")
-        .fetch_optional(&pool)
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+async fn backfill_notes_text(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let notes: Vec<(i64, String, String)> = sqlx::query_as(
+        "SELECT n.id, n.title, n.content
+         FROM notes n
+         LEFT JOIN notes_text t ON t.note_id = n.id
+         WHERE t.note_id IS NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for (id, title, content) in notes {
+        let plain = strip_html(&content);
+        sqlx::query(
+            "INSERT INTO notes_text (note_id, title, plain_text)
+             VALUES (?, ?, ?)
+             ON CONFLICT(note_id) DO UPDATE SET title = excluded.title, plain_text = excluded.plain_text",
+        )
+        .bind(id)
+        .bind(title)
+        .bind(plain)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Re-runs `strip_html` over every note so text indexed by the old
+/// tag-dropping extractor picks up decoded entities and block spacing. The
+/// stems trigger marks the rows for re-stemming.
+async fn reextract_notes_text(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    let notes: Vec<(i64, String)> = sqlx::query_as("SELECT id, content FROM notes")
+        .fetch_all(&mut *conn)
+        .await?;
+    for (id, content) in notes {
+        sqlx::query("UPDATE notes_text SET plain_text = ? WHERE note_id = ?")
+            .bind(strip_html(&content))
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn run_migrations(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    options: DbOptions,
+) -> Result<(), String> {
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    let version = ensure_schema_version(&mut conn)
         .await
         .map_err(|e| e.to_string())?;
-    let needs_text = match (text_count, notes_count) {
-        (Some((text,)), Some((notes,))) => text < notes,
-        _ => true,
-    };
-    if needs_text {
-        let notes: Vec<(i64, String, String)> =
-            sqlx::query_as("SELECT id, title, content FROM notes")
-                .fetch_all(&pool)
-                .await
-                .map_err(|e| e.to_string())?;
-        for (id, title, content) in notes {
-            let plain = strip_html(&content);
-            sqlx::query(
-                "INSERT INTO notes_text (note_id, title, plain_text)
-                 VALUES (?, ?, ?)
-                 ON CONFLICT(note_id) DO UPDATE SET title = excluded.title, plain_text = excluded.plain_text",
-            )
-            .bind(id)
-            .bind(title)
-            .bind(plain)
-            .execute(&pool)
+    drop(conn);
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "Database schema version {} is newer than this build supports ({}). Please update Notes Classic.",
+            version, SCHEMA_VERSION
+        ));
+    }
+    // Libraries from before numbered migrations report version 1-3 and may
+    // lack tables the legacy steps touch. The base schema only adds what is
+    // missing, so it runs again ahead of them.
+    let pending: Vec<&Migration> = MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version || (migration.version == 1 && version < 4))
+        .collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+    if version > 0 {
+        backup_before_migration(pool, data_dir, version).await?;
+    }
+    for migration in pending {
+        let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+        apply_migration(&mut tx, migration.version, options)
+            .await
+            .map_err(|e| {
+                format!(
+                    "migration {} ({}): {}",
+                    migration.version, migration.name, e
+                )
+            })?;
+        record_migration(&mut tx, migration)
             .await
             .map_err(|e| e.to_string())?;
-        }
+        tx.commit().await.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+pub async fn init_db(data_dir: &Path) -> Result<SqlitePool, String> {
+    init_db_with_options(data_dir, DbOptions::default()).await
+}
+
+/// Opens the library, running migrations for the given settings. The search
+/// index is built with `tokenizer` and rebuilt if the setting changed since
+/// the last launch; with the `Stacks` layout, notebooks left nested by the
+/// `Nested` layout are moved back under their stacks.
+pub async fn init_db_with_options(
+    data_dir: &Path,
+    options: DbOptions,
+) -> Result<SqlitePool, String> {
+    if !data_dir.exists() {
+        fs::create_dir_all(data_dir).map_err(|e| e.to_string())?;
+    }
+    let db_path = data_dir.join("notes.db");
+    if !db_path.exists() {
+        fs::File::create(&db_path).map_err(|e| e.to_string())?;
+    }
+
+    let db_url = format!(
+        "sqlite:{}",
+        db_path
+            .to_str()
+            .ok_or_else(|| "Path is not valid UTF-8".to_string())?
+    );
+    let pool = SqlitePool::connect(&db_url)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("PRAGMA foreign_keys = ON")
+        .execute(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("PRAGMA journal_mode = WAL")
+        .execute(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("PRAGMA synchronous = NORMAL")
+        .execute(&pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    run_migrations(&pool, data_dir, options).await?;
+    ensure_fts_tokenizer(&pool, options.tokenizer).await?;
+    if options.layout == NotebookLayout::Stacks {
+        flatten_nested_notebooks(&pool).await?;
     }
 
     Ok(pool)
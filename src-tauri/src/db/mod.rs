@@ -1,11 +1,25 @@
+mod error;
+mod fts;
 mod migrations;
 mod models;
 mod repo;
+mod search;
+mod stem;
 mod utils;
 
-pub use migrations::init_db;
+pub use error::RepoError;
+pub use fts::{ensure_fts_tokenizer, fold_search_text, optimize_fts_tables, FtsTokenizer};
+pub use migrations::{init_db, init_db_with_options, DbOptions};
 pub use models::{
-    Attachment, Note, NoteCounts, NoteHistoryItem, NoteLinkItem, NoteListItem, Notebook,
-    OcrFileItem, OcrStats, Tag,
+    Attachment, AttachmentTextStats, BulkNoteResult, DedupeReport, Note, NoteCounts,
+    NoteHistoryItem, NoteLinkItem, NoteListItem, NoteRevision, NoteRevisionItem, NoteTemplate,
+    Notebook, NotebookLayout, OcrFileItem, OcrStats, RevisionPolicy, SavedSearch, SavedSearchCount,
+    SearchResult, Tag, TrashedNotebook,
 };
 pub use repo::SqliteRepository;
+pub use search::{
+    SearchBind, SearchError, SearchFilter, SearchPlan, SearchQuery, SearchQueryError,
+    SearchSnippet, SearchSort, SearchTerm, SearchTodo,
+};
+pub use stem::{refresh_stems, set_stem_language, StemLanguage, WordStemmer};
+pub use utils::{replace_attachment_ids, strip_html};
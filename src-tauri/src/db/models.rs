@@ -1,3 +1,4 @@
+use crate::db::search::{SearchSnippet, SearchSort};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
@@ -13,6 +14,20 @@ pub struct Notebook {
     pub external_id: Option<String>,
 }
 
+/// A deleted notebook or stack as listed in the trash. Notebooks deleted
+/// with a stack are counted here rather than listed.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedNotebook {
+    pub id: i64,
+    pub name: String,
+    pub parent_id: Option<i64>,
+    pub notebook_type: String,
+    pub deleted_at: i64,
+    pub notebook_count: i64,
+    pub note_count: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Note {
@@ -50,6 +65,26 @@ pub struct NoteListItem {
     pub updated_at: i64,
     pub notebook_id: Option<i64>,
     pub ocr_match: bool,
+    pub attachment_match: bool,
+}
+
+/// A search hit. `content` repeats the body snippet text so the result can
+/// stand in for a [`NoteListItem`].
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub id: i64,
+    pub title: String,
+    pub content: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub notebook_id: Option<i64>,
+    pub ocr_match: bool,
+    pub attachment_match: bool,
+    pub score: f64,
+    pub snippet: Option<SearchSnippet>,
+    pub ocr_snippet: Option<SearchSnippet>,
+    pub attachment_snippet: Option<SearchSnippet>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
@@ -88,6 +123,27 @@ pub struct OcrStats {
     pub pending: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentTextStats {
+    pub total: i64,
+    pub done: i64,
+    pub failed: i64,
+    pub pending: i64,
+}
+
+/// Outcome of a bulk note operation for one note. `error` explains why the
+/// note was skipped; the other notes in the batch are still applied.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkNoteResult {
+    pub note_id: i64,
+    pub ok: bool,
+    pub error: Option<String>,
+    /// The new note, for duplicates.
+    pub new_note_id: Option<i64>,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct NoteCountItem {
@@ -95,12 +151,58 @@ pub struct NoteCountItem {
     pub count: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TagCountItem {
+    pub tag_id: i64,
+    pub count: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct NoteCounts {
     pub trashed: i64,
     pub total: i64,
     pub per_notebook: Vec<NoteCountItem>,
+    /// Counts include notes tagged with descendant tags.
+    pub per_tag: Vec<TagCountItem>,
+    pub saved_searches: Vec<SavedSearchCount>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedSearch {
+    pub id: i64,
+    pub name: String,
+    pub query: String,
+    pub notebook_id: Option<i64>,
+    pub sort: SearchSort,
+    pub sort_order: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedSearchCount {
+    pub saved_search_id: i64,
+    pub count: i64,
+}
+
+/// Starting point for new notes. `title` and `content` may contain
+/// `{{date}}`, `{{time}}`, `{{notebook}}` and `{{clipboard}}` placeholders.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteTemplate {
+    pub id: i64,
+    pub name: String,
+    pub title: String,
+    pub content: String,
+    pub notebook_id: Option<i64>,
+    #[sqlx(skip)]
+    pub tag_ids: Vec<i64>,
+    pub created_at: i64,
+    pub updated_at: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
@@ -115,3 +217,64 @@ pub struct NoteHistoryItem {
     pub stack_id: Option<i64>,
     pub stack_name: Option<String>,
 }
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteRevisionItem {
+    pub id: i64,
+    pub note_id: i64,
+    pub title: String,
+    pub created_at: i64,
+    pub content_size: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteRevision {
+    pub id: i64,
+    pub note_id: i64,
+    pub title: String,
+    pub content: String,
+    pub created_at: i64,
+    pub content_size: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RevisionPolicy {
+    pub enabled: bool,
+    pub min_interval_seconds: i64,
+    pub max_per_note: i64,
+    pub max_age_days: i64,
+}
+
+impl Default for RevisionPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_interval_seconds: 600,
+            max_per_note: 50,
+            max_age_days: 0,
+        }
+    }
+}
+
+/// How notebooks may nest. `Stacks` is the classic layout: stacks at the top,
+/// notebooks one level below. `Nested` lets notebooks hold notebooks to any
+/// depth, as in folder-based apps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NotebookLayout {
+    #[default]
+    Stacks,
+    Nested,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DedupeReport {
+    pub files_scanned: i64,
+    pub files_missing: i64,
+    pub files_unreadable: i64,
+    pub files_removed: i64,
+    pub bytes_reclaimed: i64,
+    pub notes_rewritten: i64,
+}
@@ -0,0 +1,74 @@
+use super::SqliteRepository;
+use crate::db::models::{Attachment, AttachmentTextStats};
+use crate::db::stem::{current_stem_language, WordStemmer};
+use crate::db::utils::DOCUMENT_TEXT_FILTER;
+
+impl SqliteRepository {
+    pub async fn get_attachment_text_pending(
+        &self,
+        limit: i64,
+    ) -> Result<Vec<Attachment>, sqlx::Error> {
+        let query = format!(
+            "SELECT a.id,
+                    a.note_id,
+                    COALESCE(a.filename, '') AS filename,
+                    COALESCE(a.mime, '') AS mime,
+                    COALESCE(a.size, 0) AS size,
+                    a.local_path
+             FROM attachments a
+             LEFT JOIN attachment_text t ON t.attachment_id = a.id
+             WHERE t.attachment_id IS NULL
+               AND COALESCE(a.local_path, '') <> ''
+               AND {filter}
+             ORDER BY a.id ASC
+             LIMIT ?",
+            filter = DOCUMENT_TEXT_FILTER
+        );
+        sqlx::query_as::<_, Attachment>(&query)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+    }
+    pub async fn upsert_attachment_text(
+        &self,
+        attachment_id: i64,
+        text: &str,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let language = current_stem_language(&mut tx).await?;
+        sqlx::query(
+            "INSERT INTO attachment_text (attachment_id, text, error, updated_at, stems, stems_lang)
+             VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT(attachment_id) DO UPDATE SET text = excluded.text, error = excluded.error, updated_at = excluded.updated_at,
+               stems = excluded.stems, stems_lang = excluded.stems_lang",
+        )
+        .bind(attachment_id)
+        .bind(text)
+        .bind(error)
+        .bind(now)
+        .bind(WordStemmer::new(language).stem_text(text))
+        .bind(language.code())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    pub async fn get_attachment_text_stats(&self) -> Result<AttachmentTextStats, sqlx::Error> {
+        let query = format!(
+            "SELECT COUNT(*) AS total,
+                    COALESCE(SUM(t.attachment_id IS NOT NULL AND t.error IS NULL), 0) AS done,
+                    COALESCE(SUM(t.error IS NOT NULL), 0) AS failed,
+                    COALESCE(SUM(t.attachment_id IS NULL), 0) AS pending
+             FROM attachments a
+             LEFT JOIN attachment_text t ON t.attachment_id = a.id
+             WHERE COALESCE(a.local_path, '') <> ''
+               AND {filter}",
+            filter = DOCUMENT_TEXT_FILTER
+        );
+        sqlx::query_as::<_, AttachmentTextStats>(&query)
+            .fetch_one(&self.pool)
+            .await
+    }
+}
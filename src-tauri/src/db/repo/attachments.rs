@@ -82,7 +82,15 @@ impl SqliteRepository {
             .bind(id)
             .execute(&mut *tx)
             .await?;
+        let path = match row {
+            Some((path,)) if !path.is_empty() => Some(path),
+            _ => None,
+        };
+        let path = match path {
+            Some(path) if self.blob_in_use_tx(&mut tx, &path).await? => None,
+            other => other,
+        };
         tx.commit().await?;
-        Ok(row.and_then(|(path,)| if path.is_empty() { None } else { Some(path) }))
+        Ok(path)
     }
 }
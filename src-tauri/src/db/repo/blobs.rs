@@ -0,0 +1,245 @@
+use super::SqliteRepository;
+use crate::db::models::DedupeReport;
+use crate::db::utils::replace_note_file_paths;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Hashes a blob; empty files hash like any other, so only files that
+/// cannot be read come back as errors.
+fn hash_blob(path: &Path) -> std::io::Result<(String, u64)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher)?;
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+fn canonical_blob_path(hash: &str, current: &str) -> String {
+    let ext = Path::new(current)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.trim().to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .unwrap_or_else(|| "bin".to_string());
+    format!("files/{}/{}.{}", &hash[0..2], hash, ext)
+}
+
+impl SqliteRepository {
+    pub(crate) async fn blob_in_use_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        local_path: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let row: Option<(i64,)> = sqlx::query_as(
+            "SELECT 1
+             WHERE EXISTS (SELECT 1 FROM attachments WHERE local_path = ?)
+                OR EXISTS (SELECT 1 FROM ocr_files WHERE 'files/' || file_path = ?)",
+        )
+        .bind(local_path)
+        .bind(local_path)
+        .fetch_optional(&mut **tx)
+        .await?;
+        Ok(row.is_some())
+    }
+    pub(crate) async fn unused_blobs_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        paths: Vec<String>,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let mut unused = Vec::new();
+        for path in paths {
+            if !self.blob_in_use_tx(tx, &path).await? {
+                unused.push(path);
+            }
+        }
+        Ok(unused)
+    }
+    pub async fn deduplicate_note_files(
+        &self,
+        data_dir: &Path,
+    ) -> Result<DedupeReport, sqlx::Error> {
+        let mut report = DedupeReport::default();
+        let file_rows: Vec<(i64, String)> =
+            sqlx::query_as("SELECT id, file_path FROM ocr_files ORDER BY id")
+                .fetch_all(&self.pool)
+                .await?;
+        let attachment_rows: Vec<(i64, String)> = sqlx::query_as(
+            "SELECT id, local_path FROM attachments
+             WHERE local_path IS NOT NULL AND local_path <> ''
+             ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut targets: HashMap<String, String> = HashMap::new();
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        let mut created: Vec<String> = Vec::new();
+        let current_paths = file_rows
+            .iter()
+            .map(|(_, path)| format!("files/{}", path))
+            .chain(attachment_rows.iter().map(|(_, path)| path.clone()));
+        for current in current_paths {
+            if targets.contains_key(&current) {
+                continue;
+            }
+            report.files_scanned += 1;
+            let full_path = data_dir.join(&current);
+            let (hash, size) = match hash_blob(&full_path) {
+                Ok(hashed) => hashed,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    report.files_missing += 1;
+                    continue;
+                }
+                Err(_) => {
+                    report.files_unreadable += 1;
+                    continue;
+                }
+            };
+            let canonical = canonical_blob_path(&hash, &current);
+            if canonical != current {
+                let canonical_full = data_dir.join(&canonical);
+                if !canonical_full.exists() {
+                    if let Some(parent) = canonical_full.parent() {
+                        let _ = fs::create_dir_all(parent);
+                    }
+                    if fs::copy(&full_path, &canonical_full).is_err() {
+                        continue;
+                    }
+                    sizes.insert(canonical.clone(), size);
+                    created.push(canonical.clone());
+                }
+            }
+            sizes.insert(current.clone(), size);
+            targets.insert(current, canonical);
+        }
+
+        // The copies are only kept once the rows point at them.
+        let (moved, notes_rewritten) = match self
+            .point_rows_at_blobs_tx(file_rows, attachment_rows, &targets)
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                for rel in &created {
+                    let _ = fs::remove_file(data_dir.join(rel));
+                }
+                return Err(e);
+            }
+        };
+        report.notes_rewritten = notes_rewritten;
+        for rel in &created {
+            report.bytes_reclaimed -= sizes.get(rel).copied().unwrap_or(0) as i64;
+        }
+
+        for rel in moved {
+            let full_path = data_dir.join(&rel);
+            if full_path.exists() && fs::remove_file(&full_path).is_ok() {
+                report.files_removed += 1;
+                report.bytes_reclaimed += sizes.get(&rel).copied().unwrap_or(0) as i64;
+            }
+            if let Some(parent) = full_path.parent() {
+                let _ = fs::remove_dir(parent);
+            }
+        }
+        Ok(report)
+    }
+    /// Moves file and attachment rows, and the HTML of notes and revisions,
+    /// onto the canonical blobs in `targets`. Returns the paths no row uses
+    /// any more and how many notes were rewritten.
+    async fn point_rows_at_blobs_tx(
+        &self,
+        file_rows: Vec<(i64, String)>,
+        attachment_rows: Vec<(i64, String)>,
+        targets: &HashMap<String, String>,
+    ) -> Result<(HashSet<String>, i64), sqlx::Error> {
+        let mut moved: HashSet<String> = HashSet::new();
+        let mut renamed: HashMap<String, String> = HashMap::new();
+        let mut tx = self.pool.begin().await?;
+        for (id, file_path) in file_rows {
+            let current = format!("files/{}", file_path);
+            let Some(canonical) = targets.get(&current) else {
+                continue;
+            };
+            if *canonical == current {
+                continue;
+            }
+            let canonical_rel = canonical.trim_start_matches("files/");
+            sqlx::query(
+                "INSERT INTO ocr_files (file_path) VALUES (?) ON CONFLICT(file_path) DO NOTHING",
+            )
+            .bind(canonical_rel)
+            .execute(&mut *tx)
+            .await?;
+            let (canonical_id,): (i64,) =
+                sqlx::query_as("SELECT id FROM ocr_files WHERE file_path = ?")
+                    .bind(canonical_rel)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            for table in ["note_files", "note_revision_files", "ocr_text"] {
+                sqlx::query(&format!(
+                    "UPDATE OR IGNORE {} SET file_id = ? WHERE file_id = ?",
+                    table
+                ))
+                .bind(canonical_id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            sqlx::query("DELETE FROM ocr_files WHERE id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            renamed.insert(file_path, canonical_rel.to_string());
+            moved.insert(current);
+        }
+        for (id, local_path) in attachment_rows {
+            let Some(canonical) = targets.get(&local_path) else {
+                continue;
+            };
+            if *canonical == local_path {
+                continue;
+            }
+            sqlx::query("UPDATE attachments SET local_path = ? WHERE id = ?")
+                .bind(canonical)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            if let Some(rel) = local_path.strip_prefix("files/") {
+                renamed.insert(
+                    rel.to_string(),
+                    canonical.trim_start_matches("files/").to_string(),
+                );
+            }
+            moved.insert(local_path);
+        }
+
+        let mut notes_rewritten = 0i64;
+        if !renamed.is_empty() {
+            for table in ["notes", "note_revisions"] {
+                let rows: Vec<(i64, String)> = sqlx::query_as(&format!(
+                    "SELECT id, content FROM {} WHERE content LIKE '%files%'",
+                    table
+                ))
+                .fetch_all(&mut *tx)
+                .await?;
+                for (id, content) in rows {
+                    let rewritten = replace_note_file_paths(&content, &renamed);
+                    if rewritten == content {
+                        continue;
+                    }
+                    sqlx::query(&format!("UPDATE {} SET content = ? WHERE id = ?", table))
+                        .bind(&rewritten)
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                    if table == "notes" {
+                        notes_rewritten += 1;
+                    }
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok((moved, notes_rewritten))
+    }
+}
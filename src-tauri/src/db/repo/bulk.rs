@@ -0,0 +1,234 @@
+use super::SqliteRepository;
+use crate::db::error::RepoError;
+use crate::db::models::BulkNoteResult;
+use std::path::Path;
+
+/// Where a note stands before a bulk operation touches it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NoteState {
+    Missing,
+    Active,
+    Trashed,
+}
+
+impl BulkNoteResult {
+    fn done(note_id: i64) -> Self {
+        BulkNoteResult {
+            note_id,
+            ok: true,
+            error: None,
+            new_note_id: None,
+        }
+    }
+    fn skipped(note_id: i64, state: NoteState) -> Self {
+        let error = match state {
+            NoteState::Missing => "Note not found",
+            NoteState::Active => "Note is not in the trash",
+            NoteState::Trashed => "Note is in the trash",
+        };
+        BulkNoteResult {
+            note_id,
+            ok: false,
+            error: Some(error.to_string()),
+            new_note_id: None,
+        }
+    }
+}
+
+/// Note ids in request order with repeats dropped, so each note gets one result.
+fn unique_ids(note_ids: &[i64]) -> Vec<i64> {
+    let mut seen = std::collections::HashSet::new();
+    note_ids
+        .iter()
+        .copied()
+        .filter(|id| seen.insert(*id))
+        .collect()
+}
+
+/// Bulk versions of the single-note operations. Each runs in one transaction;
+/// notes that are missing or in the wrong state are reported and skipped,
+/// while a database error rolls back the whole batch.
+impl SqliteRepository {
+    async fn note_state_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        note_id: i64,
+    ) -> Result<NoteState, sqlx::Error> {
+        let row: Option<(Option<i64>,)> =
+            sqlx::query_as("SELECT deleted_at FROM notes WHERE id = ?")
+                .bind(note_id)
+                .fetch_optional(&mut **tx)
+                .await?;
+        Ok(match row {
+            None => NoteState::Missing,
+            Some((None,)) => NoteState::Active,
+            Some((Some(_),)) => NoteState::Trashed,
+        })
+    }
+    pub async fn move_notes(
+        &self,
+        note_ids: &[i64],
+        notebook_id: Option<i64>,
+    ) -> Result<Vec<BulkNoteResult>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        if let Some(notebook_id) = notebook_id {
+            sqlx::query("SELECT id FROM notebooks WHERE id = ?")
+                .bind(notebook_id)
+                .fetch_one(&mut *tx)
+                .await?;
+        }
+        let mut results = Vec::new();
+        for id in unique_ids(note_ids) {
+            let state = self.note_state_tx(&mut tx, id).await?;
+            if state != NoteState::Active {
+                results.push(BulkNoteResult::skipped(id, state));
+                continue;
+            }
+            sqlx::query("UPDATE notes SET notebook_id = ? WHERE id = ?")
+                .bind(notebook_id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            results.push(BulkNoteResult::done(id));
+        }
+        tx.commit().await?;
+        Ok(results)
+    }
+    pub async fn trash_notes(&self, note_ids: &[i64]) -> Result<Vec<BulkNoteResult>, sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::new();
+        for id in unique_ids(note_ids) {
+            let state = self.note_state_tx(&mut tx, id).await?;
+            if state != NoteState::Active {
+                results.push(BulkNoteResult::skipped(id, state));
+                continue;
+            }
+            self.trash_note_tx(&mut tx, id, now).await?;
+            results.push(BulkNoteResult::done(id));
+        }
+        tx.commit().await?;
+        Ok(results)
+    }
+    pub async fn restore_notes(
+        &self,
+        note_ids: &[i64],
+    ) -> Result<Vec<BulkNoteResult>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::new();
+        for id in unique_ids(note_ids) {
+            let state = self.note_state_tx(&mut tx, id).await?;
+            if state != NoteState::Trashed {
+                results.push(BulkNoteResult::skipped(id, state));
+                continue;
+            }
+            self.restore_note_tx(&mut tx, id).await?;
+            results.push(BulkNoteResult::done(id));
+        }
+        tx.commit().await?;
+        Ok(results)
+    }
+    /// Permanently deletes notes, trashed or not, and the files only they used.
+    pub async fn delete_notes(
+        &self,
+        note_ids: &[i64],
+        data_dir: &Path,
+    ) -> Result<Vec<BulkNoteResult>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::new();
+        let mut attachment_paths = Vec::new();
+        for id in unique_ids(note_ids) {
+            let state = self.note_state_tx(&mut tx, id).await?;
+            if state == NoteState::Missing {
+                results.push(BulkNoteResult::skipped(id, state));
+                continue;
+            }
+            attachment_paths.extend(self.delete_note_tx(&mut tx, id).await?);
+            results.push(BulkNoteResult::done(id));
+        }
+        self.finish_note_deletes(tx, attachment_paths, data_dir)
+            .await?;
+        Ok(results)
+    }
+    /// Tags every note in `note_ids`; notes that already have the tag count as done.
+    pub async fn tag_notes(
+        &self,
+        tag_id: i64,
+        note_ids: &[i64],
+    ) -> Result<Vec<BulkNoteResult>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SELECT id FROM tags WHERE id = ?")
+            .bind(tag_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let mut results = Vec::new();
+        for id in unique_ids(note_ids) {
+            let state = self.note_state_tx(&mut tx, id).await?;
+            if state == NoteState::Missing {
+                results.push(BulkNoteResult::skipped(id, state));
+                continue;
+            }
+            sqlx::query("INSERT OR IGNORE INTO note_tags (note_id, tag_id) VALUES (?, ?)")
+                .bind(id)
+                .bind(tag_id)
+                .execute(&mut *tx)
+                .await?;
+            results.push(BulkNoteResult::done(id));
+        }
+        tx.commit().await?;
+        Ok(results)
+    }
+    pub async fn untag_notes(
+        &self,
+        tag_id: i64,
+        note_ids: &[i64],
+    ) -> Result<Vec<BulkNoteResult>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::new();
+        for id in unique_ids(note_ids) {
+            let state = self.note_state_tx(&mut tx, id).await?;
+            if state == NoteState::Missing {
+                results.push(BulkNoteResult::skipped(id, state));
+                continue;
+            }
+            sqlx::query("DELETE FROM note_tags WHERE note_id = ? AND tag_id = ?")
+                .bind(id)
+                .bind(tag_id)
+                .execute(&mut *tx)
+                .await?;
+            results.push(BulkNoteResult::done(id));
+        }
+        tx.commit().await?;
+        Ok(results)
+    }
+    /// Copies each note into the same notebook like `duplicate_note`, with
+    /// its own copies of the files and attachments it references.
+    pub async fn duplicate_notes<F>(
+        &self,
+        note_ids: &[i64],
+        data_dir: &Path,
+        mut store_file: F,
+    ) -> Result<Vec<BulkNoteResult>, RepoError>
+    where
+        F: FnMut(&str, &str, &[u8]) -> Result<String, String> + Send,
+    {
+        let mut tx = self.pool.begin().await?;
+        let mut results = Vec::new();
+        for id in unique_ids(note_ids) {
+            let state = self.note_state_tx(&mut tx, id).await?;
+            if state != NoteState::Active {
+                results.push(BulkNoteResult::skipped(id, state));
+                continue;
+            }
+            let new_id = self
+                .duplicate_note_tx(&mut tx, id, None, data_dir, &mut store_file)
+                .await?;
+            results.push(BulkNoteResult {
+                new_note_id: Some(new_id),
+                ..BulkNoteResult::done(id)
+            });
+        }
+        tx.commit().await?;
+        Ok(results)
+    }
+}
@@ -4,9 +4,15 @@ pub struct SqliteRepository {
     pub pool: SqlitePool,
 }
 
+mod attachment_text;
 mod attachments;
+mod blobs;
+mod bulk;
 mod history;
 mod notebooks;
 mod notes;
 mod ocr;
+mod revisions;
+mod saved_searches;
 mod tags;
+mod templates;
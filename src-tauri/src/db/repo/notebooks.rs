@@ -1,5 +1,19 @@
 use super::SqliteRepository;
-use crate::db::models::Notebook;
+use crate::db::error::RepoError;
+use crate::db::models::{Notebook, NotebookLayout, TrashedNotebook};
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(sqlx::FromRow)]
+struct TrashedNotebookRow {
+    id: i64,
+    name: String,
+    created_at: i64,
+    parent_id: Option<i64>,
+    notebook_type: String,
+    sort_order: i64,
+    external_id: Option<String>,
+}
 
 impl SqliteRepository {
     pub async fn get_notebooks(&self) -> Result<Vec<Notebook>, sqlx::Error> {
@@ -9,10 +23,13 @@ impl SqliteRepository {
             .fetch_all(&self.pool)
             .await
     }
+    /// Creates a stack at the top level or a notebook under `parent_id`, which
+    /// must be a stack unless the layout is `Nested`.
     pub async fn create_notebook(
         &self,
         name: &str,
         parent_id: Option<i64>,
+        layout: NotebookLayout,
     ) -> Result<i64, sqlx::Error> {
         let now = chrono::Utc::now().timestamp();
         let notebook_type = if parent_id.is_some() {
@@ -27,7 +44,7 @@ impl SqliteRepository {
                     .fetch_optional(&self.pool)
                     .await?;
             if let Some((ptype,)) = parent_type {
-                if ptype != "stack" {
+                if ptype != "stack" && layout != NotebookLayout::Nested {
                     return Err(sqlx::Error::RowNotFound);
                 }
             }
@@ -61,19 +78,260 @@ impl SqliteRepository {
             .await?;
         Ok(())
     }
+    /// Moves a notebook or stack to the trash together with its child
+    /// notebooks and their notes.
     pub async fn delete_notebook(&self, id: i64) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let subtree: Vec<(i64,)> = sqlx::query_as(
+            "WITH RECURSIVE subtree(id) AS (
+               SELECT id FROM notebooks WHERE id = ?
+               UNION
+               SELECT n.id FROM notebooks n
+               JOIN subtree s ON n.parent_id = s.id
+             )
+             SELECT id FROM subtree",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+        for (notebook_id,) in &subtree {
+            sqlx::query(
+                "INSERT INTO trashed_notebooks
+                   (id, name, created_at, parent_id, notebook_type, sort_order, external_id, deleted_at, trash_root_id)
+                 SELECT id, name, created_at, parent_id, notebook_type, sort_order, external_id, ?, ?
+                 FROM notebooks WHERE id = ?",
+            )
+            .bind(now)
+            .bind(id)
+            .bind(notebook_id)
+            .execute(&mut *tx)
+            .await?;
+            // Notes already in the trash stay there, but would lose their
+            // notebook to ON DELETE SET NULL; restoring one should still
+            // follow the notebook back.
+            sqlx::query(
+                "UPDATE notes
+                 SET deleted_from_notebook_id = notebook_id,
+                     notebook_id = NULL
+                 WHERE notebook_id = ? AND deleted_at IS NOT NULL",
+            )
+            .bind(notebook_id)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "UPDATE notes
+                 SET deleted_at = ?,
+                     deleted_from_notebook_id = notebook_id,
+                     deleted_with_notebook_id = ?,
+                     notebook_id = NULL
+                 WHERE notebook_id = ? AND deleted_at IS NULL",
+            )
+            .bind(now)
+            .bind(id)
+            .bind(notebook_id)
+            .execute(&mut *tx)
+            .await?;
+        }
         sqlx::query("DELETE FROM notebooks WHERE id = ?")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    pub async fn get_trashed_notebooks(&self) -> Result<Vec<TrashedNotebook>, sqlx::Error> {
+        sqlx::query_as::<_, TrashedNotebook>(
+            "SELECT t.id, t.name, t.parent_id, t.notebook_type, t.deleted_at,
+                    (SELECT COUNT(*) FROM trashed_notebooks c
+                     WHERE c.trash_root_id = t.id AND c.id != t.id) AS notebook_count,
+                    (SELECT COUNT(*) FROM notes n
+                     WHERE n.deleted_with_notebook_id = t.id) AS note_count
+             FROM trashed_notebooks t
+             WHERE t.id = t.trash_root_id
+             ORDER BY t.deleted_at DESC, t.id DESC",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+    /// Restores a trashed notebook or stack with its notebooks and notes in
+    /// their old places. A notebook whose stack is also in the trash brings
+    /// the stack back too.
+    pub async fn restore_notebook(&self, id: i64) -> Result<(), RepoError> {
+        let mut tx = self.pool.begin().await?;
+        self.restore_trashed_notebook_tx(&mut tx, id).await?;
+        tx.commit().await?;
         Ok(())
     }
+    pub(crate) async fn restore_trashed_notebook_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: i64,
+    ) -> Result<(), RepoError> {
+        // Trash entries to restore, outermost stack last.
+        let mut chain = vec![id];
+        let mut root = id;
+        loop {
+            let row: Option<(Option<i64>,)> = sqlx::query_as(
+                "SELECT parent_id FROM trashed_notebooks WHERE id = ? AND trash_root_id = ?",
+            )
+            .bind(root)
+            .bind(root)
+            .fetch_optional(&mut **tx)
+            .await?;
+            let Some((parent_id,)) = row else {
+                return Err(sqlx::Error::RowNotFound.into());
+            };
+            let Some(parent_id) = parent_id else {
+                break;
+            };
+            let live: Option<(i64,)> = sqlx::query_as("SELECT id FROM notebooks WHERE id = ?")
+                .bind(parent_id)
+                .fetch_optional(&mut **tx)
+                .await?;
+            if live.is_some() {
+                break;
+            }
+            let trashed: Option<(i64,)> =
+                sqlx::query_as("SELECT trash_root_id FROM trashed_notebooks WHERE id = ?")
+                    .bind(parent_id)
+                    .fetch_optional(&mut **tx)
+                    .await?;
+            let Some((parent_root,)) = trashed else {
+                return Err(RepoError::Invalid(
+                    "The stack of this notebook no longer exists".to_string(),
+                ));
+            };
+            chain.push(parent_root);
+            root = parent_root;
+        }
+
+        // Ids freed by the delete are normally still free; one taken since
+        // (e.g. by an import) gets a new id.
+        let mut new_ids: HashMap<i64, i64> = HashMap::new();
+        for root in chain.into_iter().rev() {
+            let rows: Vec<TrashedNotebookRow> = sqlx::query_as(
+                "WITH RECURSIVE tree(id, depth) AS (
+                   SELECT id, 0 FROM trashed_notebooks WHERE id = ?
+                   UNION ALL
+                   SELECT t.id, tree.depth + 1 FROM trashed_notebooks t
+                   JOIN tree ON t.parent_id = tree.id
+                   WHERE t.trash_root_id = ?
+                 )
+                 SELECT t.id, t.name, t.created_at, t.parent_id, t.notebook_type, t.sort_order, t.external_id
+                 FROM trashed_notebooks t
+                 JOIN tree ON tree.id = t.id
+                 ORDER BY tree.depth, t.id",
+            )
+            .bind(root)
+            .bind(root)
+            .fetch_all(&mut **tx)
+            .await?;
+            for row in rows {
+                let parent_id = row
+                    .parent_id
+                    .map(|pid| new_ids.get(&pid).copied().unwrap_or(pid));
+                if row.id == root {
+                    sqlx::query(
+                        "UPDATE notebooks SET sort_order = sort_order + 1
+                         WHERE parent_id IS ? AND sort_order >= ?",
+                    )
+                    .bind(parent_id)
+                    .bind(row.sort_order)
+                    .execute(&mut **tx)
+                    .await?;
+                }
+                let taken: Option<(i64,)> = sqlx::query_as("SELECT id FROM notebooks WHERE id = ?")
+                    .bind(row.id)
+                    .fetch_optional(&mut **tx)
+                    .await?;
+                let new_id = sqlx::query(
+                    "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order, external_id)
+                     VALUES (?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(taken.is_none().then_some(row.id))
+                .bind(&row.name)
+                .bind(row.created_at)
+                .bind(parent_id)
+                .bind(&row.notebook_type)
+                .bind(row.sort_order)
+                .bind(&row.external_id)
+                .execute(&mut **tx)
+                .await?
+                .last_insert_rowid();
+                if new_id != row.id {
+                    new_ids.insert(row.id, new_id);
+                    sqlx::query(
+                        "UPDATE notes SET deleted_from_notebook_id = ?
+                         WHERE deleted_from_notebook_id = ? AND deleted_at IS NOT NULL",
+                    )
+                    .bind(new_id)
+                    .bind(row.id)
+                    .execute(&mut **tx)
+                    .await?;
+                    sqlx::query("UPDATE trashed_notebooks SET parent_id = ? WHERE parent_id = ?")
+                        .bind(new_id)
+                        .bind(row.id)
+                        .execute(&mut **tx)
+                        .await?;
+                }
+            }
+            sqlx::query("DELETE FROM trashed_notebooks WHERE trash_root_id = ?")
+                .bind(root)
+                .execute(&mut **tx)
+                .await?;
+            let note_ids: Vec<(i64,)> =
+                sqlx::query_as("SELECT id FROM notes WHERE deleted_with_notebook_id = ?")
+                    .bind(root)
+                    .fetch_all(&mut **tx)
+                    .await?;
+            for (note_id,) in note_ids {
+                self.restore_note_tx(tx, note_id).await?;
+            }
+        }
+        Ok(())
+    }
+    /// Permanently deletes a trashed notebook or stack and the notes trashed
+    /// with it.
+    pub async fn delete_trashed_notebook(
+        &self,
+        id: i64,
+        data_dir: &Path,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let (_, attachment_paths) = self.delete_trashed_notebook_tx(&mut tx, id).await?;
+        self.finish_note_deletes(tx, attachment_paths, data_dir)
+            .await
+    }
+    /// Deletes a trash group: its notebook rows and the notes trashed with
+    /// it. Returns the number of notes and the attachment paths they held.
+    pub(crate) async fn delete_trashed_notebook_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: i64,
+    ) -> Result<(i64, Vec<String>), sqlx::Error> {
+        let note_ids: Vec<(i64,)> =
+            sqlx::query_as("SELECT id FROM notes WHERE deleted_with_notebook_id = ?")
+                .bind(id)
+                .fetch_all(&mut **tx)
+                .await?;
+        let mut attachment_paths = Vec::new();
+        for (note_id,) in &note_ids {
+            attachment_paths.extend(self.delete_note_tx(tx, *note_id).await?);
+        }
+        sqlx::query("DELETE FROM trashed_notebooks WHERE trash_root_id = ?")
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+        Ok((note_ids.len() as i64, attachment_paths))
+    }
     pub async fn move_notebook(
         &self,
         notebook_id: i64,
         target_parent_id: Option<i64>,
         target_index: usize,
-    ) -> Result<(), sqlx::Error> {
+        layout: NotebookLayout,
+    ) -> Result<(), RepoError> {
         let mut tx = self.pool.begin().await?;
 
         let current: Option<(Option<i64>, i64, String)> = sqlx::query_as(
@@ -100,10 +358,30 @@ impl SqliteRepository {
                     .fetch_optional(&mut *tx)
                     .await?;
             if let Some((ptype,)) = parent_type {
-                if ptype != "stack" {
+                if ptype != "stack" && layout != NotebookLayout::Nested {
                     return Ok(());
                 }
             }
+            if layout == NotebookLayout::Nested {
+                let (inside,): (i64,) = sqlx::query_as(
+                    "WITH RECURSIVE subtree(id) AS (
+                       SELECT id FROM notebooks WHERE id = ?
+                       UNION
+                       SELECT n.id FROM notebooks n
+                       JOIN subtree s ON n.parent_id = s.id
+                     )
+                     SELECT COUNT(*) FROM subtree WHERE id = ?",
+                )
+                .bind(notebook_id)
+                .bind(target_parent_id)
+                .fetch_one(&mut *tx)
+                .await?;
+                if inside > 0 {
+                    return Err(RepoError::Invalid(
+                        "Cannot move a notebook into itself or one of its notebooks".to_string(),
+                    ));
+                }
+            }
         }
 
         let source_ids: Vec<i64> = if let Some(pid) = current_parent_id {
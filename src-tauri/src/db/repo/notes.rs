@@ -1,12 +1,45 @@
 use super::SqliteRepository;
-use crate::db::models::{Note, NoteCountItem, NoteCounts, NoteLinkItem, NoteListItem};
-use crate::db::utils::{extract_attachment_ids, extract_note_files, strip_html};
-use std::collections::HashSet;
+use crate::db::error::RepoError;
+use crate::db::models::{
+    Note, NoteCountItem, NoteCounts, NoteLinkItem, NoteListItem, RevisionPolicy, SearchResult,
+    TagCountItem,
+};
+use crate::db::search::{SearchBind, SearchError, SearchQuery, SearchSnippet, SearchSort};
+use crate::db::stem::{current_stem_language, WordStemmer};
+use crate::db::utils::{
+    extract_attachment_ids, extract_note_files, replace_attachment_ids, strip_html,
+};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 
+/// bm25 column weights: a title hit outranks a body hit, which outranks the
+/// same hit in OCR or attachment text. A stemmed-only match ranks below an
+/// exact one.
+const SEARCH_TITLE_WEIGHT: f64 = 10.0;
+const SEARCH_BODY_WEIGHT: f64 = 1.0;
+const SEARCH_STEM_WEIGHT: f64 = 0.5;
+const SEARCH_OCR_WEIGHT: f64 = 0.5;
+const SEARCH_ATTACHMENT_WEIGHT: f64 = 0.5;
+const TRASH_PURGE_BATCH: i64 = 100;
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    id: i64,
+    title: String,
+    created_at: i64,
+    updated_at: i64,
+    notebook_id: Option<i64>,
+    body_snippet: Option<String>,
+    ocr_snippet: Option<String>,
+    attachment_snippet: Option<String>,
+    score: f64,
+    ocr_match: bool,
+    attachment_match: bool,
+}
+
 impl SqliteRepository {
-    async fn upsert_note_text_tx(
+    pub(crate) async fn upsert_note_text_tx(
         &self,
         tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
         note_id: i64,
@@ -14,19 +47,24 @@ impl SqliteRepository {
         content: &str,
     ) -> Result<(), sqlx::Error> {
         let plain = strip_html(content);
+        let language = current_stem_language(tx).await?;
+        let stems = WordStemmer::new(language).stem_text(&format!("{} {}", title, plain));
         sqlx::query(
-            "INSERT INTO notes_text (note_id, title, plain_text)
-             VALUES (?, ?, ?)
-             ON CONFLICT(note_id) DO UPDATE SET title = excluded.title, plain_text = excluded.plain_text",
+            "INSERT INTO notes_text (note_id, title, plain_text, stems, stems_lang)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(note_id) DO UPDATE SET title = excluded.title, plain_text = excluded.plain_text,
+               stems = excluded.stems, stems_lang = excluded.stems_lang",
         )
         .bind(note_id)
         .bind(title)
         .bind(plain)
+        .bind(stems)
+        .bind(language.code())
         .execute(&mut **tx)
         .await?;
         Ok(())
     }
-    async fn sync_note_files_tx(
+    pub(crate) async fn sync_note_files_tx(
         &self,
         tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
         note_id: i64,
@@ -67,8 +105,8 @@ impl SqliteRepository {
         let orphan_files: Vec<(i64, String)> = sqlx::query_as(
             "SELECT f.id, f.file_path
              FROM ocr_files f
-             LEFT JOIN note_files nf ON nf.file_id = f.id
-             WHERE nf.file_id IS NULL",
+             WHERE NOT EXISTS (SELECT 1 FROM note_files nf WHERE nf.file_id = f.id)
+               AND NOT EXISTS (SELECT 1 FROM note_revision_files rf WHERE rf.file_id = f.id)",
         )
         .fetch_all(&mut **tx)
         .await?;
@@ -78,7 +116,13 @@ impl SqliteRepository {
                 .execute(&mut **tx)
                 .await?;
         }
-        Ok(orphan_files.into_iter().map(|(_, path)| path).collect())
+        let mut removed = Vec::new();
+        for (_, path) in orphan_files {
+            if !self.blob_in_use_tx(tx, &format!("files/{}", path)).await? {
+                removed.push(path);
+            }
+        }
+        Ok(removed)
     }
     async fn cleanup_note_attachments_tx(
         &self,
@@ -106,7 +150,7 @@ impl SqliteRepository {
                 }
             }
         }
-        Ok(removed)
+        self.unused_blobs_tx(tx, removed).await
     }
     pub(crate) async fn sync_note_files(
         &self,
@@ -126,11 +170,11 @@ impl SqliteRepository {
             sqlx::query_as::<_, NoteListItem>(
                 "WITH RECURSIVE descendant_notebooks(id) AS (
                     SELECT id FROM notebooks WHERE id = ?
-                    UNION ALL
+                    UNION
                     SELECT n.id FROM notebooks n
                     JOIN descendant_notebooks dn ON n.parent_id = dn.id
                 )
-                SELECT id, title, substr(content, 1, 4000) AS content, updated_at, notebook_id, 0 AS ocr_match FROM notes
+                SELECT id, title, substr(content, 1, 4000) AS content, updated_at, notebook_id, 0 AS ocr_match, 0 AS attachment_match FROM notes
                 WHERE deleted_at IS NULL
                   AND notebook_id IN (SELECT id FROM descendant_notebooks)
                 ORDER BY updated_at DESC, created_at DESC, id DESC",
@@ -140,7 +184,7 @@ impl SqliteRepository {
             .await
         } else {
             sqlx::query_as::<_, NoteListItem>(
-                "SELECT id, title, substr(content, 1, 4000) AS content, updated_at, notebook_id, 0 AS ocr_match
+                "SELECT id, title, substr(content, 1, 4000) AS content, updated_at, notebook_id, 0 AS ocr_match, 0 AS attachment_match
                  FROM notes
                  WHERE deleted_at IS NULL
                  ORDER BY updated_at DESC, created_at DESC, id DESC",
@@ -155,7 +199,7 @@ impl SqliteRepository {
             .fetch_optional(&self.pool)
             .await
     }
-    pub async fn get_note_counts(&self) -> Result<NoteCounts, sqlx::Error> {
+    pub async fn get_note_counts(&self) -> Result<NoteCounts, SearchError> {
         let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM notes WHERE deleted_at IS NULL")
             .fetch_one(&self.pool)
             .await?;
@@ -171,10 +215,29 @@ impl SqliteRepository {
         )
         .fetch_all(&self.pool)
         .await?;
+        let per_tag = sqlx::query_as::<_, TagCountItem>(
+            "WITH RECURSIVE tag_tree(root_id, id) AS (
+                SELECT id, id FROM tags
+                UNION
+                SELECT tt.root_id, t.id FROM tags t
+                JOIN tag_tree tt ON t.parent_id = tt.id
+            )
+            SELECT tt.root_id AS tag_id, COUNT(DISTINCT n.id) AS count
+            FROM tag_tree tt
+            JOIN note_tags nt ON nt.tag_id = tt.id
+            JOIN notes n ON n.id = nt.note_id
+            WHERE n.deleted_at IS NULL
+            GROUP BY tt.root_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let saved_searches = self.count_saved_searches().await?;
         Ok(NoteCounts {
             total: total.0,
             trashed: trashed.0,
             per_notebook,
+            per_tag,
+            saved_searches,
         })
     }
     pub async fn create_note(
@@ -185,22 +248,175 @@ impl SqliteRepository {
         data_dir: &Path,
     ) -> Result<i64, sqlx::Error> {
         let _ = data_dir;
-        let now = chrono::Utc::now().timestamp();
         let mut tx = self.pool.begin().await?;
+        let id = self
+            .create_note_tx(&mut tx, title, content, notebook_id)
+            .await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+    pub(crate) async fn create_note_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        title: &str,
+        content: &str,
+        notebook_id: Option<i64>,
+    ) -> Result<i64, sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
         let result = sqlx::query("INSERT INTO notes (title, content, created_at, updated_at, notebook_id) VALUES (?, ?, ?, ?, ?)")
             .bind(title)
             .bind(content)
             .bind(now)
             .bind(now)
             .bind(notebook_id)
-            .execute(&mut *tx)
+            .execute(&mut **tx)
             .await?;
         let id = result.last_insert_rowid();
-        self.upsert_note_text_tx(&mut tx, id, title, content)
+        self.upsert_note_text_tx(tx, id, title, content).await?;
+        let _ = self.sync_note_files_tx(tx, id, content).await?;
+        Ok(id)
+    }
+    /// Copies a note with its tags. Every `files/` image and attachment it
+    /// references is stored again through `store_file` (filename, mime and
+    /// bytes in, path under `files/` out) and the copy's HTML is pointed at the
+    /// new files and attachment rows, so editing or deleting either note
+    /// cannot remove files the other still uses. `title` replaces the
+    /// original title when given.
+    pub async fn duplicate_note<F>(
+        &self,
+        note_id: i64,
+        title: Option<&str>,
+        data_dir: &Path,
+        mut store_file: F,
+    ) -> Result<i64, RepoError>
+    where
+        F: FnMut(&str, &str, &[u8]) -> Result<String, String> + Send,
+    {
+        let mut tx = self.pool.begin().await?;
+        let new_id = self
+            .duplicate_note_tx(&mut tx, note_id, title, data_dir, &mut store_file)
             .await?;
-        let _ = self.sync_note_files_tx(&mut tx, id, content).await?;
         tx.commit().await?;
-        Ok(id)
+        Ok(new_id)
+    }
+    pub(crate) async fn duplicate_note_tx<F>(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        note_id: i64,
+        title: Option<&str>,
+        data_dir: &Path,
+        store_file: &mut F,
+    ) -> Result<i64, RepoError>
+    where
+        F: FnMut(&str, &str, &[u8]) -> Result<String, String> + Send,
+    {
+        let now = chrono::Utc::now().timestamp();
+        let (original_title, mut content, notebook_id, meta): (
+            String,
+            String,
+            Option<i64>,
+            Option<String>,
+        ) = sqlx::query_as("SELECT title, content, notebook_id, meta FROM notes WHERE id = ?")
+            .bind(note_id)
+            .fetch_one(&mut **tx)
+            .await?;
+        let title = title.unwrap_or(&original_title);
+
+        // Files that are missing on disk stay shared rather than failing the copy.
+        for rel in extract_note_files(&content) {
+            let Ok(bytes) = fs::read(data_dir.join("files").join(&rel)) else {
+                continue;
+            };
+            let filename = Path::new(&rel)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("file");
+            let mime = mime_guess::from_path(&rel).first_or_octet_stream();
+            let copy =
+                store_file(filename, mime.essence_str(), &bytes).map_err(RepoError::Storage)?;
+            content = content
+                .replace(&format!("files/{}", rel), &format!("files/{}", copy))
+                .replace(
+                    &format!("files%2F{}", urlencoding::encode(&rel)),
+                    &format!("files%2F{}", urlencoding::encode(&copy)),
+                );
+        }
+
+        let new_id = sqlx::query(
+            "INSERT INTO notes (title, content, created_at, updated_at, notebook_id, meta)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(title)
+        .bind(&content)
+        .bind(now)
+        .bind(now)
+        .bind(notebook_id)
+        .bind(meta)
+        .execute(&mut **tx)
+        .await?
+        .last_insert_rowid();
+
+        let mut attachment_ids = HashMap::new();
+        for id in extract_attachment_ids(&content) {
+            let row: Option<(String, String, String)> = sqlx::query_as(
+                "SELECT COALESCE(filename, ''), COALESCE(mime, ''), COALESCE(local_path, '')
+                 FROM attachments WHERE id = ?",
+            )
+            .bind(id)
+            .fetch_optional(&mut **tx)
+            .await?;
+            let Some((filename, mime, local_path)) = row else {
+                continue;
+            };
+            if local_path.is_empty() {
+                continue;
+            }
+            let Ok(bytes) = fs::read(data_dir.join(&local_path)) else {
+                continue;
+            };
+            let stored_name = if filename.is_empty() {
+                "attachment"
+            } else {
+                &filename
+            };
+            let copy = store_file(stored_name, &mime, &bytes).map_err(RepoError::Storage)?;
+            let new_attachment_id = sqlx::query(
+                "INSERT INTO attachments (note_id, hash, filename, mime, size, width, height, local_path, source_url, is_attachment, created_at, updated_at)
+                 SELECT ?, hash, filename, mime, ?, width, height, ?, source_url, is_attachment, ?, ?
+                 FROM attachments WHERE id = ?",
+            )
+            .bind(new_id)
+            .bind(bytes.len() as i64)
+            .bind(format!("files/{}", copy))
+            .bind(now)
+            .bind(now)
+            .bind(id)
+            .execute(&mut **tx)
+            .await?
+            .last_insert_rowid();
+            attachment_ids.insert(id, new_attachment_id);
+        }
+        if !attachment_ids.is_empty() {
+            content = replace_attachment_ids(&content, &attachment_ids);
+            sqlx::query("UPDATE notes SET content = ? WHERE id = ?")
+                .bind(&content)
+                .bind(new_id)
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        self.upsert_note_text_tx(tx, new_id, title, &content)
+            .await?;
+        self.sync_note_files_tx(tx, new_id, &content).await?;
+        sqlx::query(
+            "INSERT INTO note_tags (note_id, tag_id)
+             SELECT ?, tag_id FROM note_tags WHERE note_id = ?",
+        )
+        .bind(new_id)
+        .bind(note_id)
+        .execute(&mut **tx)
+        .await?;
+        Ok(new_id)
     }
     pub async fn search_notes_by_title(
         &self,
@@ -267,10 +483,13 @@ impl SqliteRepository {
         content: &str,
         notebook_id: Option<i64>,
         data_dir: &Path,
+        revisions: &RevisionPolicy,
     ) -> Result<(), sqlx::Error> {
-        let attachment_ids = extract_attachment_ids(content);
+        let mut attachment_ids = extract_attachment_ids(content);
         let now = chrono::Utc::now().timestamp();
         let mut tx = self.pool.begin().await?;
+        self.snapshot_note_revision_tx(&mut tx, id, title, content, revisions)
+            .await?;
         sqlx::query(
             "UPDATE notes SET title = ?, content = ?, updated_at = ?, notebook_id = ? WHERE id = ?",
         )
@@ -284,6 +503,7 @@ impl SqliteRepository {
         self.upsert_note_text_tx(&mut tx, id, title, content)
             .await?;
         let _ = self.sync_note_files_tx(&mut tx, id, content).await?;
+        attachment_ids.extend(self.revision_attachment_ids_tx(&mut tx, id).await?);
         let removed_attachments = self
             .cleanup_note_attachments_tx(&mut tx, id, &attachment_ids)
             .await?;
@@ -309,116 +529,245 @@ impl SqliteRepository {
         }
         Ok(())
     }
+    /// Re-extracts `notes_text` for every note, e.g. after the HTML-to-text
+    /// extraction changed. Returns the number of notes reindexed.
+    pub async fn reindex_notes_text(&self) -> Result<i64, sqlx::Error> {
+        let notes: Vec<(i64, String, String)> =
+            sqlx::query_as("SELECT id, title, content FROM notes ORDER BY id")
+                .fetch_all(&self.pool)
+                .await?;
+        let mut tx = self.pool.begin().await?;
+        for (id, title, content) in &notes {
+            self.upsert_note_text_tx(&mut tx, *id, title, content)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(notes.len() as i64)
+    }
     pub async fn search_notes(
         &self,
         query: &str,
         notebook_id: Option<i64>,
-    ) -> Result<Vec<NoteListItem>, sqlx::Error> {
-        if let Some(id) = notebook_id {
-            sqlx::query_as::<_, NoteListItem>(
-                "WITH RECURSIVE descendant_notebooks(id) AS (
-                    SELECT id FROM notebooks WHERE id = ?
-                    UNION ALL
-                    SELECT n.id FROM notebooks n
-                    JOIN descendant_notebooks dn ON n.parent_id = dn.id
-                ),
-                text_matches AS (
-                    SELECT n.id, n.title,
-                           snippet(notes_fts, 1, '', '', '...', 20) AS content,
-                           n.updated_at, n.notebook_id,
-                           0 AS ocr_match
-                    FROM notes_fts
-                    JOIN notes n ON n.id = notes_fts.rowid
-                    WHERE notes_fts MATCH ?
-                      AND n.deleted_at IS NULL
-                      AND n.notebook_id IN (SELECT id FROM descendant_notebooks)
-                ),
-                ocr_matches AS (
-                    SELECT n.id, n.title,
-                           '' AS content,
-                           n.updated_at, n.notebook_id,
-                           1 AS ocr_match
-                    FROM ocr_fts
-                    JOIN note_files nf ON nf.file_id = ocr_fts.rowid
-                    JOIN notes n ON n.id = nf.note_id
-                    WHERE ocr_fts MATCH ?
-                      AND n.deleted_at IS NULL
-                      AND n.notebook_id IN (SELECT id FROM descendant_notebooks)
-                )
-                SELECT id, title,
-                       MAX(content) AS content,
-                       updated_at, notebook_id,
-                       MAX(ocr_match) AS ocr_match
-                FROM (
-                    SELECT * FROM text_matches
-                    UNION ALL
-                    SELECT * FROM ocr_matches
-                )
-                GROUP BY id, title, updated_at, notebook_id
-                ORDER BY updated_at DESC, id DESC",
-            )
-            .bind(id)
-            .bind(query)
-            .bind(query)
-            .fetch_all(&self.pool)
-            .await
+        sort: SearchSort,
+    ) -> Result<Vec<SearchResult>, SearchError> {
+        let parsed = SearchQuery::parse(query)?;
+        if parsed.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.pool.acquire().await?;
+        let plan = parsed.plan(current_stem_language(&mut conn).await?);
+        drop(conn);
+        let scope = if notebook_id.is_some() {
+            "WITH RECURSIVE descendant_notebooks(id) AS (
+                SELECT id FROM notebooks WHERE id = ?
+                UNION
+                SELECT n.id FROM notebooks n
+                JOIN descendant_notebooks dn ON n.parent_id = dn.id
+            )"
         } else {
-            sqlx::query_as::<_, NoteListItem>(
-                "WITH text_matches AS (
-                    SELECT n.id, n.title,
-                           snippet(notes_fts, 1, '', '', '...', 20) AS content,
-                           n.updated_at, n.notebook_id,
-                           0 AS ocr_match
-                    FROM notes_fts
-                    JOIN notes n ON n.id = notes_fts.rowid
-                    WHERE notes_fts MATCH ?
-                      AND n.deleted_at IS NULL
-                ),
-                ocr_matches AS (
-                    SELECT n.id, n.title,
-                           '' AS content,
-                           n.updated_at, n.notebook_id,
-                           1 AS ocr_match
-                    FROM ocr_fts
-                    JOIN note_files nf ON nf.file_id = ocr_fts.rowid
-                    JOIN notes n ON n.id = nf.note_id
-                    WHERE ocr_fts MATCH ?
-                      AND n.deleted_at IS NULL
+            ""
+        };
+        let mut conditions = vec!["n.deleted_at IS NULL".to_string()];
+        if notebook_id.is_some() {
+            conditions.push("n.notebook_id IN (SELECT id FROM descendant_notebooks)".to_string());
+        }
+        conditions.extend(plan.conditions.iter().cloned());
+        let filter = conditions.join(" AND ");
+
+        let mut binds: Vec<SearchBind> = notebook_id.map(SearchBind::Int).into_iter().collect();
+        let sql = match plan.notes_match.as_ref() {
+            Some(notes_match) => {
+                binds.push(SearchBind::Text(notes_match.clone()));
+                binds.extend(plan.binds.iter().cloned());
+                // MATERIALIZED stops SQLite from flattening the match CTEs into
+                // the grouped outer query, where snippet() cannot run.
+                let mut ctes = vec![format!(
+                    "text_matches AS MATERIALIZED (
+                        SELECT n.id, n.title, n.created_at, n.updated_at, n.notebook_id,
+                               snippet(notes_fts, 1, char(2), char(3), '...', 20) AS body_snippet,
+                               NULL AS ocr_snippet,
+                               NULL AS attachment_snippet,
+                               -bm25(notes_fts, {}, {}, {}) AS score,
+                               0 AS ocr_match,
+                               0 AS attachment_match
+                        FROM notes_fts
+                        JOIN notes n ON n.id = notes_fts.rowid
+                        WHERE notes_fts MATCH ?
+                          AND {}
+                    )",
+                    SEARCH_TITLE_WEIGHT, SEARCH_BODY_WEIGHT, SEARCH_STEM_WEIGHT, filter
+                )];
+                let mut sources = "SELECT * FROM text_matches".to_string();
+                if let Some(ocr_match) = plan.ocr_match.as_ref() {
+                    binds.push(SearchBind::Text(ocr_match.clone()));
+                    binds.extend(plan.binds.iter().cloned());
+                    ctes.push(format!(
+                        "ocr_matches AS MATERIALIZED (
+                            SELECT n.id, n.title, n.created_at, n.updated_at, n.notebook_id,
+                                   NULL AS body_snippet,
+                                   snippet(ocr_fts, 0, char(2), char(3), '...', 20) AS ocr_snippet,
+                                   NULL AS attachment_snippet,
+                                   -bm25(ocr_fts) * {} AS score,
+                                   1 AS ocr_match,
+                                   0 AS attachment_match
+                            FROM ocr_fts
+                            JOIN note_files nf ON nf.file_id = ocr_fts.rowid
+                            JOIN notes n ON n.id = nf.note_id
+                            WHERE ocr_fts MATCH ?
+                              AND {}
+                        )",
+                        SEARCH_OCR_WEIGHT, filter
+                    ));
+                    sources.push_str(" UNION ALL SELECT * FROM ocr_matches");
+                    binds.push(SearchBind::Text(ocr_match.clone()));
+                    binds.extend(plan.binds.iter().cloned());
+                    ctes.push(format!(
+                        "attachment_matches AS MATERIALIZED (
+                            SELECT n.id, n.title, n.created_at, n.updated_at, n.notebook_id,
+                                   NULL AS body_snippet,
+                                   NULL AS ocr_snippet,
+                                   snippet(attachment_fts, 0, char(2), char(3), '...', 20) AS attachment_snippet,
+                                   -bm25(attachment_fts) * {} AS score,
+                                   0 AS ocr_match,
+                                   1 AS attachment_match
+                            FROM attachment_fts
+                            JOIN attachments a ON a.id = attachment_fts.rowid
+                            JOIN notes n ON n.id = a.note_id
+                            WHERE attachment_fts MATCH ?
+                              AND {}
+                        )",
+                        SEARCH_ATTACHMENT_WEIGHT, filter
+                    ));
+                    sources.push_str(" UNION ALL SELECT * FROM attachment_matches");
+                }
+                let with = if scope.is_empty() {
+                    "WITH ".to_string()
+                } else {
+                    format!("{},\n", scope)
+                };
+                format!(
+                    "{}{}
+                    SELECT id, title, created_at, updated_at, notebook_id,
+                           MAX(body_snippet) AS body_snippet,
+                           MAX(ocr_snippet) AS ocr_snippet,
+                           MAX(attachment_snippet) AS attachment_snippet,
+                           SUM(score) AS score,
+                           MAX(ocr_match) AS ocr_match,
+                           MAX(attachment_match) AS attachment_match
+                    FROM ({})
+                    GROUP BY id, title, created_at, updated_at, notebook_id
+                    ORDER BY {}",
+                    with,
+                    ctes.join(",\n"),
+                    sources,
+                    sort.order_by()
                 )
-                SELECT id, title,
-                       MAX(content) AS content,
-                       updated_at, notebook_id,
-                       MAX(ocr_match) AS ocr_match
-                FROM (
-                    SELECT * FROM text_matches
-                    UNION ALL
-                    SELECT * FROM ocr_matches
+            }
+            None => {
+                binds.extend(plan.binds.iter().cloned());
+                format!(
+                    "{}
+                    SELECT n.id AS id, n.title AS title, n.created_at AS created_at,
+                           n.updated_at AS updated_at, n.notebook_id AS notebook_id,
+                           substr(COALESCE(nt.plain_text, ''), 1, 160) AS body_snippet,
+                           NULL AS ocr_snippet,
+                           NULL AS attachment_snippet,
+                           0.0 AS score,
+                           0 AS ocr_match,
+                           0 AS attachment_match
+                    FROM notes n
+                    LEFT JOIN notes_text nt ON nt.note_id = n.id
+                    WHERE {}
+                    ORDER BY {}",
+                    scope,
+                    filter,
+                    sort.order_by()
                 )
-                GROUP BY id, title, updated_at, notebook_id
-                ORDER BY updated_at DESC, id DESC",
-            )
-            .bind(query)
-            .bind(query)
-            .fetch_all(&self.pool)
-            .await
+            }
+        };
+
+        let mut statement = sqlx::query_as::<_, SearchRow>(&sql);
+        for bind in binds {
+            statement = match bind {
+                SearchBind::Text(value) => statement.bind(value),
+                SearchBind::Int(value) => statement.bind(value),
+            };
         }
+        let rows = statement.fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let snippet = row.body_snippet.as_deref().map(SearchSnippet::from_marked);
+                SearchResult {
+                    id: row.id,
+                    title: row.title,
+                    content: snippet
+                        .as_ref()
+                        .map(|snippet| snippet.text.clone())
+                        .unwrap_or_default(),
+                    created_at: row.created_at,
+                    updated_at: row.updated_at,
+                    notebook_id: row.notebook_id,
+                    ocr_match: row.ocr_match,
+                    attachment_match: row.attachment_match,
+                    score: row.score,
+                    snippet,
+                    ocr_snippet: row.ocr_snippet.as_deref().map(SearchSnippet::from_marked),
+                    attachment_snippet: row
+                        .attachment_snippet
+                        .as_deref()
+                        .map(SearchSnippet::from_marked),
+                }
+            })
+            .collect())
     }
+    /// Notes tagged with `tag_id` or any of its descendant tags.
     pub async fn get_notes_by_tag(&self, tag_id: i64) -> Result<Vec<NoteListItem>, sqlx::Error> {
-        sqlx::query_as::<_, NoteListItem>(
-            "SELECT n.id, n.title, n.content, n.updated_at, n.notebook_id, 0 AS ocr_match
-             FROM notes n
-             JOIN note_tags nt ON nt.note_id = n.id
-             WHERE nt.tag_id = ?
-               AND n.deleted_at IS NULL
-             ORDER BY n.updated_at DESC, n.created_at DESC, n.id DESC",
-        )
-        .bind(tag_id)
-        .fetch_all(&self.pool)
-        .await
+        self.get_notes_by_tags(&[tag_id], false).await
+    }
+    /// Notes tagged with any (or, with `match_all`, every) tag in `tag_ids`,
+    /// where a tag also matches through its descendant tags.
+    pub async fn get_notes_by_tags(
+        &self,
+        tag_ids: &[i64],
+        match_all: bool,
+    ) -> Result<Vec<NoteListItem>, sqlx::Error> {
+        let mut tag_ids = tag_ids.to_vec();
+        tag_ids.sort();
+        tag_ids.dedup();
+        if tag_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; tag_ids.len()].join(", ");
+        let sql = format!(
+            "WITH RECURSIVE tag_tree(root_id, id) AS (
+                SELECT id, id FROM tags WHERE id IN ({})
+                UNION
+                SELECT tt.root_id, t.id FROM tags t
+                JOIN tag_tree tt ON t.parent_id = tt.id
+            )
+            SELECT n.id, n.title, n.content, n.updated_at, n.notebook_id, 0 AS ocr_match, 0 AS attachment_match
+            FROM notes n
+            WHERE n.deleted_at IS NULL
+              AND n.id IN (
+                SELECT nt.note_id FROM note_tags nt
+                JOIN tag_tree tt ON tt.id = nt.tag_id
+                GROUP BY nt.note_id
+                HAVING COUNT(DISTINCT tt.root_id) >= ?
+              )
+            ORDER BY n.updated_at DESC, n.created_at DESC, n.id DESC",
+            placeholders
+        );
+        let mut query = sqlx::query_as::<_, NoteListItem>(&sql);
+        for id in &tag_ids {
+            query = query.bind(id);
+        }
+        let required = if match_all { tag_ids.len() as i64 } else { 1 };
+        query.bind(required).fetch_all(&self.pool).await
     }
     pub async fn get_trashed_notes(&self) -> Result<Vec<NoteListItem>, sqlx::Error> {
         sqlx::query_as::<_, NoteListItem>(
-            "SELECT id, title, substr(content, 1, 4000) AS content, updated_at, notebook_id, 0 AS ocr_match
+            "SELECT id, title, substr(content, 1, 4000) AS content, updated_at, notebook_id, 0 AS ocr_match, 0 AS attachment_match
              FROM notes
              WHERE deleted_at IS NOT NULL
              ORDER BY deleted_at DESC, updated_at DESC, id DESC",
@@ -426,28 +775,43 @@ impl SqliteRepository {
         .fetch_all(&self.pool)
         .await
     }
-    pub async fn delete_note(&self, id: i64, data_dir: &Path) -> Result<(), sqlx::Error> {
-        let mut tx = self.pool.begin().await?;
+    /// Deletes the note rows and returns the note's attachment paths; the
+    /// caller checks which files are still in use once the transaction is done.
+    pub(crate) async fn delete_note_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: i64,
+    ) -> Result<Vec<String>, sqlx::Error> {
         let attachment_paths: Vec<(Option<String>,)> =
             sqlx::query_as("SELECT local_path FROM attachments WHERE note_id = ?")
                 .bind(id)
-                .fetch_all(&mut *tx)
+                .fetch_all(&mut **tx)
                 .await?;
         sqlx::query("DELETE FROM notes WHERE id = ?")
             .bind(id)
-            .execute(&mut *tx)
+            .execute(&mut **tx)
             .await?;
         sqlx::query("DELETE FROM notes_text WHERE note_id = ?")
             .bind(id)
-            .execute(&mut *tx)
+            .execute(&mut **tx)
             .await?;
+        Ok(attachment_paths
+            .into_iter()
+            .filter_map(|(path,)| path.filter(|path| !path.is_empty()))
+            .collect())
+    }
+    /// Finishes deleting notes: drops files no longer referenced by any note
+    /// or revision, commits, then removes them from disk.
+    pub(crate) async fn finish_note_deletes(
+        &self,
+        mut tx: sqlx::Transaction<'_, sqlx::Sqlite>,
+        attachment_paths: Vec<String>,
+        data_dir: &Path,
+    ) -> Result<(), sqlx::Error> {
         let orphan_files = self.cleanup_orphan_note_files_tx(&mut tx).await?;
+        let attachment_paths = self.unused_blobs_tx(&mut tx, attachment_paths).await?;
         tx.commit().await?;
-        for (path_opt,) in attachment_paths {
-            let Some(path) = path_opt else { continue };
-            if path.is_empty() {
-                continue;
-            }
+        for path in attachment_paths {
             let full_path = data_dir.join(path);
             if full_path.exists() {
                 let _ = fs::remove_file(&full_path);
@@ -467,8 +831,18 @@ impl SqliteRepository {
         }
         Ok(())
     }
-    pub async fn trash_note(&self, id: i64) -> Result<(), sqlx::Error> {
-        let now = chrono::Utc::now().timestamp();
+    pub async fn delete_note(&self, id: i64, data_dir: &Path) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let attachment_paths = self.delete_note_tx(&mut tx, id).await?;
+        self.finish_note_deletes(tx, attachment_paths, data_dir)
+            .await
+    }
+    pub(crate) async fn trash_note_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: i64,
+        now: i64,
+    ) -> Result<(), sqlx::Error> {
         sqlx::query(
             "UPDATE notes
              SET deleted_at = ?,
@@ -478,21 +852,31 @@ impl SqliteRepository {
         )
         .bind(now)
         .bind(id)
-        .execute(&self.pool)
+        .execute(&mut **tx)
         .await?;
         Ok(())
     }
-    pub async fn restore_note(&self, id: i64) -> Result<(), sqlx::Error> {
+    pub async fn trash_note(&self, id: i64) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
         let mut tx = self.pool.begin().await?;
+        self.trash_note_tx(&mut tx, id, now).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    pub(crate) async fn restore_note_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: i64,
+    ) -> Result<(), sqlx::Error> {
         let row: Option<(Option<i64>,)> =
             sqlx::query_as("SELECT deleted_from_notebook_id FROM notes WHERE id = ?")
                 .bind(id)
-                .fetch_optional(&mut *tx)
+                .fetch_optional(&mut **tx)
                 .await?;
         let target_notebook_id = if let Some((Some(notebook_id),)) = row {
             let exists: Option<(i64,)> = sqlx::query_as("SELECT id FROM notebooks WHERE id = ?")
                 .bind(notebook_id)
-                .fetch_optional(&mut *tx)
+                .fetch_optional(&mut **tx)
                 .await?;
             if exists.is_some() {
                 Some(notebook_id)
@@ -507,39 +891,136 @@ impl SqliteRepository {
             "UPDATE notes
              SET deleted_at = NULL,
                  deleted_from_notebook_id = NULL,
+                 deleted_with_notebook_id = NULL,
                  notebook_id = ?
              WHERE id = ?",
         )
         .bind(target_notebook_id)
         .bind(id)
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await?;
+        Ok(())
+    }
+    pub async fn restore_note(&self, id: i64) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        self.restore_note_tx(&mut tx, id).await?;
         tx.commit().await?;
         Ok(())
     }
-    pub async fn restore_all_notes(&self) -> Result<(), sqlx::Error> {
+    /// Restores every trashed notebook that still has a stack to return to,
+    /// then every trashed note.
+    pub async fn restore_all_notes(&self) -> Result<(), RepoError> {
+        let mut tx = self.pool.begin().await?;
+        let roots: Vec<(i64,)> = sqlx::query_as(
+            "SELECT id FROM trashed_notebooks WHERE id = trash_root_id ORDER BY deleted_at, id",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (root,) in roots {
+            // Already restored as the stack of an earlier notebook.
+            let trashed: Option<(i64,)> = sqlx::query_as(
+                "SELECT id FROM trashed_notebooks WHERE id = ? AND trash_root_id = ?",
+            )
+            .bind(root)
+            .bind(root)
+            .fetch_optional(&mut *tx)
+            .await?;
+            if trashed.is_none() {
+                continue;
+            }
+            match self.restore_trashed_notebook_tx(&mut tx, root).await {
+                // Its stack is gone; the notes are restored unfiled below.
+                Err(RepoError::Invalid(_)) => {}
+                result => result?,
+            }
+        }
         sqlx::query(
             "UPDATE notes
              SET notebook_id = (
                  SELECT id FROM notebooks WHERE id = notes.deleted_from_notebook_id
              ),
                  deleted_at = NULL,
-                 deleted_from_notebook_id = NULL
+                 deleted_from_notebook_id = NULL,
+                 deleted_with_notebook_id = NULL
              WHERE deleted_at IS NOT NULL",
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
+        tx.commit().await?;
         Ok(())
     }
     pub async fn delete_all_trashed_notes(&self, data_dir: &Path) -> Result<i64, sqlx::Error> {
-        let ids: Vec<(i64,)> = sqlx::query_as("SELECT id FROM notes WHERE deleted_at IS NOT NULL")
-            .fetch_all(&self.pool)
-            .await?;
+        self.purge_trashed_notes(i64::MAX, data_dir).await
+    }
+    /// Trashed notes that a purge with this cutoff would delete, oldest first.
+    /// Notes trashed with a notebook expire with it.
+    pub async fn get_trashed_notes_before(
+        &self,
+        deleted_before: i64,
+    ) -> Result<Vec<NoteListItem>, sqlx::Error> {
+        sqlx::query_as::<_, NoteListItem>(
+            "SELECT n.id, n.title, substr(n.content, 1, 4000) AS content, n.updated_at, n.notebook_id, 0 AS ocr_match, 0 AS attachment_match
+             FROM notes n
+             LEFT JOIN trashed_notebooks t
+               ON t.id = n.deleted_with_notebook_id AND t.id = t.trash_root_id
+             WHERE n.deleted_at IS NOT NULL AND COALESCE(t.deleted_at, n.deleted_at) < ?
+             ORDER BY COALESCE(t.deleted_at, n.deleted_at) ASC, n.id ASC",
+        )
+        .bind(deleted_before)
+        .fetch_all(&self.pool)
+        .await
+    }
+    /// Permanently deletes notes trashed before `deleted_before`, one
+    /// transaction per batch so a large trash does not hold the write lock.
+    /// A trashed notebook goes as a unit with its notes, in a transaction
+    /// of its own, once the notebook itself has expired.
+    pub async fn purge_trashed_notes(
+        &self,
+        deleted_before: i64,
+        data_dir: &Path,
+    ) -> Result<i64, sqlx::Error> {
         let mut deleted = 0;
-        for (id,) in ids {
-            self.delete_note(id, data_dir).await?;
-            deleted += 1;
+        let roots: Vec<(i64,)> = sqlx::query_as(
+            "SELECT id FROM trashed_notebooks
+             WHERE id = trash_root_id AND deleted_at < ?
+             ORDER BY deleted_at ASC, id ASC",
+        )
+        .bind(deleted_before)
+        .fetch_all(&self.pool)
+        .await?;
+        for (root,) in roots {
+            let mut tx = self.pool.begin().await?;
+            let (count, attachment_paths) = self.delete_trashed_notebook_tx(&mut tx, root).await?;
+            self.finish_note_deletes(tx, attachment_paths, data_dir)
+                .await?;
+            deleted += count;
+        }
+        loop {
+            let mut tx = self.pool.begin().await?;
+            let ids: Vec<(i64,)> = sqlx::query_as(
+                "SELECT n.id FROM notes n
+                 WHERE n.deleted_at IS NOT NULL AND n.deleted_at < ?
+                   AND NOT EXISTS (
+                     SELECT 1 FROM trashed_notebooks t
+                     WHERE t.id = n.deleted_with_notebook_id AND t.id = t.trash_root_id
+                   )
+                 ORDER BY n.deleted_at ASC, n.id ASC
+                 LIMIT ?",
+            )
+            .bind(deleted_before)
+            .bind(TRASH_PURGE_BATCH)
+            .fetch_all(&mut *tx)
+            .await?;
+            if ids.is_empty() {
+                return Ok(deleted);
+            }
+            let mut attachment_paths = Vec::new();
+            for (id,) in &ids {
+                attachment_paths.extend(self.delete_note_tx(&mut tx, *id).await?);
+            }
+            self.finish_note_deletes(tx, attachment_paths, data_dir)
+                .await?;
+            deleted += ids.len() as i64;
         }
-        Ok(deleted)
     }
 }
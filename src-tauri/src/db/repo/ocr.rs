@@ -1,5 +1,6 @@
 use super::SqliteRepository;
 use crate::db::models::{OcrFileItem, OcrStats};
+use crate::db::stem::{current_stem_language, WordStemmer};
 use crate::db::utils::OCR_IMAGE_FILTER;
 use std::path::Path;
 
@@ -60,18 +61,24 @@ impl SqliteRepository {
         hash: &str,
     ) -> Result<(), sqlx::Error> {
         let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let language = current_stem_language(&mut tx).await?;
         sqlx::query(
-            "INSERT INTO ocr_text (file_id, lang, text, hash, updated_at)
-             VALUES (?, ?, ?, ?, ?)
-             ON CONFLICT(file_id) DO UPDATE SET lang = excluded.lang, text = excluded.text, hash = excluded.hash, updated_at = excluded.updated_at",
+            "INSERT INTO ocr_text (file_id, lang, text, hash, updated_at, stems, stems_lang)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(file_id) DO UPDATE SET lang = excluded.lang, text = excluded.text, hash = excluded.hash, updated_at = excluded.updated_at,
+               stems = excluded.stems, stems_lang = excluded.stems_lang",
         )
         .bind(file_id)
         .bind(lang)
         .bind(text)
         .bind(hash)
         .bind(now)
-        .execute(&self.pool)
+        .bind(WordStemmer::new(language).stem_text(text))
+        .bind(language.code())
+        .execute(&mut *tx)
         .await?;
+        tx.commit().await?;
         Ok(())
     }
     pub async fn mark_ocr_failed(&self, file_id: i64, message: &str) -> Result<(), sqlx::Error> {
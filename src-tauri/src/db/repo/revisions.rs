@@ -0,0 +1,178 @@
+use super::SqliteRepository;
+use crate::db::models::{NoteRevision, NoteRevisionItem, RevisionPolicy};
+use crate::db::utils::{extract_attachment_ids, extract_note_files};
+use std::collections::HashSet;
+use std::path::Path;
+
+impl SqliteRepository {
+    pub(crate) async fn snapshot_note_revision_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        note_id: i64,
+        next_title: &str,
+        next_content: &str,
+        policy: &RevisionPolicy,
+    ) -> Result<bool, sqlx::Error> {
+        if !policy.enabled {
+            return Ok(false);
+        }
+        let current: Option<(String, String)> =
+            sqlx::query_as("SELECT title, content FROM notes WHERE id = ?")
+                .bind(note_id)
+                .fetch_optional(&mut **tx)
+                .await?;
+        let Some((title, content)) = current else {
+            return Ok(false);
+        };
+        if title == next_title && content == next_content {
+            return Ok(false);
+        }
+        let now = chrono::Utc::now().timestamp();
+        if policy.min_interval_seconds > 0 {
+            let last: Option<(i64,)> = sqlx::query_as(
+                "SELECT created_at FROM note_revisions WHERE note_id = ? ORDER BY created_at DESC, id DESC LIMIT 1",
+            )
+            .bind(note_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+            if let Some((created_at,)) = last {
+                if now - created_at < policy.min_interval_seconds {
+                    return Ok(false);
+                }
+            }
+        }
+        let result = sqlx::query(
+            "INSERT INTO note_revisions (note_id, title, content, created_at, content_size)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(note_id)
+        .bind(&title)
+        .bind(&content)
+        .bind(now)
+        .bind(content.len() as i64)
+        .execute(&mut **tx)
+        .await?;
+        let revision_id = result.last_insert_rowid();
+        for file_path in extract_note_files(&content) {
+            sqlx::query(
+                "INSERT INTO ocr_files (file_path) VALUES (?) ON CONFLICT(file_path) DO NOTHING",
+            )
+            .bind(&file_path)
+            .execute(&mut **tx)
+            .await?;
+            sqlx::query(
+                "INSERT INTO note_revision_files (revision_id, file_id)
+                 SELECT ?, id FROM ocr_files WHERE file_path = ?
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(revision_id)
+            .bind(&file_path)
+            .execute(&mut **tx)
+            .await?;
+        }
+        self.prune_note_revisions_tx(tx, note_id, policy).await?;
+        Ok(true)
+    }
+    async fn prune_note_revisions_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        note_id: i64,
+        policy: &RevisionPolicy,
+    ) -> Result<(), sqlx::Error> {
+        if policy.max_per_note > 0 {
+            sqlx::query(
+                "DELETE FROM note_revisions
+                 WHERE note_id = ?
+                   AND id NOT IN (
+                       SELECT id FROM note_revisions
+                       WHERE note_id = ?
+                       ORDER BY created_at DESC, id DESC
+                       LIMIT ?
+                   )",
+            )
+            .bind(note_id)
+            .bind(note_id)
+            .bind(policy.max_per_note)
+            .execute(&mut **tx)
+            .await?;
+        }
+        if policy.max_age_days > 0 {
+            let cutoff = chrono::Utc::now().timestamp() - (policy.max_age_days * 86400);
+            sqlx::query("DELETE FROM note_revisions WHERE note_id = ? AND created_at < ?")
+                .bind(note_id)
+                .bind(cutoff)
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(())
+    }
+    pub(crate) async fn revision_attachment_ids_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        note_id: i64,
+    ) -> Result<HashSet<i64>, sqlx::Error> {
+        let rows: Vec<(String,)> = sqlx::query_as(
+            "SELECT content FROM note_revisions
+             WHERE note_id = ? AND content LIKE '%data-attachment-id%'",
+        )
+        .bind(note_id)
+        .fetch_all(&mut **tx)
+        .await?;
+        let mut ids = HashSet::new();
+        for (content,) in rows {
+            ids.extend(extract_attachment_ids(&content));
+        }
+        Ok(ids)
+    }
+    pub async fn get_note_revisions(
+        &self,
+        note_id: i64,
+    ) -> Result<Vec<NoteRevisionItem>, sqlx::Error> {
+        sqlx::query_as::<_, NoteRevisionItem>(
+            "SELECT id, note_id, title, created_at, content_size
+             FROM note_revisions
+             WHERE note_id = ?
+             ORDER BY created_at DESC, id DESC",
+        )
+        .bind(note_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+    pub async fn get_note_revision(&self, id: i64) -> Result<Option<NoteRevision>, sqlx::Error> {
+        sqlx::query_as::<_, NoteRevision>(
+            "SELECT id, note_id, title, content, created_at, content_size
+             FROM note_revisions
+             WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+    pub async fn restore_note_revision(
+        &self,
+        revision_id: i64,
+        data_dir: &Path,
+        policy: &RevisionPolicy,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let Some(revision) = self.get_note_revision(revision_id).await? else {
+            return Ok(None);
+        };
+        let Some(note) = self.get_note(revision.note_id).await? else {
+            return Ok(None);
+        };
+        let forced = RevisionPolicy {
+            min_interval_seconds: 0,
+            ..*policy
+        };
+        self.update_note(
+            note.id,
+            &revision.title,
+            &revision.content,
+            note.notebook_id,
+            data_dir,
+            &forced,
+        )
+        .await?;
+        Ok(Some(note.id))
+    }
+}
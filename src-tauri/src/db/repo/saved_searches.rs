@@ -0,0 +1,117 @@
+use super::SqliteRepository;
+use crate::db::models::{SavedSearch, SavedSearchCount, SearchResult};
+use crate::db::search::{SearchError, SearchQuery, SearchSort};
+
+impl SqliteRepository {
+    pub async fn get_saved_searches(&self) -> Result<Vec<SavedSearch>, sqlx::Error> {
+        sqlx::query_as::<_, SavedSearch>(
+            "SELECT * FROM saved_searches ORDER BY sort_order ASC, name ASC, id ASC",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+    pub async fn get_saved_search(&self, id: i64) -> Result<Option<SavedSearch>, sqlx::Error> {
+        sqlx::query_as::<_, SavedSearch>("SELECT * FROM saved_searches WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+    /// Stores a search after checking that `query` parses, so a saved search
+    /// cannot fail on every run.
+    pub async fn create_saved_search(
+        &self,
+        name: &str,
+        query: &str,
+        notebook_id: Option<i64>,
+        sort: SearchSort,
+    ) -> Result<i64, SearchError> {
+        SearchQuery::parse(query)?;
+        let now = chrono::Utc::now().timestamp();
+        let max_order: (Option<i64>,) =
+            sqlx::query_as("SELECT MAX(sort_order) FROM saved_searches")
+                .fetch_one(&self.pool)
+                .await?;
+        let result = sqlx::query(
+            "INSERT INTO saved_searches (name, query, notebook_id, sort, sort_order, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(name)
+        .bind(query)
+        .bind(notebook_id)
+        .bind(sort)
+        .bind(max_order.0.unwrap_or(-1) + 1)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.last_insert_rowid())
+    }
+    pub async fn rename_saved_search(&self, id: i64, name: &str) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
+        sqlx::query("UPDATE saved_searches SET name = ?, updated_at = ? WHERE id = ?")
+            .bind(name)
+            .bind(now)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+    pub async fn move_saved_search(&self, id: i64, target_index: usize) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut ids: Vec<i64> = sqlx::query_as(
+            "SELECT id FROM saved_searches WHERE id != ? ORDER BY sort_order ASC, name ASC, id ASC",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+        let insert_index = target_index.min(ids.len());
+        ids.insert(insert_index, id);
+        for (index, id) in ids.iter().enumerate() {
+            sqlx::query("UPDATE saved_searches SET sort_order = ? WHERE id = ?")
+                .bind(index as i64)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+    pub async fn delete_saved_search(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM saved_searches WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+    pub async fn run_saved_search(&self, id: i64) -> Result<Vec<SearchResult>, SearchError> {
+        let saved = self
+            .get_saved_search(id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        self.search_notes(&saved.query, saved.notebook_id, saved.sort)
+            .await
+    }
+    /// Result counts for the sidebar. A stored query that no longer parses
+    /// counts as zero instead of failing the whole request.
+    pub async fn count_saved_searches(&self) -> Result<Vec<SavedSearchCount>, SearchError> {
+        let mut counts = Vec::new();
+        for saved in self.get_saved_searches().await? {
+            let count = match self
+                .search_notes(&saved.query, saved.notebook_id, saved.sort)
+                .await
+            {
+                Ok(results) => results.len() as i64,
+                Err(SearchError::Query(_)) => 0,
+                Err(err) => return Err(err),
+            };
+            counts.push(SavedSearchCount {
+                saved_search_id: saved.id,
+                count,
+            });
+        }
+        Ok(counts)
+    }
+}
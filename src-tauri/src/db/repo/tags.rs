@@ -1,5 +1,7 @@
 use super::SqliteRepository;
+use crate::db::error::RepoError;
 use crate::db::models::Tag;
+use crate::db::search::SearchQuery;
 
 impl SqliteRepository {
     pub async fn get_tags(&self) -> Result<Vec<Tag>, sqlx::Error> {
@@ -101,16 +103,198 @@ impl SqliteRepository {
             .await?;
         Ok(())
     }
+    /// Renames a tag; saved searches using `tag:old` follow the new name
+    /// unless another tag still carries the old one.
     pub async fn rename_tag(&self, tag_id: i64, name: &str) -> Result<(), sqlx::Error> {
         let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let (old_name,): (String,) = sqlx::query_as("SELECT name FROM tags WHERE id = ?")
+            .bind(tag_id)
+            .fetch_one(&mut *tx)
+            .await?;
         sqlx::query("UPDATE tags SET name = ?, updated_at = ? WHERE id = ?")
             .bind(name)
             .bind(now)
             .bind(tag_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
+            .await?;
+        self.rename_saved_search_tags_tx(&mut tx, &old_name, name)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    async fn rename_saved_search_tags_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), sqlx::Error> {
+        if old_name.to_lowercase() == new_name.to_lowercase() {
+            return Ok(());
+        }
+        let (still_used,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM tags WHERE LOWER(name) = LOWER(?)")
+                .bind(old_name)
+                .fetch_one(&mut **tx)
+                .await?;
+        if still_used > 0 {
+            return Ok(());
+        }
+        let now = chrono::Utc::now().timestamp();
+        let searches: Vec<(i64, String)> = sqlx::query_as("SELECT id, query FROM saved_searches")
+            .fetch_all(&mut **tx)
+            .await?;
+        for (id, query) in searches {
+            let Some(query) = SearchQuery::rename_tag(&query, old_name, new_name) else {
+                continue;
+            };
+            sqlx::query("UPDATE saved_searches SET query = ?, updated_at = ? WHERE id = ?")
+                .bind(query)
+                .bind(now)
+                .bind(id)
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(())
+    }
+    /// Merges `source_id` into `target_id`: its notes get the target tag, its
+    /// children move under the target (merging into a same-named child of the
+    /// target where one exists), and the source is deleted.
+    pub async fn merge_tags(&self, source_id: i64, target_id: i64) -> Result<(), RepoError> {
+        let mut tx = self.pool.begin().await?;
+        let (inside_source,): (i64,) = sqlx::query_as(
+            "WITH RECURSIVE tag_tree(id) AS (
+               SELECT id FROM tags WHERE id = ?
+               UNION
+               SELECT t.id FROM tags t
+               JOIN tag_tree tt ON t.parent_id = tt.id
+             )
+             SELECT COUNT(*) FROM tag_tree WHERE id = ?",
+        )
+        .bind(source_id)
+        .bind(target_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if inside_source > 0 {
+            return Err(RepoError::Invalid(
+                "Cannot merge a tag into itself or one of its children".to_string(),
+            ));
+        }
+        let (source_name,): (String,) = sqlx::query_as("SELECT name FROM tags WHERE id = ?")
+            .bind(source_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let (target_name,): (String,) = sqlx::query_as("SELECT name FROM tags WHERE id = ?")
+            .bind(target_id)
+            .fetch_one(&mut *tx)
             .await?;
+
+        let now = chrono::Utc::now().timestamp();
+        let mut pending = vec![(source_id, target_id)];
+        let mut merged = Vec::new();
+        while let Some((source, target)) = pending.pop() {
+            sqlx::query(
+                "INSERT OR IGNORE INTO note_tags (note_id, tag_id)
+                 SELECT note_id, ? FROM note_tags WHERE tag_id = ?",
+            )
+            .bind(target)
+            .bind(source)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM note_tags WHERE tag_id = ?")
+                .bind(source)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "INSERT OR IGNORE INTO note_template_tags (template_id, tag_id)
+                 SELECT template_id, ? FROM note_template_tags WHERE tag_id = ?",
+            )
+            .bind(target)
+            .bind(source)
+            .execute(&mut *tx)
+            .await?;
+            let children: Vec<(i64, String)> =
+                sqlx::query_as("SELECT id, name FROM tags WHERE parent_id = ?")
+                    .bind(source)
+                    .fetch_all(&mut *tx)
+                    .await?;
+            for (child_id, name) in children {
+                // idx_tags_parent_name allows one child per name.
+                let existing: Option<(i64,)> =
+                    sqlx::query_as("SELECT id FROM tags WHERE parent_id = ? AND name = ?")
+                        .bind(target)
+                        .bind(&name)
+                        .fetch_optional(&mut *tx)
+                        .await?;
+                match existing {
+                    Some((existing_id,)) => pending.push((child_id, existing_id)),
+                    None => {
+                        sqlx::query("UPDATE tags SET parent_id = ?, updated_at = ? WHERE id = ?")
+                            .bind(target)
+                            .bind(now)
+                            .bind(child_id)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                }
+            }
+            merged.push(source);
+        }
+        // Children are moved off first so the parent cascade removes nothing else.
+        for id in merged.iter().rev() {
+            sqlx::query("DELETE FROM tags WHERE id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("UPDATE tags SET updated_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(target_id)
+            .execute(&mut *tx)
+            .await?;
+        self.rename_saved_search_tags_tx(&mut tx, &source_name, &target_name)
+            .await?;
+        tx.commit().await?;
         Ok(())
     }
+    /// Tags every note in `note_ids`; returns how many notes gained the tag.
+    pub async fn add_tag_to_notes(
+        &self,
+        tag_id: i64,
+        note_ids: &[i64],
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut added = 0;
+        for note_id in note_ids {
+            added += sqlx::query("INSERT OR IGNORE INTO note_tags (note_id, tag_id) VALUES (?, ?)")
+                .bind(note_id)
+                .bind(tag_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(added)
+    }
+    /// Untags every note in `note_ids`; returns how many notes lost the tag.
+    pub async fn remove_tag_from_notes(
+        &self,
+        tag_id: i64,
+        note_ids: &[i64],
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut removed = 0;
+        for note_id in note_ids {
+            removed += sqlx::query("DELETE FROM note_tags WHERE note_id = ? AND tag_id = ?")
+                .bind(note_id)
+                .bind(tag_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(removed)
+    }
     pub async fn remove_note_tag(&self, note_id: i64, tag_id: i64) -> Result<(), sqlx::Error> {
         sqlx::query("DELETE FROM note_tags WHERE note_id = ? AND tag_id = ?")
             .bind(note_id)
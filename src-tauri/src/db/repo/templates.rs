@@ -0,0 +1,229 @@
+use super::SqliteRepository;
+use crate::db::models::NoteTemplate;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Replaces `{{name}}` placeholders with their values. Unknown placeholders
+/// are left as written. Values are HTML-escaped when going into a note body
+/// and kept on one line when going into a title.
+fn expand_placeholders(text: &str, values: &[(&str, &str)], html: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + 2 + len].trim();
+        out.push_str(&rest[..start]);
+        match values
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some((_, value)) if html => out.push_str(&escape_html(value)),
+            Some((_, value)) => out.push_str(&value.replace(['\r', '\n'], " ")),
+            None => out.push_str(&rest[start..start + len + 4]),
+        }
+        rest = &rest[start + len + 4..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\r', "")
+        .replace('\n', "<br>")
+}
+
+impl SqliteRepository {
+    pub async fn get_note_templates(&self) -> Result<Vec<NoteTemplate>, sqlx::Error> {
+        let mut templates = sqlx::query_as::<_, NoteTemplate>(
+            "SELECT * FROM note_templates ORDER BY name COLLATE NOCASE ASC, id ASC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let links: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT template_id, tag_id FROM note_template_tags ORDER BY template_id, tag_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut tags: HashMap<i64, Vec<i64>> = HashMap::new();
+        for (template_id, tag_id) in links {
+            tags.entry(template_id).or_default().push(tag_id);
+        }
+        for template in &mut templates {
+            template.tag_ids = tags.remove(&template.id).unwrap_or_default();
+        }
+        Ok(templates)
+    }
+    pub async fn get_note_template(&self, id: i64) -> Result<Option<NoteTemplate>, sqlx::Error> {
+        let template =
+            sqlx::query_as::<_, NoteTemplate>("SELECT * FROM note_templates WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(mut template) = template else {
+            return Ok(None);
+        };
+        template.tag_ids = sqlx::query_as(
+            "SELECT tag_id FROM note_template_tags WHERE template_id = ? ORDER BY tag_id",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(tag_id,): (i64,)| tag_id)
+        .collect();
+        Ok(Some(template))
+    }
+    pub async fn create_note_template(
+        &self,
+        name: &str,
+        title: &str,
+        content: &str,
+        notebook_id: Option<i64>,
+        tag_ids: &[i64],
+    ) -> Result<i64, sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let id = sqlx::query(
+            "INSERT INTO note_templates (name, title, content, notebook_id, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(name)
+        .bind(title)
+        .bind(content)
+        .bind(notebook_id)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+        self.set_template_tags_tx(&mut tx, id, tag_ids).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+    pub async fn update_note_template(
+        &self,
+        id: i64,
+        name: &str,
+        title: &str,
+        content: &str,
+        notebook_id: Option<i64>,
+        tag_ids: &[i64],
+    ) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query(
+            "UPDATE note_templates
+             SET name = ?, title = ?, content = ?, notebook_id = ?, updated_at = ?
+             WHERE id = ?",
+        )
+        .bind(name)
+        .bind(title)
+        .bind(content)
+        .bind(notebook_id)
+        .bind(now)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        self.set_template_tags_tx(&mut tx, id, tag_ids).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+    async fn set_template_tags_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        template_id: i64,
+        tag_ids: &[i64],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM note_template_tags WHERE template_id = ?")
+            .bind(template_id)
+            .execute(&mut **tx)
+            .await?;
+        for tag_id in tag_ids {
+            sqlx::query(
+                "INSERT OR IGNORE INTO note_template_tags (template_id, tag_id) VALUES (?, ?)",
+            )
+            .bind(template_id)
+            .bind(tag_id)
+            .execute(&mut **tx)
+            .await?;
+        }
+        Ok(())
+    }
+    pub async fn delete_note_template(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM note_templates WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+    /// Creates a note from a template in `notebook_id`, or in the template's
+    /// default notebook when none is given and it still exists. Placeholders
+    /// are expanded with the local date and time, the notebook name and
+    /// `clipboard`. The note and the template's tags are written in one
+    /// transaction.
+    pub async fn create_note_from_template(
+        &self,
+        template_id: i64,
+        notebook_id: Option<i64>,
+        clipboard: &str,
+        data_dir: &Path,
+    ) -> Result<i64, sqlx::Error> {
+        let template = self
+            .get_note_template(template_id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let notebook: Option<(i64, String)> = match notebook_id {
+            Some(id) => Some(
+                sqlx::query_as("SELECT id, name FROM notebooks WHERE id = ?")
+                    .bind(id)
+                    .fetch_one(&self.pool)
+                    .await?,
+            ),
+            None => {
+                sqlx::query_as("SELECT id, name FROM notebooks WHERE id = ?")
+                    .bind(template.notebook_id)
+                    .fetch_optional(&self.pool)
+                    .await?
+            }
+        };
+        let now = chrono::Local::now();
+        let date = now.format("%Y-%m-%d").to_string();
+        let time = now.format("%H:%M").to_string();
+        let notebook_name = notebook
+            .as_ref()
+            .map(|(_, name)| name.as_str())
+            .unwrap_or("");
+        let values = [
+            ("date", date.as_str()),
+            ("time", time.as_str()),
+            ("notebook", notebook_name),
+            ("clipboard", clipboard),
+        ];
+        let title = expand_placeholders(&template.title, &values, false);
+        let content = expand_placeholders(&template.content, &values, true);
+        let _ = data_dir;
+        let mut tx = self.pool.begin().await?;
+        let note_id = self
+            .create_note_tx(&mut tx, title.trim(), &content, notebook.map(|(id, _)| id))
+            .await?;
+        for tag_id in &template.tag_ids {
+            sqlx::query("INSERT OR IGNORE INTO note_tags (note_id, tag_id) VALUES (?, ?)")
+                .bind(note_id)
+                .bind(tag_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(note_id)
+    }
+}
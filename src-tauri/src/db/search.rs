@@ -0,0 +1,657 @@
+use crate::db::fts::fold_search_text;
+use crate::db::stem::{StemLanguage, WordStemmer};
+use chrono::{Datelike, Duration, Local, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+use std::fmt;
+
+/// A malformed search query, with the byte offset of the offending token.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchQueryError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl fmt::Display for SearchQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum SearchError {
+    Query(SearchQueryError),
+    Database { message: String },
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Query(err) => write!(f, "Invalid search query: {}", err),
+            SearchError::Database { message } => f.write_str(message),
+        }
+    }
+}
+
+impl From<sqlx::Error> for SearchError {
+    fn from(err: sqlx::Error) -> Self {
+        SearchError::Database {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<SearchQueryError> for SearchError {
+    fn from(err: SearchQueryError) -> Self {
+        SearchError::Query(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum SearchSort {
+    /// bm25 score, with title hits above body hits above OCR hits.
+    #[default]
+    Relevance,
+    Updated,
+    Created,
+    Title,
+}
+
+impl SearchSort {
+    pub fn order_by(self) -> &'static str {
+        match self {
+            SearchSort::Relevance => "score DESC, updated_at DESC, id DESC",
+            SearchSort::Updated => "updated_at DESC, id DESC",
+            SearchSort::Created => "created_at DESC, id DESC",
+            SearchSort::Title => "title COLLATE NOCASE ASC, id ASC",
+        }
+    }
+}
+
+impl std::str::FromStr for SearchSort {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "" | "relevance" => Ok(SearchSort::Relevance),
+            "updated" => Ok(SearchSort::Updated),
+            "created" => Ok(SearchSort::Created),
+            "title" => Ok(SearchSort::Title),
+            other => Err(format!("Unknown sort: {}", other)),
+        }
+    }
+}
+
+/// FTS `snippet()` markers; they never occur in indexed text and are
+/// replaced by offsets in [`SearchSnippet`].
+pub const SNIPPET_START: char = '\u{2}';
+pub const SNIPPET_END: char = '\u{3}';
+
+/// Snippet text with the matched ranges as `[start, end)` offsets in UTF-16
+/// code units, so the UI can slice it as a JavaScript string.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SearchSnippet {
+    pub text: String,
+    pub highlights: Vec<[usize; 2]>,
+}
+
+impl SearchSnippet {
+    pub fn from_marked(raw: &str) -> Self {
+        let mut text = String::with_capacity(raw.len());
+        let mut highlights = Vec::new();
+        let mut offset = 0usize;
+        let mut start = None;
+        for ch in raw.chars() {
+            match ch {
+                SNIPPET_START => start = Some(offset),
+                SNIPPET_END => {
+                    if let Some(begin) = start.take() {
+                        highlights.push([begin, offset]);
+                    }
+                }
+                _ => {
+                    text.push(ch);
+                    offset += ch.len_utf16();
+                }
+            }
+        }
+        SearchSnippet { text, highlights }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchTodo {
+    Checked,
+    Unchecked,
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchFilter {
+    /// Free text; a trailing `*` makes it a prefix match.
+    Text(String),
+    Phrase(String),
+    InTitle(String),
+    Tag(String),
+    Notebook(String),
+    Stack(String),
+    CreatedSince(i64),
+    UpdatedSince(i64),
+    Resource(String),
+    Todo(SearchTodo),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchTerm {
+    pub negated: bool,
+    pub filter: SearchFilter,
+}
+
+/// Parsed form of the Evernote-style search grammar:
+/// `tag:`, `notebook:`, `stack:`, `created:`, `updated:`, `intitle:`,
+/// `resource:`, `todo:`, `"phrases"` and `-negation`. Unknown `prefix:`
+/// words are searched as plain text; a bare `AND` is ignored since every
+/// term must match anyway.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    pub terms: Vec<SearchTerm>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SearchBind {
+    Text(String),
+    Int(i64),
+}
+
+/// SQL pieces for a parsed query. `notes_match` and `ocr_match` are FTS5
+/// expressions (absent when the query has no positive text terms; OCR is
+/// skipped for `intitle:` queries); `ocr_match` also runs against
+/// `attachment_fts`, which has the same columns. `conditions` are ANDed onto
+/// `notes n`.
+#[derive(Debug, Default)]
+pub struct SearchPlan {
+    pub notes_match: Option<String>,
+    pub ocr_match: Option<String>,
+    pub conditions: Vec<String>,
+    pub binds: Vec<SearchBind>,
+}
+
+struct Token {
+    negated: bool,
+    key: Option<String>,
+    value: String,
+    quoted: bool,
+    position: usize,
+    end: usize,
+}
+
+fn syntax_error(message: impl Into<String>, position: usize) -> SearchQueryError {
+    SearchQueryError {
+        message: message.into(),
+        position,
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, SearchQueryError> {
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].1.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let position = chars[i].0;
+        let mut negated = false;
+        if chars[i].1 == '-' {
+            negated = true;
+            i += 1;
+            if i >= chars.len() || chars[i].1.is_whitespace() {
+                return Err(syntax_error("Nothing to exclude after '-'", position));
+            }
+        }
+        let mut key = None;
+        let mut value = String::new();
+        let mut quoted = false;
+        while i < chars.len() && !chars[i].1.is_whitespace() {
+            let ch = chars[i].1;
+            if ch == '"' {
+                let start = chars[i].0;
+                i += 1;
+                let mut closed = false;
+                while i < chars.len() {
+                    if chars[i].1 == '"' {
+                        closed = true;
+                        break;
+                    }
+                    value.push(chars[i].1);
+                    i += 1;
+                }
+                if !closed {
+                    return Err(syntax_error("Unterminated quote", start));
+                }
+                quoted = true;
+                i += 1;
+                continue;
+            }
+            if ch == ':' && key.is_none() && !quoted && !value.is_empty() {
+                key = Some(std::mem::take(&mut value).to_lowercase());
+                i += 1;
+                continue;
+            }
+            value.push(ch);
+            i += 1;
+        }
+        tokens.push(Token {
+            negated,
+            key,
+            value,
+            quoted,
+            position,
+            end: chars.get(i).map_or(input.len(), |(offset, _)| *offset),
+        });
+    }
+    Ok(tokens)
+}
+
+fn start_of_day(date: NaiveDate) -> i64 {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("valid time");
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|value| value.timestamp())
+        .unwrap_or_else(|| Utc.from_utc_datetime(&midnight).timestamp())
+}
+
+/// `YYYYMMDD`, `YYYY-MM-DD`, or Evernote's relative `day`, `week`, `month`
+/// and `year` with an optional `-N` offset, in local time.
+fn parse_date(value: &str, position: usize) -> Result<i64, SearchQueryError> {
+    let lower = value.to_lowercase();
+    for format in ["%Y%m%d", "%Y-%m-%d"] {
+        if let Ok(date) = NaiveDate::parse_from_str(&lower, format) {
+            return Ok(start_of_day(date));
+        }
+    }
+    let (unit, offset) = match lower.split_once('-') {
+        Some((unit, offset)) => {
+            let offset = offset
+                .parse::<u32>()
+                .map_err(|_| syntax_error(format!("Invalid date offset: {}", value), position))?;
+            (unit.to_string(), offset)
+        }
+        None => (lower.clone(), 0),
+    };
+    let today = Local::now().date_naive();
+    let date = match unit.as_str() {
+        "day" => today - Duration::days(offset as i64),
+        "week" => {
+            let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+            monday - Duration::weeks(offset as i64)
+        }
+        "month" => {
+            let months = today.year() * 12 + today.month0() as i32 - offset as i32;
+            NaiveDate::from_ymd_opt(months.div_euclid(12), months.rem_euclid(12) as u32 + 1, 1)
+                .ok_or_else(|| syntax_error(format!("Invalid date: {}", value), position))?
+        }
+        "year" => NaiveDate::from_ymd_opt(today.year() - offset as i32, 1, 1)
+            .ok_or_else(|| syntax_error(format!("Invalid date: {}", value), position))?,
+        _ => return Err(syntax_error(format!("Invalid date: {}", value), position)),
+    };
+    Ok(start_of_day(date))
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Result<Self, SearchQueryError> {
+        let mut terms = Vec::new();
+        for token in tokenize(input)? {
+            let Token {
+                negated,
+                key,
+                value,
+                quoted,
+                position,
+                ..
+            } = token;
+            let filter = match key.as_deref() {
+                None if !quoted && !negated && value == "AND" => continue,
+                None if quoted => SearchFilter::Phrase(value),
+                None => SearchFilter::Text(value),
+                Some(key) => {
+                    let known = matches!(
+                        key,
+                        "tag"
+                            | "notebook"
+                            | "stack"
+                            | "created"
+                            | "updated"
+                            | "intitle"
+                            | "resource"
+                            | "todo"
+                    );
+                    if known && value.trim().is_empty() {
+                        return Err(syntax_error(
+                            format!("Missing value for {}:", key),
+                            position,
+                        ));
+                    }
+                    match key {
+                        "tag" => SearchFilter::Tag(value),
+                        "notebook" => SearchFilter::Notebook(value),
+                        "stack" => SearchFilter::Stack(value),
+                        "created" => SearchFilter::CreatedSince(parse_date(&value, position)?),
+                        "updated" => SearchFilter::UpdatedSince(parse_date(&value, position)?),
+                        "intitle" => SearchFilter::InTitle(value),
+                        "resource" => SearchFilter::Resource(value.to_lowercase()),
+                        "todo" => SearchFilter::Todo(match value.to_lowercase().as_str() {
+                            "true" => SearchTodo::Checked,
+                            "false" => SearchTodo::Unchecked,
+                            "*" => SearchTodo::Any,
+                            _ => {
+                                return Err(syntax_error(
+                                    format!("todo: expects true, false or *, not {}", value),
+                                    position,
+                                ))
+                            }
+                        }),
+                        _ => SearchFilter::Text(format!("{}:{}", key, value)),
+                    }
+                }
+            };
+            if let SearchFilter::Text(text) = &filter {
+                if fts_words(text).is_empty() {
+                    continue;
+                }
+            }
+            terms.push(SearchTerm { negated, filter });
+        }
+        Ok(SearchQuery { terms })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Rewrites `tag:old_name` terms (matched case-insensitively, prefix
+    /// patterns left alone) to `tag:new_name`. Returns `None` when the query
+    /// has no such term or does not parse.
+    pub fn rename_tag(input: &str, old_name: &str, new_name: &str) -> Option<String> {
+        let old_name = old_name.to_lowercase();
+        let replacement = if new_name.contains(char::is_whitespace) {
+            format!("tag:\"{}\"", new_name)
+        } else {
+            format!("tag:{}", new_name)
+        };
+        let mut output = String::with_capacity(input.len());
+        let mut copied = None;
+        for token in tokenize(input).ok()? {
+            if token.key.as_deref() != Some("tag") || token.value.to_lowercase() != old_name {
+                continue;
+            }
+            output.push_str(&input[copied.unwrap_or(0)..token.position]);
+            if token.negated {
+                output.push('-');
+            }
+            output.push_str(&replacement);
+            copied = Some(token.end);
+        }
+        output.push_str(&input[copied?..]);
+        Some(output)
+    }
+
+    /// Builds the SQL plan. Unquoted words also match their stems in
+    /// `language`; quoted phrases, `word*` and `intitle:` match exactly.
+    pub fn plan(&self, language: StemLanguage) -> SearchPlan {
+        let stemmer = WordStemmer::new(language);
+        let mut plan = SearchPlan::default();
+        let mut notes_terms: Vec<String> = Vec::new();
+        let mut ocr_terms: Vec<String> = Vec::new();
+        let mut title_only = false;
+        for term in &self.terms {
+            let fts = match &term.filter {
+                SearchFilter::Text(text) => Some(fts_text(text, &stemmer)),
+                SearchFilter::Phrase(text) => Some(fts_phrase(text)),
+                SearchFilter::InTitle(text) => Some(fts_title(text)),
+                _ => None,
+            };
+            if let Some(fts) = fts {
+                let Some(FtsTerm { notes, ocr }) = fts else {
+                    continue;
+                };
+                if term.negated {
+                    plan.conditions.push(
+                        "n.id NOT IN (SELECT rowid FROM notes_fts WHERE notes_fts MATCH ?)"
+                            .to_string(),
+                    );
+                    plan.binds.push(SearchBind::Text(notes));
+                    if let Some(ocr) = ocr {
+                        plan.conditions.push(
+                            "n.id NOT IN (SELECT nf.note_id FROM ocr_fts
+                               JOIN note_files nf ON nf.file_id = ocr_fts.rowid
+                               WHERE ocr_fts MATCH ?)"
+                                .to_string(),
+                        );
+                        plan.binds.push(SearchBind::Text(ocr.clone()));
+                        plan.conditions.push(
+                            "n.id NOT IN (SELECT a.note_id FROM attachment_fts
+                               JOIN attachments a ON a.id = attachment_fts.rowid
+                               WHERE attachment_fts MATCH ?)"
+                                .to_string(),
+                        );
+                        plan.binds.push(SearchBind::Text(ocr));
+                    }
+                } else {
+                    match ocr {
+                        Some(ocr) => ocr_terms.push(ocr),
+                        None => title_only = true,
+                    }
+                    notes_terms.push(notes);
+                }
+                continue;
+            }
+            let (condition, binds) = filter_condition(&term.filter);
+            plan.conditions.push(if term.negated {
+                format!("NOT ({})", condition)
+            } else {
+                condition
+            });
+            plan.binds.extend(binds);
+        }
+        if !notes_terms.is_empty() {
+            plan.notes_match = Some(notes_terms.join(" AND "));
+            if !title_only {
+                plan.ocr_match = Some(ocr_terms.join(" AND "));
+            }
+        }
+        plan
+    }
+}
+
+/// FTS5 expressions for one text term against `notes_fts` and `ocr_fts` (or
+/// `attachment_fts`); `ocr` is `None` for title-only terms.
+struct FtsTerm {
+    notes: String,
+    ocr: Option<String>,
+}
+
+const NOTES_EXACT_COLUMNS: &str = "{title plain_text}";
+
+fn fts_words(text: &str) -> Vec<String> {
+    fold_search_text(text)
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_string())
+        .collect()
+}
+fn fts_quote(word: &str) -> String {
+    format!("\"{}\"", word.replace('"', "\"\""))
+}
+/// A free-text word becomes quoted FTS tokens so punctuation can never reach
+/// the FTS5 parser; `foo-bar` matches as the phrase "foo bar". Without a
+/// trailing `*` the stemmed form is accepted too.
+fn fts_text(text: &str, stemmer: &WordStemmer) -> Option<FtsTerm> {
+    let words = fts_words(text);
+    if words.is_empty() {
+        return None;
+    }
+    let exact = fts_quote(&words.join(" "));
+    if text.ends_with('*') {
+        return Some(FtsTerm {
+            notes: format!("{} : {}*", NOTES_EXACT_COLUMNS, exact),
+            ocr: Some(format!("text : {}*", exact)),
+        });
+    }
+    let stems: Vec<String> = words.iter().map(|word| stemmer.stem(word)).collect();
+    let stemmed = fts_quote(&stems.join(" "));
+    Some(FtsTerm {
+        notes: format!(
+            "({} : {} OR stems : {})",
+            NOTES_EXACT_COLUMNS, exact, stemmed
+        ),
+        ocr: Some(format!("(text : {} OR stems : {})", exact, stemmed)),
+    })
+}
+fn fts_phrase(text: &str) -> Option<FtsTerm> {
+    let words = fts_words(text);
+    if words.is_empty() {
+        return None;
+    }
+    let exact = fts_quote(&words.join(" "));
+    Some(FtsTerm {
+        notes: format!("{} : {}", NOTES_EXACT_COLUMNS, exact),
+        ocr: Some(format!("text : {}", exact)),
+    })
+}
+fn fts_title(text: &str) -> Option<FtsTerm> {
+    let words = fts_words(text);
+    if words.is_empty() {
+        return None;
+    }
+    Some(FtsTerm {
+        notes: format!("title : {}", fts_quote(&words.join(" "))),
+        ocr: None,
+    })
+}
+fn like_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+/// Case-insensitive name match; a trailing `*` matches by prefix.
+fn name_pattern(value: &str) -> String {
+    match value.strip_suffix('*') {
+        Some(prefix) => format!("{}%", like_escape(&prefix.to_lowercase())),
+        None => like_escape(&value.to_lowercase()),
+    }
+}
+fn resource_extensions(mime: &str) -> Vec<&'static str> {
+    match mime {
+        "image" | "image/*" => vec!["png", "jpg", "jpeg", "gif", "webp", "bmp", "tif", "tiff"],
+        "image/png" => vec!["png"],
+        "image/jpeg" | "image/jpg" => vec!["jpg", "jpeg"],
+        "image/gif" => vec!["gif"],
+        "image/webp" => vec!["webp"],
+        "image/bmp" => vec!["bmp"],
+        "image/tiff" => vec!["tif", "tiff"],
+        "application/pdf" => vec!["pdf"],
+        _ => Vec::new(),
+    }
+}
+
+fn filter_condition(filter: &SearchFilter) -> (String, Vec<SearchBind>) {
+    match filter {
+        SearchFilter::Tag(name) => (
+            "EXISTS (SELECT 1 FROM note_tags nt JOIN tags t ON t.id = nt.tag_id
+               WHERE nt.note_id = n.id AND LOWER(t.name) LIKE ? ESCAPE '\\')"
+                .to_string(),
+            vec![SearchBind::Text(name_pattern(name))],
+        ),
+        // Both match notes anywhere below the named notebook or stack, so
+        // nested notebooks are covered too.
+        SearchFilter::Notebook(name) => (
+            "EXISTS (
+               WITH RECURSIVE matched(id) AS (
+                 SELECT id FROM notebooks
+                 WHERE notebook_type = 'notebook' AND LOWER(name) LIKE ? ESCAPE '\\'
+                 UNION
+                 SELECT nb.id FROM notebooks nb JOIN matched m ON nb.parent_id = m.id
+               )
+               SELECT 1 FROM matched WHERE matched.id = n.notebook_id)"
+                .to_string(),
+            vec![SearchBind::Text(name_pattern(name))],
+        ),
+        SearchFilter::Stack(name) => (
+            "EXISTS (
+               WITH RECURSIVE matched(id) AS (
+                 SELECT nb.id FROM notebooks nb JOIN notebooks st ON st.id = nb.parent_id
+                 WHERE st.notebook_type = 'stack' AND LOWER(st.name) LIKE ? ESCAPE '\\'
+                 UNION
+                 SELECT nb.id FROM notebooks nb JOIN matched m ON nb.parent_id = m.id
+               )
+               SELECT 1 FROM matched WHERE matched.id = n.notebook_id)"
+                .to_string(),
+            vec![SearchBind::Text(name_pattern(name))],
+        ),
+        SearchFilter::CreatedSince(ts) => {
+            ("n.created_at >= ?".to_string(), vec![SearchBind::Int(*ts)])
+        }
+        SearchFilter::UpdatedSince(ts) => {
+            ("n.updated_at >= ?".to_string(), vec![SearchBind::Int(*ts)])
+        }
+        SearchFilter::Resource(mime) => {
+            let pattern = match mime.strip_suffix('*') {
+                Some(prefix) => format!("{}%", like_escape(prefix)),
+                None if !mime.contains('/') => format!("{}/%", like_escape(mime)),
+                None => like_escape(mime),
+            };
+            let mut sql = "(EXISTS (SELECT 1 FROM attachments a
+               WHERE a.note_id = n.id AND LOWER(a.mime) LIKE ? ESCAPE '\\')"
+                .to_string();
+            let mut binds = vec![SearchBind::Text(pattern)];
+            let extensions = resource_extensions(mime);
+            if !extensions.is_empty() {
+                let checks = extensions
+                    .iter()
+                    .map(|_| "LOWER(f.file_path) LIKE ?")
+                    .collect::<Vec<_>>()
+                    .join(" OR ");
+                sql.push_str(&format!(
+                    " OR EXISTS (SELECT 1 FROM note_files nf JOIN ocr_files f ON f.id = nf.file_id
+                       WHERE nf.note_id = n.id AND ({}))",
+                    checks
+                ));
+                binds.extend(
+                    extensions
+                        .iter()
+                        .map(|ext| SearchBind::Text(format!("%.{}", ext))),
+                );
+            }
+            sql.push(')');
+            (sql, binds)
+        }
+        SearchFilter::Todo(todo) => {
+            let patterns: &[&str] = match todo {
+                SearchTodo::Checked => {
+                    &["%data-en-checked=\"true\"%", "%type=\"checkbox\" checked%"]
+                }
+                SearchTodo::Unchecked => &[
+                    "%data-en-checked=\"false\"%",
+                    "%type=\"checkbox\" disabled%",
+                ],
+                SearchTodo::Any => &["%data-en-todo=\"true\"%", "%type=\"checkbox\"%"],
+            };
+            (
+                "(n.content LIKE ? OR n.content LIKE ?)".to_string(),
+                patterns
+                    .iter()
+                    .map(|pattern| SearchBind::Text(pattern.to_string()))
+                    .collect(),
+            )
+        }
+        SearchFilter::Text(_) | SearchFilter::Phrase(_) | SearchFilter::InTitle(_) => {
+            unreachable!("text terms are planned as FTS expressions")
+        }
+    }
+}
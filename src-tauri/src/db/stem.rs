@@ -0,0 +1,163 @@
+use crate::db::fts::fold_search_text;
+use rust_stemmers::{Algorithm, Stemmer};
+use sqlx::sqlite::{SqliteConnection, SqlitePool};
+
+/// Stemming follows the UI language: English stems Latin words only, Russian
+/// stems Cyrillic words and still stems Latin words as English.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StemLanguage {
+    #[default]
+    English,
+    Russian,
+}
+
+impl StemLanguage {
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "ru" => StemLanguage::Russian,
+            _ => StemLanguage::English,
+        }
+    }
+    pub fn code(self) -> &'static str {
+        match self {
+            StemLanguage::English => "en",
+            StemLanguage::Russian => "ru",
+        }
+    }
+}
+
+pub struct WordStemmer {
+    language: StemLanguage,
+    english: Stemmer,
+    russian: Stemmer,
+}
+
+impl WordStemmer {
+    pub fn new(language: StemLanguage) -> Self {
+        WordStemmer {
+            language,
+            english: Stemmer::create(Algorithm::English),
+            russian: Stemmer::create(Algorithm::Russian),
+        }
+    }
+    pub fn stem(&self, word: &str) -> String {
+        let word = fold_search_text(&word.to_lowercase());
+        let cyrillic = word.chars().any(|c| matches!(c, '\u{0400}'..='\u{04ff}'));
+        if cyrillic {
+            if self.language == StemLanguage::Russian {
+                return self.russian.stem(&word).into_owned();
+            }
+            return word;
+        }
+        if word.chars().all(|c| c.is_ascii_alphabetic()) {
+            return self.english.stem(&word).into_owned();
+        }
+        word
+    }
+    /// Space-separated stems of every word in `text`, as stored in the
+    /// `stems` columns of `notes_text`, `ocr_text` and `attachment_text`.
+    pub fn stem_text(&self, text: &str) -> String {
+        text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|word| !word.is_empty())
+            .map(|word| self.stem(word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+pub async fn current_stem_language(
+    conn: &mut SqliteConnection,
+) -> Result<StemLanguage, sqlx::Error> {
+    let value: Option<(String,)> =
+        sqlx::query_as("SELECT value FROM search_meta WHERE key = 'stem_language'")
+            .fetch_optional(&mut *conn)
+            .await?;
+    Ok(value
+        .map(|(code,)| StemLanguage::from_code(&code))
+        .unwrap_or_default())
+}
+
+/// Switches the stemming language and restems every row when it changed.
+pub async fn set_stem_language(pool: &SqlitePool, language: StemLanguage) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let stored: Option<(String,)> =
+        sqlx::query_as("SELECT value FROM search_meta WHERE key = 'stem_language'")
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    if stored.as_ref().map(|(code,)| code.as_str()) == Some(language.code()) {
+        return Ok(());
+    }
+    sqlx::query(
+        "INSERT INTO search_meta (key, value) VALUES ('stem_language', ?)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(language.code())
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    for sql in [
+        "UPDATE notes_text SET stems_lang = NULL",
+        "UPDATE ocr_text SET stems_lang = NULL",
+        "UPDATE attachment_text SET stems_lang = NULL",
+    ] {
+        sqlx::query(sql)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+    refresh_stems(pool).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Fills `stems` for rows written without them (imports, migrations, a
+/// language change); searches read whatever is stored. Returns the number of rows updated.
+pub async fn refresh_stems(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let language = current_stem_language(&mut tx).await?;
+    let stemmer = WordStemmer::new(language);
+    let notes: Vec<(i64, String, String)> = sqlx::query_as(
+        "SELECT note_id, title, plain_text FROM notes_text WHERE stems_lang IS NULL",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let ocr: Vec<(i64, String)> =
+        sqlx::query_as("SELECT file_id, text FROM ocr_text WHERE stems_lang IS NULL")
+            .fetch_all(&mut *tx)
+            .await?;
+    let attachments: Vec<(i64, String)> =
+        sqlx::query_as("SELECT attachment_id, text FROM attachment_text WHERE stems_lang IS NULL")
+            .fetch_all(&mut *tx)
+            .await?;
+    let updated = (notes.len() + ocr.len() + attachments.len()) as i64;
+    if updated == 0 {
+        return Ok(0);
+    }
+    for (note_id, title, plain_text) in notes {
+        sqlx::query("UPDATE notes_text SET stems = ?, stems_lang = ? WHERE note_id = ?")
+            .bind(stemmer.stem_text(&format!("{} {}", title, plain_text)))
+            .bind(language.code())
+            .bind(note_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for (file_id, text) in ocr {
+        sqlx::query("UPDATE ocr_text SET stems = ?, stems_lang = ? WHERE file_id = ?")
+            .bind(stemmer.stem_text(&text))
+            .bind(language.code())
+            .bind(file_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for (attachment_id, text) in attachments {
+        sqlx::query("UPDATE attachment_text SET stems = ?, stems_lang = ? WHERE attachment_id = ?")
+            .bind(stemmer.stem_text(&text))
+            .bind(language.code())
+            .bind(attachment_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(updated)
+}
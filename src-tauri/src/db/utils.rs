@@ -1,25 +1,112 @@
+use html5ever::tokenizer::states::RawKind;
+use html5ever::tokenizer::{
+    BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+};
 use regex::Regex;
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 
-pub fn strip_html(input: &str) -> String {
-    let mut output = String::with_capacity(input.len());
-    let mut in_tag = false;
-    for ch in input.chars() {
-        match ch {
-            '<' => in_tag = true,
-            '>' => in_tag = false,
-            _ => {
-                if !in_tag {
-                    output.push(ch);
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "del", "dfn", "em", "font", "i", "ins", "kbd",
+    "label", "mark", "q", "s", "samp", "small", "span", "strike", "strong", "sub", "sup", "time",
+    "tt", "u", "var",
+];
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "script", "style", "head", "title", "template", "noscript", "svg", "iframe", "object",
+    "textarea", "en-crypt",
+];
+
+#[derive(Default)]
+struct TextState {
+    text: String,
+    /// Element being skipped and how many of its kind are open inside it.
+    skipping: Option<(String, usize)>,
+}
+
+#[derive(Default)]
+struct TextSink {
+    state: RefCell<TextState>,
+}
+
+impl TextSink {
+    fn tag(&self, tag: Tag) -> TokenSinkResult<()> {
+        let name: &str = &tag.name;
+        let mut state = self.state.borrow_mut();
+        if let Some((skipped, depth)) = state.skipping.as_mut() {
+            if skipped.as_str() == name && !tag.self_closing {
+                match tag.kind {
+                    TagKind::StartTag => *depth += 1,
+                    TagKind::EndTag => *depth -= 1,
+                }
+                if *depth == 0 {
+                    state.skipping = None;
+                    state.text.push(' ');
+                }
+            }
+            return TokenSinkResult::Continue;
+        }
+        if tag.kind == TagKind::StartTag {
+            let encrypted = tag
+                .attrs
+                .iter()
+                .any(|attr| &*attr.name.local == "data-secure");
+            if (SKIPPED_ELEMENTS.contains(&name) || encrypted) && !tag.self_closing {
+                state.skipping = Some((name.to_string(), 1));
+                return match name {
+                    "script" => TokenSinkResult::RawData(RawKind::ScriptData),
+                    "style" | "noscript" | "iframe" => TokenSinkResult::RawData(RawKind::Rawtext),
+                    "title" | "textarea" => TokenSinkResult::RawData(RawKind::Rcdata),
+                    _ => TokenSinkResult::Continue,
+                };
+            }
+            if name == "img" {
+                for attr in &tag.attrs {
+                    if matches!(&*attr.name.local, "alt" | "title") {
+                        state.text.push(' ');
+                        state.text.push_str(&attr.value);
+                    }
+                }
+            }
+        }
+        if !INLINE_ELEMENTS.contains(&name) {
+            state.text.push(' ');
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+impl TokenSink for TextSink {
+    type Handle = ();
+
+    fn process_token(&self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => self.tag(tag),
+            Token::CharacterTokens(text) => {
+                let mut state = self.state.borrow_mut();
+                if state.skipping.is_none() {
+                    state.text.push_str(&text);
                 }
+                TokenSinkResult::Continue
             }
+            _ => TokenSinkResult::Continue,
         }
     }
-    output
-        .replace('\u{00a0}', " ")
-        .split_whitespace()
-        .collect::<Vec<_>>()
-        .join(" ")
+}
+
+/// Plain text of note HTML for the search index and revision diffs:
+/// entities decoded, block elements separated by spaces, image `alt`/`title`
+/// kept, and scripts, styles and encrypted blocks left out.
+pub fn strip_html(input: &str) -> String {
+    if input.is_empty() {
+        return String::new();
+    }
+    let tokenizer = Tokenizer::new(TextSink::default(), TokenizerOpts::default());
+    let queue = BufferQueue::default();
+    queue.push_back(input.into());
+    let _ = tokenizer.feed(&queue);
+    tokenizer.end();
+    let text = tokenizer.sink.state.borrow();
+    text.text.split_whitespace().collect::<Vec<_>>().join(" ")
 }
 
 pub fn extract_note_files(content: &str) -> Vec<String> {
@@ -85,6 +172,44 @@ pub fn extract_note_files(content: &str) -> Vec<String> {
     results
 }
 
+/// Points `src` attributes at new files, in every form `extract_note_files`
+/// reads: plain, `notes-file://` and asset URLs, with the path as written or
+/// percent-encoded. `paths` maps paths under `files/` to their replacements;
+/// other paths are left alone.
+pub fn replace_note_file_paths(content: &str, paths: &HashMap<String, String>) -> String {
+    let rewrite = |caps: &regex::Captures| {
+        let prefix = caps.get(2).map_or("", |prefix| prefix.as_str());
+        let marker = &caps[3];
+        let encoded = !marker.eq_ignore_ascii_case("files/");
+        let raw = &caps[4];
+        let decoded = urlencoding::decode(raw)
+            .map(|path| path.into_owned())
+            .unwrap_or_else(|_| raw.to_string());
+        let Some(new_path) = paths.get(raw).or_else(|| paths.get(&decoded)) else {
+            return caps[0].to_string();
+        };
+        let new_path = if encoded {
+            urlencoding::encode(new_path).into_owned()
+        } else {
+            new_path.clone()
+        };
+        format!(
+            "src={}{}{}{}{}",
+            &caps[1], prefix, marker, new_path, &caps[1]
+        )
+    };
+    let re_double = Regex::new(
+        r#"src=(")(notes-file://|[^"]*asset\.localhost[^"]*)?(files/|(?i:files%2F))([^"]*)""#,
+    )
+    .unwrap();
+    let content = re_double.replace_all(content, rewrite);
+    let re_single = Regex::new(
+        r#"src=(')(notes-file://|[^']*asset\.localhost[^']*)?(files/|(?i:files%2F))([^']*)'"#,
+    )
+    .unwrap();
+    re_single.replace_all(&content, rewrite).into_owned()
+}
+
 pub const OCR_IMAGE_FILTER: &str = "(
     lower(f.file_path) LIKE '%.png' OR
     lower(f.file_path) LIKE '%.jpg' OR
@@ -98,6 +223,37 @@ pub const OCR_IMAGE_FILTER: &str = "(
     lower(a.mime) LIKE 'image/%'
 )";
 
+/// Attachments whose text can be extracted for search; keep in sync with
+/// `DocumentKind` in `services::attachment_text`.
+pub const DOCUMENT_TEXT_FILTER: &str = "(
+    lower(a.filename) LIKE '%.pdf' OR
+    lower(a.filename) LIKE '%.docx' OR
+    lower(a.filename) LIKE '%.xlsx' OR
+    lower(a.filename) LIKE '%.pptx' OR
+    lower(a.filename) LIKE '%.odt' OR
+    lower(a.filename) LIKE '%.ods' OR
+    lower(a.filename) LIKE '%.odp' OR
+    lower(a.filename) LIKE '%.txt' OR
+    lower(a.filename) LIKE '%.md' OR
+    lower(a.filename) LIKE '%.csv' OR
+    lower(a.filename) LIKE '%.tsv' OR
+    lower(a.filename) LIKE '%.log' OR
+    lower(a.filename) LIKE '%.json' OR
+    lower(a.filename) LIKE '%.xml' OR
+    lower(a.filename) LIKE '%.html' OR
+    lower(a.filename) LIKE '%.htm' OR
+    lower(a.mime) = 'application/pdf' OR
+    lower(a.mime) IN (
+        'application/vnd.openxmlformats-officedocument.wordprocessingml.document',
+        'application/vnd.openxmlformats-officedocument.spreadsheetml.sheet',
+        'application/vnd.openxmlformats-officedocument.presentationml.presentation',
+        'application/vnd.oasis.opendocument.text',
+        'application/vnd.oasis.opendocument.spreadsheet',
+        'application/vnd.oasis.opendocument.presentation'
+    ) OR
+    lower(a.mime) LIKE 'text/%'
+)";
+
 pub fn extract_attachment_ids(content: &str) -> HashSet<i64> {
     let mut results = HashSet::new();
     let re_double = Regex::new(r#"data-attachment-id="(\d+)""#).unwrap();
@@ -118,3 +274,16 @@ pub fn extract_attachment_ids(content: &str) -> HashSet<i64> {
     }
     results
 }
+
+/// Points `data-attachment-id` attributes at new attachment ids; ids missing
+/// from `ids` are left alone.
+pub fn replace_attachment_ids(content: &str, ids: &HashMap<i64, i64>) -> String {
+    let re = Regex::new(r#"data-attachment-id=(["'])(\d+)["']"#).unwrap();
+    re.replace_all(content, |caps: &regex::Captures| {
+        match caps[2].parse::<i64>().ok().and_then(|id| ids.get(&id)) {
+            Some(new_id) => format!("data-attachment-id={}{}{}", &caps[1], new_id, &caps[1]),
+            None => caps[0].to_string(),
+        }
+    })
+    .into_owned()
+}
@@ -0,0 +1,436 @@
+use super::*;
+use crate::services::prelude::*;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+pub const DEFAULT_API_PORT: u16 = 37420;
+const CORS_ALLOW_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+const CORS_ALLOW_HEADERS: &str = "Authorization, Content-Type, X-Api-Token";
+
+#[derive(Clone)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub port: u16,
+    pub token: String,
+}
+
+struct ApiContext {
+    pool: SqlitePool,
+    settings_dir: PathBuf,
+    data_dir: PathBuf,
+    token: String,
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+    fn not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "Not found")
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    }
+}
+
+impl From<SearchError> for ApiError {
+    fn from(err: SearchError) -> Self {
+        match err {
+            SearchError::Query(_) => Self::bad_request(err.to_string()),
+            SearchError::Database { message } => {
+                Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+            }
+        }
+    }
+}
+
+type ApiResult = Result<(StatusCode, Value), ApiError>;
+
+fn generate_api_token() -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn read_api_config(settings_dir: &Path) -> Result<ApiConfig, String> {
+    let mut settings = read_settings_file(settings_dir)?;
+    if !settings.is_object() {
+        settings = Value::Object(serde_json::Map::new());
+    }
+    let enabled = settings
+        .get("apiEnabled")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let port = settings
+        .get("apiPort")
+        .and_then(|v| v.as_u64())
+        .and_then(|v| u16::try_from(v).ok())
+        .filter(|v| *v != 0)
+        .unwrap_or(DEFAULT_API_PORT);
+    let mut token = settings
+        .get("apiToken")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    if enabled && token.is_empty() {
+        token = generate_api_token()?;
+        if let Some(obj) = settings.as_object_mut() {
+            obj.insert("apiToken".to_string(), Value::String(token.clone()));
+        }
+        fs::create_dir_all(settings_dir).map_err(|e| e.to_string())?;
+        let data = serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())?;
+        fs::write(settings_dir.join(SETTINGS_FILE_NAME), data).map_err(|e| e.to_string())?;
+    }
+    Ok(ApiConfig {
+        enabled,
+        port,
+        token,
+    })
+}
+
+pub fn start_api_server(pool: SqlitePool, settings_dir: PathBuf, data_dir: PathBuf) {
+    let config = match read_api_config(&settings_dir) {
+        Ok(config) if config.enabled => config,
+        _ => return,
+    };
+    tauri::async_runtime::spawn(async move {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, config.port));
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("local API could not listen on {}: {}", addr, err);
+                return;
+            }
+        };
+        serve_api(listener, pool, settings_dir, data_dir, config.token).await;
+    });
+}
+
+/// Answers API requests on `listener`, authorized by `token`, for as long as
+/// the task runs.
+pub async fn serve_api(
+    listener: tokio::net::TcpListener,
+    pool: SqlitePool,
+    settings_dir: PathBuf,
+    data_dir: PathBuf,
+    token: String,
+) {
+    let ctx = Arc::new(ApiContext {
+        pool,
+        settings_dir,
+        data_dir,
+        token,
+    });
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle_api_request(ctx.clone(), req));
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap_or_else(|_| Response::new(Full::new(Bytes::new())))
+}
+
+/// Browsers preflight cross-origin calls without the token, so clippers
+/// running in a page can only reach the API once this is answered.
+fn preflight_response() -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", CORS_ALLOW_METHODS)
+        .header("Access-Control-Allow-Headers", CORS_ALLOW_HEADERS)
+        .header("Access-Control-Max-Age", "600")
+        .body(Full::new(Bytes::new()))
+        .unwrap_or_else(|_| Response::new(Full::new(Bytes::new())))
+}
+
+fn is_authorized(ctx: &ApiContext, req: &Request<Incoming>) -> bool {
+    let headers = req.headers();
+    let bearer = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim());
+    let header = headers
+        .get("x-api-token")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim());
+    let provided = bearer.or(header).unwrap_or("");
+    !ctx.token.is_empty()
+        && provided.len() == ctx.token.len()
+        && provided
+            .bytes()
+            .zip(ctx.token.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn parse_query(uri: &Uri) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    for pair in uri.query().unwrap_or("").split('&') {
+        if pair.is_empty() {
+            continue;
+        }
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let decode = |part: &str| {
+            urlencoding::decode(&part.replace('+', " "))
+                .map(|v| v.into_owned())
+                .unwrap_or_default()
+        };
+        params.insert(decode(key), decode(value));
+    }
+    params
+}
+
+fn query_i64(params: &HashMap<String, String>, key: &str) -> Result<Option<i64>, ApiError> {
+    match params.get(key).map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        Some(value) => value
+            .parse::<i64>()
+            .map(Some)
+            .map_err(|_| ApiError::bad_request(format!("Invalid {}", key))),
+        None => Ok(None),
+    }
+}
+
+fn parse_json_body(body: &[u8]) -> Result<Value, ApiError> {
+    if body.is_empty() {
+        return Ok(Value::Object(serde_json::Map::new()));
+    }
+    serde_json::from_slice(body).map_err(|e| ApiError::bad_request(e.to_string()))
+}
+
+fn to_json<T: serde::Serialize>(value: T) -> Result<Value, ApiError> {
+    serde_json::to_value(value)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn handle_api_request(
+    ctx: Arc<ApiContext>,
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if req.method() == http::Method::OPTIONS {
+        return Ok(preflight_response());
+    }
+    if !is_authorized(&ctx, &req) {
+        return Ok(json_response(
+            StatusCode::UNAUTHORIZED,
+            &serde_json::json!({ "error": "Unauthorized" }),
+        ));
+    }
+    let method = req.method().clone();
+    let params = parse_query(req.uri());
+    let segments: Vec<String> = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect();
+    let body = match Limited::new(req.into_body(), MAX_NOTE_FILE_BYTES)
+        .collect()
+        .await
+    {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => {
+            return Ok(json_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &serde_json::json!({ "error": "Request body too large" }),
+            ));
+        }
+    };
+    let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+    let result = route_api_request(&ctx, &method, &segments, &params, &body).await;
+    Ok(match result {
+        Ok((status, value)) => json_response(status, &value),
+        Err(err) => json_response(err.status, &serde_json::json!({ "error": err.message })),
+    })
+}
+
+async fn route_api_request(
+    ctx: &ApiContext,
+    method: &http::Method,
+    segments: &[&str],
+    params: &HashMap<String, String>,
+    body: &[u8],
+) -> ApiResult {
+    let repo = SqliteRepository {
+        pool: ctx.pool.clone(),
+    };
+    let note_id = |raw: &str| {
+        raw.parse::<i64>()
+            .map_err(|_| ApiError::bad_request("Invalid note id"))
+    };
+    match (method.as_str(), segments) {
+        ("GET", ["api", "notes"]) => {
+            let notes = match params.get("tagIds").filter(|v| !v.trim().is_empty()) {
+                Some(raw) => {
+                    let tag_ids = raw
+                        .split(',')
+                        .map(|v| v.trim().parse::<i64>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| ApiError::bad_request("Invalid tagIds"))?;
+                    let match_all = params.get("matchAll").map(|v| v.as_str()) == Some("true");
+                    repo.get_notes_by_tags(&tag_ids, match_all).await?
+                }
+                None => repo.get_all_notes(query_i64(params, "notebookId")?).await?,
+            };
+            Ok((StatusCode::OK, to_json(notes)?))
+        }
+        ("POST", ["api", "notes"]) => create_api_note(ctx, &repo, body).await,
+        ("GET", ["api", "notes", id]) => match repo.get_note(note_id(id)?).await? {
+            Some(note) => Ok((StatusCode::OK, to_json(note)?)),
+            None => Err(ApiError::not_found()),
+        },
+        ("PUT", ["api", "notes", id]) => update_api_note(ctx, &repo, note_id(id)?, body).await,
+        ("DELETE", ["api", "notes", id]) => {
+            let id = note_id(id)?;
+            if repo.get_note(id).await?.is_none() {
+                return Err(ApiError::not_found());
+            }
+            if params
+                .get("permanent")
+                .map(|v| v == "true")
+                .unwrap_or(false)
+            {
+                repo.delete_note(id, &ctx.data_dir).await?;
+            } else {
+                repo.trash_note(id).await?;
+            }
+            Ok((StatusCode::OK, serde_json::json!({ "id": id })))
+        }
+        ("GET", ["api", "notes", id, "tags"]) => {
+            let tags = repo.get_note_tags(note_id(id)?).await?;
+            Ok((StatusCode::OK, to_json(tags)?))
+        }
+        ("GET", ["api", "search"]) => {
+            let query = params.get("q").map(|v| v.trim()).unwrap_or("");
+            if query.is_empty() {
+                return Err(ApiError::bad_request("Missing q"));
+            }
+            let sort = params
+                .get("sort")
+                .map(|value| value.parse::<SearchSort>())
+                .transpose()
+                .map_err(ApiError::bad_request)?
+                .unwrap_or_default();
+            let notes = repo
+                .search_notes(query, query_i64(params, "notebookId")?, sort)
+                .await?;
+            Ok((StatusCode::OK, to_json(notes)?))
+        }
+        ("GET", ["api", "notebooks"]) => {
+            Ok((StatusCode::OK, to_json(repo.get_notebooks().await?)?))
+        }
+        ("GET", ["api", "tags"]) => Ok((StatusCode::OK, to_json(repo.get_tags().await?)?)),
+        ("POST", ["api", "files"]) => {
+            let filename = params
+                .get("filename")
+                .map(|v| v.as_str())
+                .unwrap_or("upload");
+            let mime = params.get("mime").map(|v| v.as_str()).unwrap_or("");
+            let stored = store_note_bytes(
+                &ctx.data_dir,
+                filename,
+                mime,
+                body,
+                content_addressed_files(&ctx.settings_dir),
+            )
+            .map_err(ApiError::bad_request)?;
+            let src = format!("files/{}", stored.rel_path);
+            let mut value = to_json(stored)?;
+            if let Some(obj) = value.as_object_mut() {
+                obj.insert("src".to_string(), Value::String(src));
+            }
+            Ok((StatusCode::CREATED, value))
+        }
+        _ => Err(ApiError::not_found()),
+    }
+}
+
+fn body_notebook_id(payload: &Value) -> Result<Option<Option<i64>>, ApiError> {
+    match payload.get("notebookId") {
+        None => Ok(None),
+        Some(Value::Null) => Ok(Some(None)),
+        Some(value) => value
+            .as_i64()
+            .map(|id| Some(Some(id)))
+            .ok_or_else(|| ApiError::bad_request("Invalid notebookId")),
+    }
+}
+
+async fn create_api_note(ctx: &ApiContext, repo: &SqliteRepository, body: &[u8]) -> ApiResult {
+    let payload = parse_json_body(body)?;
+    let title = payload
+        .get("title")
+        .and_then(|v| v.as_str())
+        .unwrap_or("Untitled");
+    let content = payload
+        .get("content")
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+    let notebook_id = body_notebook_id(&payload)?.flatten();
+    let id = repo
+        .create_note(title, content, notebook_id, &ctx.data_dir)
+        .await?;
+    Ok((StatusCode::CREATED, serde_json::json!({ "id": id })))
+}
+
+async fn update_api_note(
+    ctx: &ApiContext,
+    repo: &SqliteRepository,
+    id: i64,
+    body: &[u8],
+) -> ApiResult {
+    let payload = parse_json_body(body)?;
+    let Some(note) = repo.get_note(id).await? else {
+        return Err(ApiError::not_found());
+    };
+    let title = payload
+        .get("title")
+        .and_then(|v| v.as_str())
+        .unwrap_or(&note.title);
+    let content = payload
+        .get("content")
+        .and_then(|v| v.as_str())
+        .unwrap_or(&note.content);
+    let notebook_id = body_notebook_id(&payload)?.unwrap_or(note.notebook_id);
+    let revisions = read_revision_policy(&ctx.settings_dir);
+    repo.update_note(id, title, content, notebook_id, &ctx.data_dir, &revisions)
+        .await?;
+    Ok((StatusCode::OK, serde_json::json!({ "id": id })))
+}
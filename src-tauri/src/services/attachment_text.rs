@@ -0,0 +1,249 @@
+use super::*;
+use crate::services::prelude::*;
+use quick_xml::events::Event;
+
+/// Larger files are recorded as failed instead of being read into memory.
+const MAX_ATTACHMENT_TEXT_BYTES: u64 = 64 * 1024 * 1024;
+/// Extracted text is capped so one huge log or table cannot dominate the index.
+const MAX_ATTACHMENT_TEXT_CHARS: usize = 1_000_000;
+const ATTACHMENT_TEXT_BATCH: i64 = 10;
+const ATTACHMENT_TEXT_IDLE_SECS: u64 = 30;
+
+/// Elements that end a word in OOXML and OpenDocument XML: paragraphs, line
+/// breaks, tabs, table cells and spreadsheet strings.
+const XML_BREAK_ELEMENTS: &[&[u8]] = &[
+    b"p",
+    b"h",
+    b"br",
+    b"cr",
+    b"tab",
+    b"s",
+    b"line-break",
+    b"si",
+    b"c",
+    b"tc",
+    b"table-cell",
+];
+
+/// Document types with text extraction; keep in sync with
+/// `DOCUMENT_TEXT_FILTER` in `db::utils`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DocumentKind {
+    Pdf,
+    Docx,
+    Xlsx,
+    Pptx,
+    OpenDocument,
+    Html,
+    PlainText,
+}
+
+impl DocumentKind {
+    fn detect(filename: &str, mime: &str) -> Option<Self> {
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|value| value.to_str())
+            .map(|value| value.to_lowercase())
+            .unwrap_or_default();
+        let kind = match extension.as_str() {
+            "pdf" => Some(DocumentKind::Pdf),
+            "docx" => Some(DocumentKind::Docx),
+            "xlsx" => Some(DocumentKind::Xlsx),
+            "pptx" => Some(DocumentKind::Pptx),
+            "odt" | "ods" | "odp" => Some(DocumentKind::OpenDocument),
+            "html" | "htm" => Some(DocumentKind::Html),
+            "txt" | "md" | "csv" | "tsv" | "log" | "json" | "xml" => Some(DocumentKind::PlainText),
+            _ => None,
+        };
+        if kind.is_some() {
+            return kind;
+        }
+        match mime.to_lowercase().as_str() {
+            "application/pdf" => Some(DocumentKind::Pdf),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+                Some(DocumentKind::Docx)
+            }
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => {
+                Some(DocumentKind::Xlsx)
+            }
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation" => {
+                Some(DocumentKind::Pptx)
+            }
+            "application/vnd.oasis.opendocument.text"
+            | "application/vnd.oasis.opendocument.spreadsheet"
+            | "application/vnd.oasis.opendocument.presentation" => Some(DocumentKind::OpenDocument),
+            "text/html" => Some(DocumentKind::Html),
+            other if other.starts_with("text/") => Some(DocumentKind::PlainText),
+            _ => None,
+        }
+    }
+}
+
+/// Appends the character data of `xml` to `out`. With `text_elements` set,
+/// only text inside those elements counts (`w:t`, `a:t`); OpenDocument keeps
+/// all text.
+fn xml_text(xml: &str, text_elements: &[&[u8]], out: &mut String) -> Result<(), String> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut inside = 0usize;
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(tag) => {
+                let name = tag.local_name();
+                if text_elements.contains(&name.as_ref()) {
+                    inside += 1;
+                }
+                if XML_BREAK_ELEMENTS.contains(&name.as_ref()) {
+                    out.push(' ');
+                }
+            }
+            Event::End(tag) => {
+                let name = tag.local_name();
+                if text_elements.contains(&name.as_ref()) {
+                    inside = inside.saturating_sub(1);
+                }
+                if XML_BREAK_ELEMENTS.contains(&name.as_ref()) {
+                    out.push(' ');
+                }
+            }
+            Event::Empty(tag) if XML_BREAK_ELEMENTS.contains(&tag.local_name().as_ref()) => {
+                out.push(' ');
+            }
+            Event::Text(text) if text_elements.is_empty() || inside > 0 => {
+                out.push_str(&text.unescape().map_err(|e| e.to_string())?);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Text of the XML parts of a zipped office document whose names match
+/// `parts`, in part order (`slide2.xml` before `slide10.xml`).
+fn zipped_xml_text(bytes: &[u8], parts: &str, text_elements: &[&[u8]]) -> Result<String, String> {
+    let parts = Regex::new(parts).map_err(|e| e.to_string())?;
+    let mut archive =
+        zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| parts.is_match(name))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    let mut text = String::new();
+    for name in names {
+        let part = archive.by_name(&name).map_err(|e| e.to_string())?;
+        let mut xml = String::new();
+        part.take(MAX_ATTACHMENT_TEXT_BYTES)
+            .read_to_string(&mut xml)
+            .map_err(|e| e.to_string())?;
+        xml_text(&xml, text_elements, &mut text)?;
+        text.push(' ');
+    }
+    Ok(text)
+}
+
+/// Searchable text of an attached document, with whitespace collapsed.
+/// `filename` and `mime` pick the format; unsupported types are an error.
+pub fn extract_document_text(bytes: &[u8], filename: &str, mime: &str) -> Result<String, String> {
+    let kind = DocumentKind::detect(filename, mime)
+        .ok_or_else(|| "Unsupported document type".to_string())?;
+    let text = match kind {
+        DocumentKind::Pdf => {
+            pdf_extract::extract_text_from_mem(bytes).map_err(|e| e.to_string())?
+        }
+        DocumentKind::Docx => zipped_xml_text(
+            bytes,
+            r"^word/(document|footnotes|endnotes|header\d*|footer\d*)\.xml$",
+            &[b"t"],
+        )?,
+        DocumentKind::Xlsx => zipped_xml_text(
+            bytes,
+            r"^xl/(sharedStrings|worksheets/sheet\d+)\.xml$",
+            &[b"t"],
+        )?,
+        DocumentKind::Pptx => zipped_xml_text(
+            bytes,
+            r"^ppt/(slides/slide|notesSlides/notesSlide)\d+\.xml$",
+            &[b"t"],
+        )?,
+        DocumentKind::OpenDocument => zipped_xml_text(bytes, r"^content\.xml$", &[])?,
+        DocumentKind::Html => strip_html(&String::from_utf8_lossy(bytes)),
+        DocumentKind::PlainText => String::from_utf8_lossy(bytes).into_owned(),
+    };
+    let mut text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if let Some((cut, _)) = text.char_indices().nth(MAX_ATTACHMENT_TEXT_CHARS) {
+        text.truncate(cut);
+    }
+    Ok(text)
+}
+
+fn read_document_text(path: &Path, filename: &str, mime: &str) -> Result<String, String> {
+    let size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    if size > MAX_ATTACHMENT_TEXT_BYTES {
+        return Err("File too large to index".to_string());
+    }
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    extract_document_text(&bytes, filename, mime)
+}
+
+/// Extracts text from up to `limit` attachments that have none yet. Failures
+/// are stored with the error so the file is not retried until it changes.
+/// Returns the number of attachments processed.
+pub async fn index_attachment_texts(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    limit: i64,
+) -> Result<usize, String> {
+    let repo = SqliteRepository { pool: pool.clone() };
+    let pending = repo
+        .get_attachment_text_pending(limit)
+        .await
+        .map_err(|e| e.to_string())?;
+    for attachment in &pending {
+        let path = data_dir.join(&attachment.local_path);
+        let filename = attachment.filename.clone();
+        let mime = attachment.mime.clone();
+        // PDF parsing can panic on malformed files; the join error stands in.
+        let extracted =
+            tokio::task::spawn_blocking(move || read_document_text(&path, &filename, &mime))
+                .await
+                .unwrap_or_else(|_| Err("Text extraction failed".to_string()));
+        let (text, error) = match extracted {
+            Ok(text) => (text, None),
+            Err(err) => (String::new(), Some(err)),
+        };
+        repo.upsert_attachment_text(attachment.id, &text, error.as_deref())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(pending.len())
+}
+
+/// Keeps attachment text up to date in the background: works through pending
+/// attachments in batches, then checks again after a pause.
+pub fn start_attachment_text_indexer(pool: SqlitePool, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match index_attachment_texts(&pool, &data_dir, ATTACHMENT_TEXT_BATCH).await {
+                Ok(count) if count > 0 => continue,
+                _ => {
+                    tokio::time::sleep(std::time::Duration::from_secs(ATTACHMENT_TEXT_IDLE_SECS))
+                        .await
+                }
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_attachment_text_stats(
+    state: State<'_, AppState>,
+) -> Result<AttachmentTextStats, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.get_attachment_text_stats()
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -1,5 +1,6 @@
 use super::*;
 use crate::services::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 pub fn normalize_export_html(html: &str) -> String {
     if html.is_empty() {
@@ -77,9 +78,26 @@ pub struct ExportNote {
     pub content_size: Option<i64>,
     pub deleted_at: Option<i64>,
     pub deleted_from_notebook_id: Option<i64>,
+    /// Trashed notebook whose deletion took the note along.
+    #[serde(default)]
+    pub deleted_with_notebook_id: Option<i64>,
     pub content_path: String,
     pub meta_path: String,
 }
+/// A notebook in the trash; `trash_root_id` is the notebook or stack the
+/// user deleted, which restores the whole group.
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct ExportTrashedNotebook {
+    pub id: i64,
+    pub name: String,
+    pub created_at: i64,
+    pub parent_id: Option<i64>,
+    pub notebook_type: String,
+    pub sort_order: i64,
+    pub external_id: Option<String>,
+    pub deleted_at: i64,
+    pub trash_root_id: i64,
+}
 #[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
 pub struct ExportNoteText {
     pub note_id: i64,
@@ -150,6 +168,48 @@ pub struct ExportHistory {
     pub stack_id: Option<i64>,
     pub stack_name: Option<String>,
 }
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct ExportSavedSearch {
+    pub id: i64,
+    pub name: String,
+    pub query: String,
+    pub notebook_id: Option<i64>,
+    pub sort: SearchSort,
+    pub sort_order: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct ExportNoteTemplate {
+    pub id: i64,
+    pub name: String,
+    pub title: String,
+    pub content: String,
+    pub notebook_id: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct ExportNoteTemplateTag {
+    pub template_id: i64,
+    pub tag_id: i64,
+}
+/// Change-detection fingerprint of a note, recorded for every note so the
+/// package can serve as the base of a later incremental export.
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct ExportNoteState {
+    pub id: i64,
+    pub updated_at: i64,
+    pub content_hash: Option<String>,
+    pub notebook_id: Option<i64>,
+    pub deleted_at: Option<i64>,
+}
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct ExportFileState {
+    pub size: u64,
+    pub modified: i64,
+    pub hash: String,
+}
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct ExportManifest {
     pub version: String,
@@ -164,6 +224,34 @@ pub struct ExportManifest {
     pub note_files: Vec<ExportNoteFile>,
     pub ocr_text: Vec<ExportOcrText>,
     pub note_history: Vec<ExportHistory>,
+    #[serde(default)]
+    pub saved_searches: Vec<ExportSavedSearch>,
+    #[serde(default)]
+    pub note_templates: Vec<ExportNoteTemplate>,
+    #[serde(default)]
+    pub note_template_tags: Vec<ExportNoteTemplateTag>,
+    #[serde(default)]
+    pub trashed_notebooks: Vec<ExportTrashedNotebook>,
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+    #[serde(default)]
+    pub package_id: Option<String>,
+    /// Set on incremental packages: the `package_id` they were diffed against.
+    #[serde(default)]
+    pub base_package_id: Option<String>,
+    #[serde(default)]
+    pub note_states: Vec<ExportNoteState>,
+    #[serde(default)]
+    pub deleted_notes: Vec<i64>,
+    /// Every file of the exported library keyed by package path, including
+    /// files an incremental package leaves to its base.
+    #[serde(default)]
+    pub files: BTreeMap<String, ExportFileState>,
+}
+impl ExportManifest {
+    pub fn checksum(&self, rel: &str) -> Option<&str> {
+        self.checksums.get(rel).map(|hash| hash.as_str())
+    }
 }
 #[derive(serde::Serialize)]
 pub struct ExportReport {
@@ -174,92 +262,229 @@ pub struct ExportReport {
     pub tags: i64,
     pub attachments: i64,
     pub images: i64,
+    pub incremental: bool,
+    pub deleted_notes: i64,
     pub errors: Vec<String>,
 }
+#[derive(serde::Serialize, Clone)]
+pub struct PackageProgress {
+    pub stage: String,
+    pub current: i64,
+    pub total: i64,
+    pub state: String,
+    pub message: Option<String>,
+}
+/// Receives progress events from package export and import so the same code
+/// can drive Tauri events, CLI output or nothing at all.
+pub trait ProgressSink: Sync {
+    fn report(&self, event: PackageProgress);
+}
+impl<F> ProgressSink for F
+where
+    F: Fn(PackageProgress) + Sync,
+{
+    fn report(&self, event: PackageProgress) {
+        self(event)
+    }
+}
+#[derive(Default)]
+pub struct ExportOptions {
+    /// Write a single `.ncx` zip archive instead of a folder tree.
+    pub archive: bool,
+    /// Previous package (manifest, folder or archive) to diff against; only
+    /// changed notes and files are written when set.
+    pub base: Option<PathBuf>,
+}
+fn file_stamp(path: &Path) -> Option<(u64, i64)> {
+    let meta = fs::metadata(path).ok()?;
+    let modified = meta
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_millis() as i64;
+    Some((meta.len(), modified))
+}
+/// Copies `source` into the package unless the base package already holds an
+/// identical file (same size and mtime) under `rel`.
+fn export_package_file(
+    writer: &mut PackageWriter,
+    source: &Path,
+    rel: &str,
+    base: Option<&ExportManifest>,
+    checksums: &mut BTreeMap<String, String>,
+    files: &mut BTreeMap<String, ExportFileState>,
+) -> Result<(), String> {
+    if files.contains_key(rel) {
+        return Ok(());
+    }
+    let stamp = file_stamp(source);
+    let unchanged = base
+        .and_then(|base| base.files.get(rel))
+        .filter(|prev| stamp == Some((prev.size, prev.modified)));
+    if let Some(prev) = unchanged {
+        files.insert(rel.to_string(), prev.clone());
+        return Ok(());
+    }
+    let hash = writer.copy_file(rel, source)?;
+    let (size, modified) = stamp.unwrap_or((0, 0));
+    checksums.insert(rel.to_string(), hash.clone());
+    files.insert(
+        rel.to_string(),
+        ExportFileState {
+            size,
+            modified,
+            hash,
+        },
+    );
+    Ok(())
+}
 #[tauri::command]
 pub async fn export_notes_classic(
     dest_dir: String,
+    archive: Option<bool>,
+    base_path: Option<String>,
+    app_handle: AppHandle,
     state: State<'_, AppState>,
 ) -> Result<ExportReport, String> {
     if dest_dir.trim().is_empty() {
         return Err("Export folder is empty".to_string());
     }
+    export_notes_classic_package(
+        &state.pool,
+        &state.data_dir,
+        Path::new(&dest_dir),
+        &ExportOptions {
+            archive: archive.unwrap_or(false),
+            base: base_path
+                .filter(|path| !path.trim().is_empty())
+                .map(PathBuf::from),
+        },
+        &|event: PackageProgress| {
+            let _ = app_handle.emit("export-notes-classic-progress", event);
+        },
+    )
+    .await
+}
+pub async fn export_notes_classic_package(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    dest_dir: &Path,
+    options: &ExportOptions,
+    progress: &dyn ProgressSink,
+) -> Result<ExportReport, String> {
     let now = chrono::Utc::now();
     let stamp = now.format("%Y%m%d-%H%M%S").to_string();
-    let export_root = PathBuf::from(dest_dir).join(format!("notes-classic-export-{}", stamp));
-    fs::create_dir_all(&export_root).map_err(|e| e.to_string())?;
-    let notes_dir = export_root.join("notes");
-    let attachments_dir = export_root.join("attachments");
-    let files_dir = export_root.join("files");
-    fs::create_dir_all(&notes_dir).map_err(|e| e.to_string())?;
-    fs::create_dir_all(&attachments_dir).map_err(|e| e.to_string())?;
-    fs::create_dir_all(&files_dir).map_err(|e| e.to_string())?;
-
-    let pool = state.pool.clone();
-    let data_dir = state.data_dir.clone();
-    let mut errors: Vec<String> = Vec::new();
+    let base = match options.base.as_deref() {
+        Some(path) => {
+            let manifest = PackageReader::open(path)?.read_manifest()?;
+            if manifest.package_id.is_none() {
+                return Err(
+                    "Base package has no change index; create a new full export first".to_string(),
+                );
+            }
+            Some(manifest)
+        }
+        None => None,
+    };
+    let base_notes: HashMap<i64, &ExportNoteState> = base
+        .iter()
+        .flat_map(|base| base.note_states.iter())
+        .map(|state| (state.id, state))
+        .collect();
 
     let notebooks: Vec<ExportNotebook> = sqlx::query_as(
         "SELECT id, name, created_at, parent_id, notebook_type, sort_order, external_id FROM notebooks ORDER BY id ASC",
     )
-    .fetch_all(&pool)
+    .fetch_all(pool)
     .await
     .map_err(|e| e.to_string())?;
 
-    let notes_rows: Vec<(i64, String, String, i64, i64, Option<i64>, Option<String>, Option<i64>, Option<String>, Option<String>, Option<String>, Option<i64>, Option<i64>, Option<i64>)> =
+    let trashed_notebooks: Vec<ExportTrashedNotebook> = sqlx::query_as(
+        "SELECT id, name, created_at, parent_id, notebook_type, sort_order, external_id, deleted_at, trash_root_id
+         FROM trashed_notebooks ORDER BY id ASC",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let notes_rows: Vec<(i64, String, String, i64, i64, Option<i64>, Option<String>, Option<i64>, Option<String>, Option<String>, Option<String>, Option<i64>, Option<i64>, Option<i64>, Option<i64>)> =
         sqlx::query_as(
-            "SELECT id, title, content, created_at, updated_at, sync_status, remote_id, notebook_id, external_id, meta, content_hash, content_size, deleted_at, deleted_from_notebook_id
+            "SELECT id, title, content, created_at, updated_at, sync_status, remote_id, notebook_id, external_id, meta, content_hash, content_size, deleted_at, deleted_from_notebook_id, deleted_with_notebook_id
              FROM notes ORDER BY id ASC",
         )
-        .fetch_all(&pool)
+        .fetch_all(pool)
         .await
         .map_err(|e| e.to_string())?;
 
     let notes_text: Vec<ExportNoteText> =
         sqlx::query_as("SELECT note_id, title, plain_text FROM notes_text ORDER BY note_id ASC")
-            .fetch_all(&pool)
+            .fetch_all(pool)
             .await
             .map_err(|e| e.to_string())?;
 
     let tags: Vec<ExportTag> = sqlx::query_as(
         "SELECT id, name, parent_id, created_at, updated_at, external_id FROM tags ORDER BY id ASC",
     )
-    .fetch_all(&pool)
+    .fetch_all(pool)
     .await
     .map_err(|e| e.to_string())?;
 
     let note_tags: Vec<ExportNoteTag> =
         sqlx::query_as("SELECT note_id, tag_id FROM note_tags ORDER BY note_id ASC, tag_id ASC")
-            .fetch_all(&pool)
+            .fetch_all(pool)
             .await
             .map_err(|e| e.to_string())?;
 
+    let saved_searches: Vec<ExportSavedSearch> = sqlx::query_as(
+        "SELECT id, name, query, notebook_id, sort, sort_order, created_at, updated_at
+         FROM saved_searches ORDER BY id ASC",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let note_templates: Vec<ExportNoteTemplate> = sqlx::query_as(
+        "SELECT id, name, title, content, notebook_id, created_at, updated_at
+         FROM note_templates ORDER BY id ASC",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let note_template_tags: Vec<ExportNoteTemplateTag> = sqlx::query_as(
+        "SELECT template_id, tag_id FROM note_template_tags ORDER BY template_id ASC, tag_id ASC",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
     let attachments_rows: Vec<(i64, i64, Option<String>, Option<String>, Option<String>, Option<String>, Option<i64>, Option<i64>, Option<i64>, Option<String>, Option<String>, Option<i64>, Option<i64>, Option<i64>)> =
         sqlx::query_as(
             "SELECT id, note_id, external_id, hash, filename, mime, size, width, height, local_path, source_url, is_attachment, created_at, updated_at
              FROM attachments ORDER BY id ASC",
         )
-        .fetch_all(&pool)
+        .fetch_all(pool)
         .await
         .map_err(|e| e.to_string())?;
 
     let ocr_files_rows: Vec<(i64, String, i64, Option<String>)> = sqlx::query_as(
         "SELECT id, file_path, attempts_left, last_error FROM ocr_files ORDER BY id ASC",
     )
-    .fetch_all(&pool)
+    .fetch_all(pool)
     .await
     .map_err(|e| e.to_string())?;
 
     let note_files: Vec<ExportNoteFile> =
         sqlx::query_as("SELECT note_id, file_id FROM note_files ORDER BY note_id ASC, file_id ASC")
-            .fetch_all(&pool)
+            .fetch_all(pool)
             .await
             .map_err(|e| e.to_string())?;
 
     let ocr_text: Vec<ExportOcrText> = sqlx::query_as(
         "SELECT file_id, lang, text, hash, updated_at FROM ocr_text ORDER BY file_id ASC",
     )
-    .fetch_all(&pool)
+    .fetch_all(pool)
     .await
     .map_err(|e| e.to_string())?;
 
@@ -267,10 +492,30 @@ pub async fn export_notes_classic(
         "SELECT id, note_id, opened_at, note_title, notebook_id, notebook_name, stack_id, stack_name
          FROM note_history ORDER BY opened_at DESC, id DESC",
     )
-    .fetch_all(&pool)
+    .fetch_all(pool)
     .await
     .map_err(|e| e.to_string())?;
 
+    let name = if base.is_some() {
+        format!("notes-classic-incremental-{}", stamp)
+    } else {
+        format!("notes-classic-export-{}", stamp)
+    };
+    let mut writer = if options.archive {
+        PackageWriter::create_archive(
+            dest_dir.join(format!("{}.{}", name, PACKAGE_ARCHIVE_EXTENSION)),
+        )?
+    } else {
+        PackageWriter::create_dir(dest_dir.join(name))?
+    };
+
+    let mut errors: Vec<String> = Vec::new();
+    let mut checksums: BTreeMap<String, String> = BTreeMap::new();
+    let mut files: BTreeMap<String, ExportFileState> = BTreeMap::new();
+    let mut note_states: Vec<ExportNoteState> = Vec::new();
+
+    let total_notes = notes_rows.len() as i64;
+    let total_files = attachments_rows.len() as i64 + ocr_files_rows.len() as i64;
     let mut notes: Vec<ExportNote> = Vec::new();
     for row in notes_rows {
         let (
@@ -288,7 +533,20 @@ pub async fn export_notes_classic(
             content_size,
             deleted_at,
             deleted_from_notebook_id,
+            deleted_with_notebook_id,
         ) = row;
+        let state = ExportNoteState {
+            id,
+            updated_at,
+            content_hash: content_hash.clone(),
+            notebook_id,
+            deleted_at,
+        };
+        let unchanged = base_notes.get(&id).is_some_and(|prev| **prev == state);
+        note_states.push(state);
+        if unchanged {
+            continue;
+        }
         let content_path = format!("notes/{}.html", id);
         let meta_path = format!("notes/{}.meta.json", id);
         let note = ExportNote {
@@ -305,19 +563,32 @@ pub async fn export_notes_classic(
             content_size,
             deleted_at,
             deleted_from_notebook_id,
+            deleted_with_notebook_id,
             content_path: content_path.clone(),
             meta_path: meta_path.clone(),
         };
-        let html_path = export_root.join(&content_path);
         let normalized = normalize_export_html(&content);
-        if let Err(e) = fs::write(&html_path, normalized) {
-            errors.push(format!("note {} html: {}", id, e));
+        match writer.write_bytes(&content_path, normalized.as_bytes()) {
+            Ok(hash) => {
+                checksums.insert(content_path, hash);
+            }
+            Err(e) => errors.push(format!("note {} html: {}", id, e)),
         }
         let meta_json = serde_json::to_string_pretty(&note).map_err(|e| e.to_string())?;
-        if let Err(e) = fs::write(export_root.join(&meta_path), meta_json) {
-            errors.push(format!("note {} meta: {}", id, e));
+        match writer.write_bytes(&meta_path, meta_json.as_bytes()) {
+            Ok(hash) => {
+                checksums.insert(meta_path, hash);
+            }
+            Err(e) => errors.push(format!("note {} meta: {}", id, e)),
         }
         notes.push(note);
+        progress.report(PackageProgress {
+            stage: "notes".to_string(),
+            current: note_states.len() as i64,
+            total: total_notes,
+            state: "running".to_string(),
+            message: None,
+        });
     }
 
     let mut attachments: Vec<ExportAttachment> = Vec::new();
@@ -349,18 +620,20 @@ pub async fn export_notes_classic(
                 format!("attachments/{}", cleaned)
             }
         });
-        if let Some(ref rel) = local_path {
-            let source = data_dir.join(rel);
-            if let Some(ref export_rel) = export_path {
-                let target = export_root.join(export_rel);
-                if let Some(parent) = target.parent() {
-                    let _ = fs::create_dir_all(parent);
-                }
-                if let Err(e) = fs::copy(&source, &target) {
-                    errors.push(format!("attachment {} copy: {}", id, e));
-                    export_path = None;
-                }
-            }
+        let copied = match (local_path.as_ref(), export_path.as_ref()) {
+            (Some(rel), Some(export_rel)) => export_package_file(
+                &mut writer,
+                &data_dir.join(rel),
+                export_rel,
+                base.as_ref(),
+                &mut checksums,
+                &mut files,
+            ),
+            _ => Ok(()),
+        };
+        if let Err(e) = copied {
+            errors.push(format!("attachment {} copy: {}", id, e));
+            export_path = None;
         }
         attachments.push(ExportAttachment {
             id,
@@ -379,17 +652,27 @@ pub async fn export_notes_classic(
             updated_at,
             export_path,
         });
+        progress.report(PackageProgress {
+            stage: "attachments".to_string(),
+            current: attachments.len() as i64,
+            total: total_files,
+            state: "running".to_string(),
+            message: None,
+        });
     }
 
     let mut ocr_files: Vec<ExportOcrFile> = Vec::new();
     for (id, file_path, attempts_left, last_error) in ocr_files_rows {
         let export_path = format!("files/{}", file_path.replace('\\', "/"));
         let source = data_dir.join("files").join(&file_path);
-        let target = export_root.join(&export_path);
-        if let Some(parent) = target.parent() {
-            let _ = fs::create_dir_all(parent);
-        }
-        if let Err(e) = fs::copy(&source, &target) {
+        if let Err(e) = export_package_file(
+            &mut writer,
+            &source,
+            &export_path,
+            base.as_ref(),
+            &mut checksums,
+            &mut files,
+        ) {
             errors.push(format!("file {} copy: {}", id, e));
         }
         ocr_files.push(ExportOcrFile {
@@ -399,8 +682,28 @@ pub async fn export_notes_classic(
             last_error,
             export_path,
         });
+        progress.report(PackageProgress {
+            stage: "attachments".to_string(),
+            current: (attachments.len() + ocr_files.len()) as i64,
+            total: total_files,
+            state: "running".to_string(),
+            message: None,
+        });
     }
 
+    let current_ids: HashSet<i64> = note_states.iter().map(|state| state.id).collect();
+    let deleted_notes: Vec<i64> = base
+        .iter()
+        .flat_map(|base| base.note_states.iter())
+        .map(|state| state.id)
+        .filter(|id| !current_ids.contains(id))
+        .collect();
+    let exported_ids: HashSet<i64> = notes.iter().map(|note| note.id).collect();
+    let notes_text: Vec<ExportNoteText> = notes_text
+        .into_iter()
+        .filter(|text| exported_ids.contains(&text.note_id))
+        .collect();
+
     let manifest = ExportManifest {
         version: "1.0".to_string(),
         exported_at: now.to_rfc3339(),
@@ -414,11 +717,37 @@ pub async fn export_notes_classic(
         note_files,
         ocr_text,
         note_history,
+        saved_searches,
+        note_templates,
+        note_template_tags,
+        trashed_notebooks,
+        checksums,
+        package_id: Some(format!("{}-{:08x}", stamp, now.timestamp_subsec_nanos())),
+        base_package_id: base.as_ref().and_then(|base| base.package_id.clone()),
+        note_states,
+        deleted_notes,
+        files,
     };
 
-    let manifest_path = export_root.join("manifest.json");
     let manifest_json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
-    fs::write(&manifest_path, manifest_json).map_err(|e| e.to_string())?;
+    writer.write_bytes(PACKAGE_MANIFEST_NAME, manifest_json.as_bytes())?;
+    let export_root = writer.root().to_path_buf();
+    let manifest_path = writer.manifest_path();
+    writer.finish()?;
+    progress.report(PackageProgress {
+        stage: "notes".to_string(),
+        current: total_notes,
+        total: total_notes,
+        state: "done".to_string(),
+        message: None,
+    });
+    progress.report(PackageProgress {
+        stage: "attachments".to_string(),
+        current: total_files,
+        total: total_files,
+        state: "done".to_string(),
+        message: None,
+    });
 
     Ok(ExportReport {
         export_root: export_root.to_string_lossy().to_string(),
@@ -428,6 +757,8 @@ pub async fn export_notes_classic(
         tags: manifest.tags.len() as i64,
         attachments: manifest.attachments.len() as i64,
         images: manifest.ocr_files.len() as i64,
+        incremental: manifest.base_package_id.is_some(),
+        deleted_notes: manifest.deleted_notes.len() as i64,
         errors,
     })
 }
@@ -462,3 +793,14 @@ pub fn attachment_export_to_storage_path(export_path: &str) -> String {
         .trim_start_matches('/');
     format!("files/{}", rel)
 }
+pub fn attachment_storage_path(att: &ExportAttachment) -> Option<String> {
+    att.local_path
+        .as_ref()
+        .map(|path| path.replace('\\', "/"))
+        .filter(|path| path.starts_with("files/") && !path.contains(".."))
+        .or_else(|| {
+            att.export_path
+                .as_ref()
+                .map(|path| attachment_export_to_storage_path(path))
+        })
+}
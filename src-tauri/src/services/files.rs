@@ -7,6 +7,7 @@ pub fn store_note_bytes(
     filename: &str,
     mime: &str,
     bytes: &[u8],
+    content_addressed: bool,
 ) -> Result<StoredNoteFile, String> {
     if bytes.is_empty() {
         return Err("Empty file bytes".to_string());
@@ -33,23 +34,33 @@ pub fn store_note_bytes(
     let mut hasher = Sha256::new();
     hasher.update(bytes);
     let content_hash = format!("{:x}", hasher.finalize());
-    let nonce = NOTE_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
-    let nanos = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|e| e.to_string())?
-        .as_nanos();
-    let mut name_hasher = Sha256::new();
-    name_hasher.update(bytes);
-    name_hasher.update(nanos.to_string().as_bytes());
-    name_hasher.update(nonce.to_string().as_bytes());
-    let unique_hash = format!("{:x}", name_hasher.finalize());
+    let unique_hash = if content_addressed {
+        content_hash.clone()
+    } else {
+        let nonce = NOTE_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_nanos();
+        let mut name_hasher = Sha256::new();
+        name_hasher.update(bytes);
+        name_hasher.update(nanos.to_string().as_bytes());
+        name_hasher.update(nonce.to_string().as_bytes());
+        format!("{:x}", name_hasher.finalize())
+    };
     let rel_dir = PathBuf::from("files").join(&unique_hash[0..2]);
     let rel_file = format!("{}.{}", unique_hash, resolved_ext);
     let rel_path = rel_dir.join(&rel_file);
     let full_dir = data_dir.join(&rel_dir);
     fs::create_dir_all(&full_dir).map_err(|e| e.to_string())?;
     let full_path = data_dir.join(&rel_path);
-    fs::write(&full_path, bytes).map_err(|e| e.to_string())?;
+    let already_stored = content_addressed
+        && fs::metadata(&full_path)
+            .map(|meta| meta.len() == bytes.len() as u64)
+            .unwrap_or(false);
+    if !already_stored {
+        fs::write(&full_path, bytes).map_err(|e| e.to_string())?;
+    }
     let rel_display = PathBuf::from(&unique_hash[0..2]).join(rel_file);
     Ok(StoredNoteFile {
         rel_path: rel_display.to_string_lossy().replace('\\', "/"),
@@ -57,6 +68,45 @@ pub fn store_note_bytes(
         mime: resolved_mime,
     })
 }
+fn content_blob_path(hash: &str, filename: &str) -> String {
+    let ext = ext_from_filename(filename).unwrap_or_else(|| "bin".to_string());
+    format!("files/{}/{}.{}", &hash[0..2], hash, ext)
+}
+pub fn store_attachment_blob(
+    data_dir: &Path,
+    filename: &str,
+    bytes: &[u8],
+) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    let rel_path = content_blob_path(&format!("{:x}", hasher.finalize()), filename);
+    let full_path = data_dir.join(&rel_path);
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    if !full_path.exists() {
+        fs::write(&full_path, bytes).map_err(|e| e.to_string())?;
+    }
+    Ok(rel_path)
+}
+pub fn store_attachment_blob_from_path(
+    data_dir: &Path,
+    filename: &str,
+    source: &Path,
+) -> Result<String, String> {
+    let mut file = fs::File::open(source).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| e.to_string())?;
+    let rel_path = content_blob_path(&format!("{:x}", hasher.finalize()), filename);
+    let full_path = data_dir.join(&rel_path);
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    if !full_path.exists() {
+        fs::copy(source, &full_path).map_err(|e| e.to_string())?;
+    }
+    Ok(rel_path)
+}
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FileEntry {
@@ -127,7 +177,13 @@ pub async fn store_note_file_bytes(
     bytes: Vec<u8>,
     state: State<'_, AppState>,
 ) -> Result<StoredNoteFile, String> {
-    store_note_bytes(&state.data_dir, &filename, &mime, &bytes)
+    store_note_bytes(
+        &state.data_dir,
+        &filename,
+        &mime,
+        &bytes,
+        content_addressed_files(&state.settings_dir),
+    )
 }
 #[tauri::command]
 pub async fn download_note_file(
@@ -161,7 +217,13 @@ pub async fn download_note_file(
         .and_then(|value| value.to_str().ok())
         .unwrap_or("");
     let filename = filename_from_url(&url).unwrap_or_else(|| "download".to_string());
-    store_note_bytes(&state.data_dir, &filename, mime, &bytes)
+    store_note_bytes(
+        &state.data_dir,
+        &filename,
+        mime,
+        &bytes,
+        content_addressed_files(&state.settings_dir),
+    )
 }
 #[tauri::command]
 pub async fn store_note_file_from_path(
@@ -181,7 +243,13 @@ pub async fn store_note_file_from_path(
         .first_or_octet_stream()
         .to_string();
     let bytes = fs::read(&path).map_err(|e| e.to_string())?;
-    store_note_bytes(&state.data_dir, &filename, &mime, &bytes)
+    store_note_bytes(
+        &state.data_dir,
+        &filename,
+        &mime,
+        &bytes,
+        content_addressed_files(&state.settings_dir),
+    )
 }
 #[tauri::command]
 pub async fn delete_attachment(id: i64, state: State<'_, AppState>) -> Result<(), String> {
@@ -204,6 +272,15 @@ pub async fn delete_attachment(id: i64, state: State<'_, AppState>) -> Result<()
     Ok(())
 }
 #[tauri::command]
+pub async fn deduplicate_note_files(state: State<'_, AppState>) -> Result<DedupeReport, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.deduplicate_note_files(&state.data_dir)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
 pub async fn save_attachment_as(
     id: i64,
     dest_path: String,
@@ -3,62 +3,81 @@ use crate::services::*;
 
 #[tauri::command]
 pub async fn clear_storage_for_import(state: State<'_, AppState>) -> Result<(), String> {
-    let pool = state.pool.clone();
+    clear_storage(&state.pool, &state.data_dir).await
+}
+pub async fn clear_storage(pool: &SqlitePool, data_dir: &Path) -> Result<(), String> {
     let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    clear_storage_tx(&mut tx).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    let files_dir = data_dir.join("files");
+    if files_dir.exists() {
+        let _ = fs::remove_dir_all(&files_dir);
+    }
+    let ocr_dir = data_dir.join("ocr");
+    if ocr_dir.exists() {
+        let _ = fs::remove_dir_all(&ocr_dir);
+    }
+    Ok(())
+}
+/// Deletes every library row an import replaces; files are left to the caller.
+pub async fn clear_storage_tx(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<(), String> {
     sqlx::query("DELETE FROM note_tags")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM attachments")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM notes_text")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM notes")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM tags")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM notebooks")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM trashed_notebooks")
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM note_files")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM ocr_text")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM ocr_files")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
     sqlx::query("DELETE FROM note_history")
-        .execute(&mut *tx)
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM sqlite_sequence WHERE name IN ('note_tags','attachments','notes_text','notes','tags','notebooks','note_files','ocr_files','ocr_text','note_history')")
-        .execute(&mut *tx)
+    sqlx::query("DELETE FROM saved_searches")
+        .execute(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM note_templates")
+        .execute(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM sqlite_sequence WHERE name IN ('note_tags','attachments','notes_text','notes','tags','notebooks','note_files','ocr_files','ocr_text','note_history','saved_searches','note_templates')")
+        .execute(&mut **tx)
         .await
         .map_err(|e| e.to_string())?;
-    tx.commit().await.map_err(|e| e.to_string())?;
-
-    let files_dir = state.data_dir.join("files");
-    if files_dir.exists() {
-        let _ = fs::remove_dir_all(&files_dir);
-    }
-    let ocr_dir = state.data_dir.join("ocr");
-    if ocr_dir.exists() {
-        let _ = fs::remove_dir_all(&ocr_dir);
-    }
     Ok(())
 }
 #[allow(non_snake_case)]
@@ -87,17 +106,25 @@ pub async fn import_attachment(
         .create_attachment(noteId, &filename, &mime, size)
         .await
         .map_err(|e| e.to_string())?;
-    let rel_dir = PathBuf::from("files")
-        .join("attachments")
-        .join(id.to_string());
-    let dest_dir = state.data_dir.join(&rel_dir);
-    fs::create_dir_all(&dest_dir).map_err(|e| e.to_string())?;
-    let dest_path = dest_dir.join(&filename);
-    if let Err(e) = fs::copy(&source, &dest_path) {
-        let _ = repo.delete_attachment(id).await;
-        return Err(e.to_string());
-    }
-    let rel_path = rel_dir.join(&filename).to_string_lossy().replace('\\', "/");
+    let stored = if content_addressed_files(&state.settings_dir) {
+        store_attachment_blob_from_path(&state.data_dir, &filename, &source)
+    } else {
+        let rel_dir = PathBuf::from("files")
+            .join("attachments")
+            .join(id.to_string());
+        let dest_dir = state.data_dir.join(&rel_dir);
+        fs::create_dir_all(&dest_dir)
+            .and_then(|_| fs::copy(&source, dest_dir.join(&filename)))
+            .map(|_| rel_dir.join(&filename).to_string_lossy().replace('\\', "/"))
+            .map_err(|e| e.to_string())
+    };
+    let rel_path = match stored {
+        Ok(rel_path) => rel_path,
+        Err(e) => {
+            let _ = repo.delete_attachment(id).await;
+            return Err(e);
+        }
+    };
     repo.update_attachment_path(id, &rel_path)
         .await
         .map_err(|e| e.to_string())?;
@@ -135,17 +162,25 @@ pub async fn import_attachment_bytes(
         .create_attachment(noteId, &filename, &resolved_mime, size)
         .await
         .map_err(|e| e.to_string())?;
-    let rel_dir = PathBuf::from("files")
-        .join("attachments")
-        .join(id.to_string());
-    let dest_dir = state.data_dir.join(&rel_dir);
-    fs::create_dir_all(&dest_dir).map_err(|e| e.to_string())?;
-    let dest_path = dest_dir.join(&filename);
-    if let Err(e) = fs::write(&dest_path, &bytes) {
-        let _ = repo.delete_attachment(id).await;
-        return Err(e.to_string());
-    }
-    let rel_path = rel_dir.join(&filename).to_string_lossy().replace('\\', "/");
+    let stored = if content_addressed_files(&state.settings_dir) {
+        store_attachment_blob(&state.data_dir, &filename, &bytes)
+    } else {
+        let rel_dir = PathBuf::from("files")
+            .join("attachments")
+            .join(id.to_string());
+        let dest_dir = state.data_dir.join(&rel_dir);
+        fs::create_dir_all(&dest_dir)
+            .and_then(|_| fs::write(dest_dir.join(&filename), &bytes))
+            .map(|_| rel_dir.join(&filename).to_string_lossy().replace('\\', "/"))
+            .map_err(|e| e.to_string())
+    };
+    let rel_path = match stored {
+        Ok(rel_path) => rel_path,
+        Err(e) => {
+            let _ = repo.delete_attachment(id).await;
+            return Err(e);
+        }
+    };
     repo.update_attachment_path(id, &rel_path)
         .await
         .map_err(|e| e.to_string())?;
@@ -160,6 +195,10 @@ pub async fn import_attachment_bytes(
 }
 #[tauri::command]
 pub fn create_import_backup(kind: String, state: State<'_, AppState>) -> Result<String, String> {
+    create_storage_backup(&state.data_dir, &kind)
+        .map(|backup_dir| backup_dir.to_string_lossy().to_string())
+}
+pub fn create_storage_backup(data_dir: &Path, kind: &str) -> Result<PathBuf, String> {
     let clean = kind
         .chars()
         .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '-' })
@@ -171,18 +210,17 @@ pub fn create_import_backup(kind: String, state: State<'_, AppState>) -> Result<
         clean.as_str()
     };
     let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
-    let backup_dir = state
-        .data_dir
+    let backup_dir = data_dir
         .join("backups")
         .join(format!("{}-{}", prefix, timestamp));
     fs::create_dir_all(&backup_dir).map_err(|e| e.to_string())?;
-    let notes_db = state.data_dir.join("notes.db");
+    let notes_db = data_dir.join("notes.db");
     if notes_db.exists() {
         fs::copy(&notes_db, backup_dir.join("notes.db")).map_err(|e| e.to_string())?;
     }
-    copy_dir_recursive(&state.data_dir.join("files"), &backup_dir.join("files"))?;
-    copy_dir_recursive(&state.data_dir.join("ocr"), &backup_dir.join("ocr"))?;
-    Ok(backup_dir.to_string_lossy().to_string())
+    copy_dir_recursive(&data_dir.join("files"), &backup_dir.join("files"))?;
+    copy_dir_recursive(&data_dir.join("ocr"), &backup_dir.join("ocr"))?;
+    Ok(backup_dir)
 }
 #[tauri::command]
 pub fn restore_import_backup(backup_dir: String, state: State<'_, AppState>) -> Result<(), String> {
@@ -202,3 +240,351 @@ pub fn restore_import_backup(backup_dir: String, state: State<'_, AppState>) ->
     copy_dir_recursive(&backup.join("ocr"), &state.data_dir.join("ocr"))?;
     Ok(())
 }
+/// Writes a notebook with its package id, replacing the row with that id.
+/// The full and incremental Notes Classic imports share these row writers;
+/// a full import runs them against emptied tables.
+pub async fn upsert_notebook_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    nb: &ExportNotebook,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order, external_id)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET name = excluded.name, created_at = excluded.created_at,
+           parent_id = excluded.parent_id, notebook_type = excluded.notebook_type,
+           sort_order = excluded.sort_order, external_id = excluded.external_id",
+    )
+    .bind(nb.id)
+    .bind(&nb.name)
+    .bind(nb.created_at)
+    .bind(nb.parent_id)
+    .bind(&nb.notebook_type)
+    .bind(nb.sort_order)
+    .bind(&nb.external_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+pub async fn upsert_note_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    note: &ExportNote,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO notes (id, title, content, created_at, updated_at, sync_status, remote_id, notebook_id, external_id, meta, content_hash, content_size, deleted_at, deleted_from_notebook_id, deleted_with_notebook_id)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET title = excluded.title, content = excluded.content,
+           created_at = excluded.created_at, updated_at = excluded.updated_at,
+           sync_status = excluded.sync_status, remote_id = excluded.remote_id,
+           notebook_id = excluded.notebook_id, external_id = excluded.external_id,
+           meta = excluded.meta, content_hash = excluded.content_hash,
+           content_size = excluded.content_size, deleted_at = excluded.deleted_at,
+           deleted_from_notebook_id = excluded.deleted_from_notebook_id,
+           deleted_with_notebook_id = excluded.deleted_with_notebook_id",
+    )
+    .bind(note.id)
+    .bind(&note.title)
+    .bind(content)
+    .bind(note.created_at)
+    .bind(note.updated_at)
+    .bind(note.sync_status)
+    .bind(&note.remote_id)
+    .bind(note.notebook_id)
+    .bind(&note.external_id)
+    .bind(&note.meta)
+    .bind(&note.content_hash)
+    .bind(note.content_size)
+    .bind(note.deleted_at)
+    .bind(note.deleted_from_notebook_id)
+    .bind(note.deleted_with_notebook_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+/// Indexes the text of an imported note under its id in this library.
+pub async fn upsert_note_text_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    note_id: i64,
+    title: &str,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO notes_text (note_id, title, plain_text)
+         VALUES (?, ?, ?)
+         ON CONFLICT(note_id) DO UPDATE SET title = excluded.title, plain_text = excluded.plain_text",
+    )
+    .bind(note_id)
+    .bind(title)
+    .bind(strip_html(content))
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+pub async fn upsert_tag_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    tag: &ExportTag,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO tags (id, name, parent_id, created_at, updated_at, external_id)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET name = excluded.name, parent_id = excluded.parent_id,
+           created_at = excluded.created_at, updated_at = excluded.updated_at,
+           external_id = excluded.external_id",
+    )
+    .bind(tag.id)
+    .bind(&tag.name)
+    .bind(tag.parent_id)
+    .bind(tag.created_at)
+    .bind(tag.updated_at)
+    .bind(&tag.external_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+pub async fn upsert_attachment_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    att: &ExportAttachment,
+    storage_path: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO attachments (id, note_id, external_id, hash, filename, mime, size, width, height, local_path, source_url, is_attachment, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET note_id = excluded.note_id,
+           external_id = excluded.external_id, hash = excluded.hash,
+           filename = excluded.filename, mime = excluded.mime, size = excluded.size,
+           width = excluded.width, height = excluded.height,
+           local_path = excluded.local_path, source_url = excluded.source_url,
+           is_attachment = excluded.is_attachment, created_at = excluded.created_at,
+           updated_at = excluded.updated_at",
+    )
+    .bind(att.id)
+    .bind(att.note_id)
+    .bind(&att.external_id)
+    .bind(&att.hash)
+    .bind(&att.filename)
+    .bind(&att.mime)
+    .bind(att.size)
+    .bind(att.width)
+    .bind(att.height)
+    .bind(storage_path)
+    .bind(&att.source_url)
+    .bind(att.is_attachment)
+    .bind(att.created_at)
+    .bind(att.updated_at)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+pub async fn upsert_ocr_file_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    file: &ExportOcrFile,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO ocr_files (id, file_path, attempts_left, last_error)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET file_path = excluded.file_path,
+           attempts_left = excluded.attempts_left, last_error = excluded.last_error",
+    )
+    .bind(file.id)
+    .bind(&file.file_path)
+    .bind(file.attempts_left)
+    .bind(&file.last_error)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+/// Stores recognized text for `file_id`, which a merge has remapped to the
+/// id of the file in this library.
+pub async fn upsert_ocr_text_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    file_id: i64,
+    text: &ExportOcrText,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO ocr_text (file_id, lang, text, hash, updated_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(file_id) DO UPDATE SET lang = excluded.lang, text = excluded.text,
+           hash = excluded.hash, updated_at = excluded.updated_at",
+    )
+    .bind(file_id)
+    .bind(&text.lang)
+    .bind(&text.text)
+    .bind(&text.hash)
+    .bind(text.updated_at)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+pub async fn insert_note_tag_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    note_id: i64,
+    tag_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT OR IGNORE INTO note_tags (note_id, tag_id) VALUES (?, ?)")
+        .bind(note_id)
+        .bind(tag_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+pub async fn insert_note_file_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    note_id: i64,
+    file_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT OR IGNORE INTO note_files (note_id, file_id) VALUES (?, ?)")
+        .bind(note_id)
+        .bind(file_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+pub async fn insert_note_template_tag_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    template_id: i64,
+    tag_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT OR IGNORE INTO note_template_tags (template_id, tag_id) VALUES (?, ?)")
+        .bind(template_id)
+        .bind(tag_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+/// Puts a notebook back into the trash under `id`; a merge passes the ids
+/// it has mapped into this library.
+pub async fn insert_trashed_notebook_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    nb: &ExportTrashedNotebook,
+    id: i64,
+    parent_id: Option<i64>,
+    trash_root_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO trashed_notebooks
+           (id, name, created_at, parent_id, notebook_type, sort_order, external_id, deleted_at, trash_root_id)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(id)
+    .bind(&nb.name)
+    .bind(nb.created_at)
+    .bind(parent_id)
+    .bind(&nb.notebook_type)
+    .bind(nb.sort_order)
+    .bind(&nb.external_id)
+    .bind(nb.deleted_at)
+    .bind(trash_root_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+/// Replaces the tables a package always carries in full: note history,
+/// saved searches, templates and the notebook trash. Rows that fail are
+/// reported in `errors`.
+pub async fn replace_package_extras_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    manifest: &ExportManifest,
+    errors: &mut Vec<String>,
+) -> Result<(), String> {
+    for table in [
+        "note_history",
+        "saved_searches",
+        "note_templates",
+        "trashed_notebooks",
+    ] {
+        sqlx::query(&format!("DELETE FROM {}", table))
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    for item in &manifest.note_history {
+        if let Err(e) = sqlx::query(
+            "INSERT INTO note_history (id, note_id, opened_at, note_title, notebook_id, notebook_name, stack_id, stack_name)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(item.id)
+        .bind(item.note_id)
+        .bind(item.opened_at)
+        .bind(&item.note_title)
+        .bind(item.notebook_id)
+        .bind(&item.notebook_name)
+        .bind(item.stack_id)
+        .bind(&item.stack_name)
+        .execute(&mut **tx)
+        .await
+        {
+            errors.push(format!("history {}: {}", item.id, e));
+        }
+    }
+    for search in &manifest.saved_searches {
+        if let Err(e) = sqlx::query(
+            "INSERT INTO saved_searches (id, name, query, notebook_id, sort, sort_order, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(search.id)
+        .bind(&search.name)
+        .bind(&search.query)
+        .bind(search.notebook_id)
+        .bind(search.sort)
+        .bind(search.sort_order)
+        .bind(search.created_at)
+        .bind(search.updated_at)
+        .execute(&mut **tx)
+        .await
+        {
+            errors.push(format!("saved_search {}: {}", search.id, e));
+        }
+    }
+    for template in &manifest.note_templates {
+        if let Err(e) = sqlx::query(
+            "INSERT INTO note_templates (id, name, title, content, notebook_id, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(template.id)
+        .bind(&template.name)
+        .bind(&template.title)
+        .bind(&template.content)
+        .bind(template.notebook_id)
+        .bind(template.created_at)
+        .bind(template.updated_at)
+        .execute(&mut **tx)
+        .await
+        {
+            errors.push(format!("note_template {}: {}", template.id, e));
+        }
+    }
+    for link in &manifest.note_template_tags {
+        if let Err(e) = insert_note_template_tag_tx(tx, link.template_id, link.tag_id).await {
+            errors.push(format!(
+                "note_template_tag {}-{}: {}",
+                link.template_id, link.tag_id, e
+            ));
+        }
+    }
+    for nb in &manifest.trashed_notebooks {
+        if let Err(e) =
+            insert_trashed_notebook_tx(tx, nb, nb.id, nb.parent_id, nb.trash_root_id).await
+        {
+            errors.push(format!("trashed_notebook {}: {}", nb.id, e));
+        }
+    }
+    Ok(())
+}
+/// Moves the id sequences of every imported table past the imported ids.
+pub async fn update_import_sequences(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+) -> Result<(), String> {
+    for table in [
+        "notebooks",
+        "notes",
+        "tags",
+        "attachments",
+        "ocr_files",
+        "note_history",
+        "saved_searches",
+        "note_templates",
+    ] {
+        update_sqlite_sequence(tx, table).await?;
+    }
+    Ok(())
+}
@@ -23,18 +23,43 @@ pub async fn select_evernote_folder(app_handle: AppHandle) -> Result<Option<Stri
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EvernoteImportResult {
-    notes: i64,
-    notebooks: i64,
-    tags: i64,
-    attachments: i64,
+    pub notes: i64,
+    pub notebooks: i64,
+    pub tags: i64,
+    pub attachments: i64,
+    pub merge: Option<MergeSummary>,
+    pub preflight: Option<ImportPreflightReport>,
 }
 #[tauri::command]
 pub async fn import_evernote_from_json(
     json_path: String,
     assets_dir: String,
+    mode: Option<ImportMode>,
+    dry_run: Option<bool>,
     state: State<'_, AppState>,
 ) -> Result<EvernoteImportResult, String> {
-    let raw = fs::read_to_string(&json_path).map_err(|e| e.to_string())?;
+    import_evernote_json(
+        &state.pool,
+        &state.data_dir,
+        Path::new(&json_path),
+        Path::new(assets_dir.trim()),
+        mode.unwrap_or_default(),
+        dry_run.unwrap_or(false),
+    )
+    .await
+}
+/// Imports the JSON written by the Evernote converter, with its files under
+/// `assets_dir`. A dry run only checks the data and reports what is missing.
+pub async fn import_evernote_json(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    json_path: &Path,
+    assets_dir: &Path,
+    mode: ImportMode,
+    dry_run: bool,
+) -> Result<EvernoteImportResult, String> {
+    let merge = mode == ImportMode::Merge;
+    let raw = fs::read_to_string(json_path).map_err(|e| e.to_string())?;
     let data: Value = serde_json::from_str(&raw).map_err(|e| e.to_string())?;
     let stacks = data
         .get("stacks")
@@ -67,50 +92,24 @@ pub async fn import_evernote_from_json(
         .cloned()
         .unwrap_or_default();
 
+    if dry_run {
+        return Ok(EvernoteImportResult {
+            notes: notes.len() as i64,
+            notebooks: notebooks.len() as i64,
+            tags: tags.len() as i64,
+            attachments: attachments.len() as i64,
+            merge: None,
+            preflight: Some(preflight_evernote_data(&data, assets_dir)),
+        });
+    }
+
     let now = chrono::Utc::now().timestamp();
-    let pool = state.pool.clone();
     let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
 
-    sqlx::query("DELETE FROM note_tags")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM attachments")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM notes_text")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM notes")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM tags")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM notebooks")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM note_files")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM ocr_text")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM ocr_files")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
-    sqlx::query("DELETE FROM sqlite_sequence WHERE name IN ('note_tags','attachments','notes_text','notes','tags','notebooks','note_files','ocr_files','ocr_text')")
-        .execute(&mut *tx)
-        .await
-        .map_err(|e| e.to_string())?;
+    let mut summary = MergeSummary::default();
+    if !merge {
+        clear_storage_tx(&mut tx).await?;
+    }
 
     let mut stack_name_map: std::collections::HashMap<String, String> =
         std::collections::HashMap::new();
@@ -153,11 +152,30 @@ pub async fn import_evernote_from_json(
 
     let mut stack_id_map = std::collections::HashMap::new();
     let mut stack_index = 0i64;
+    if merge {
+        let next: (i64,) = sqlx::query_as(
+            "SELECT COALESCE(MAX(sort_order) + 1, 0) FROM notebooks WHERE notebook_type = 'stack'",
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+        stack_index = next.0;
+    }
     for stack_key in &stack_order {
         let name = stack_name_map
             .get(stack_key)
             .cloned()
             .unwrap_or_else(|| stack_key.clone());
+        let external_id = format!("stack:{}", stack_key);
+        if merge {
+            if let Some(id) =
+                find_notebook_tx(&mut tx, Some(external_id.as_str()), &name, "stack", None).await?
+            {
+                stack_id_map.insert(stack_key.clone(), id);
+                summary.notebooks.record(MergeAction::Skipped);
+                continue;
+            }
+        }
         sqlx::query(
             "INSERT INTO notebooks (name, created_at, parent_id, notebook_type, sort_order, external_id)
              VALUES (?, ?, NULL, 'stack', ?, ?)",
@@ -165,7 +183,7 @@ pub async fn import_evernote_from_json(
         .bind(name)
         .bind(now)
         .bind(stack_index)
-        .bind(format!("stack:{}", stack_key))
+        .bind(external_id)
         .execute(&mut *tx)
         .await
         .map_err(|e| e.to_string())?;
@@ -174,9 +192,24 @@ pub async fn import_evernote_from_json(
             .await
             .map_err(|e| e.to_string())?;
         stack_id_map.insert(stack_key.clone(), row_id.0);
+        summary.notebooks.record(MergeAction::Created);
         stack_index += 1;
     }
 
+    if merge && unsorted_needed && !stack_id_map.contains_key("__unsorted__") {
+        if let Some(id) = find_notebook_tx(
+            &mut tx,
+            Some("stack:__unsorted__"),
+            "Unsorted",
+            "stack",
+            None,
+        )
+        .await?
+        {
+            stack_id_map.insert("__unsorted__".to_string(), id);
+            summary.notebooks.record(MergeAction::Skipped);
+        }
+    }
     if unsorted_needed && !stack_id_map.contains_key("__unsorted__") {
         sqlx::query(
             "INSERT INTO notebooks (name, created_at, parent_id, notebook_type, sort_order, external_id)
@@ -193,6 +226,7 @@ pub async fn import_evernote_from_json(
             .await
             .map_err(|e| e.to_string())?;
         stack_id_map.insert("__unsorted__".to_string(), row_id.0);
+        summary.notebooks.record(MergeAction::Created);
     }
 
     let mut notebook_id_map = std::collections::HashMap::new();
@@ -205,7 +239,20 @@ pub async fn import_evernote_from_json(
             .or_else(|| nb.get("stack_Stack_id").and_then(|v| v.as_str()));
         let stack_id = normalize_stack_id(stack_raw).unwrap_or_else(|| "__unsorted__".to_string());
         let parent_id = stack_id_map.get(&stack_id).copied();
-        let index = notebook_order.get(&stack_id).copied().unwrap_or(0);
+        let index = match notebook_order.get(&stack_id).copied() {
+            Some(index) => index,
+            None if merge => {
+                let next: (i64,) = sqlx::query_as(
+                    "SELECT COALESCE(MAX(sort_order) + 1, 0) FROM notebooks WHERE parent_id IS ?",
+                )
+                .bind(parent_id)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+                next.0
+            }
+            None => 0,
+        };
         let name = nb
             .get("label")
             .and_then(|v| v.as_str())
@@ -215,6 +262,21 @@ pub async fn import_evernote_from_json(
             .or_else(|| nb.get("id").and_then(value_to_string))
             .unwrap_or_else(|| "Notebook".to_string());
         let external_id = nb.get("id").and_then(value_to_string).unwrap_or_default();
+        if merge {
+            if let Some(id) = find_notebook_tx(
+                &mut tx,
+                Some(external_id.as_str()),
+                &name,
+                "notebook",
+                parent_id,
+            )
+            .await?
+            {
+                notebook_id_map.insert(external_id, id);
+                summary.notebooks.record(MergeAction::Skipped);
+                continue;
+            }
+        }
         sqlx::query(
             "INSERT INTO notebooks (name, created_at, parent_id, notebook_type, sort_order, external_id)
              VALUES (?, ?, ?, 'notebook', ?, ?)",
@@ -233,6 +295,7 @@ pub async fn import_evernote_from_json(
             .map_err(|e| e.to_string())?;
         notebook_id_map.insert(external_id, row_id.0);
         notebook_order.insert(stack_id, index + 1);
+        summary.notebooks.record(MergeAction::Created);
     }
 
     let mut note_id_map = std::collections::HashMap::new();
@@ -274,6 +337,52 @@ pub async fn import_evernote_from_json(
         let content_size = note.get("contentSize").and_then(|v| v.as_i64());
         let meta = note.get("meta").map(|v| v.to_string());
         let external_id = note.get("id").and_then(value_to_string).unwrap_or_default();
+        if merge {
+            if let Some((id, existing_updated_at)) = find_note_tx(
+                &mut tx,
+                Some(external_id.as_str()),
+                &title,
+                created_at,
+                content,
+            )
+            .await?
+            {
+                if existing_updated_at >= updated_at {
+                    summary.notes.record(MergeAction::Skipped);
+                    continue;
+                }
+                sqlx::query(
+                    "UPDATE notes SET title = ?, content = ?, updated_at = ?, notebook_id = ?, meta = ?,
+                       content_hash = ?, content_size = ?
+                     WHERE id = ?",
+                )
+                .bind(&title)
+                .bind(content)
+                .bind(updated_at)
+                .bind(notebook_id)
+                .bind(meta)
+                .bind(content_hash)
+                .bind(content_size)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+                sqlx::query(
+                    "INSERT INTO notes_text (note_id, title, plain_text)
+                     VALUES (?, ?, ?)
+                     ON CONFLICT(note_id) DO UPDATE SET title = excluded.title, plain_text = excluded.plain_text",
+                )
+                .bind(id)
+                .bind(&title)
+                .bind(strip_html(content))
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+                note_id_map.insert(external_id, id);
+                summary.notes.record(MergeAction::Updated);
+                continue;
+            }
+        }
         sqlx::query(
             "INSERT INTO notes (title, content, created_at, updated_at, notebook_id, external_id, meta, content_hash, content_size)
              VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
@@ -306,6 +415,7 @@ pub async fn import_evernote_from_json(
         .execute(&mut *tx)
         .await
         .map_err(|e| e.to_string())?;
+        summary.notes.record(MergeAction::Created);
     }
 
     let mut tag_id_map = std::collections::HashMap::new();
@@ -326,6 +436,13 @@ pub async fn import_evernote_from_json(
             .or_else(|| tag.get("id").and_then(value_to_string))
             .unwrap_or_else(|| "Tag".to_string());
         let external_id = tag.get("id").and_then(value_to_string).unwrap_or_default();
+        if merge {
+            if let Some(id) = find_tag_tx(&mut tx, Some(external_id.as_str()), &name, None).await? {
+                tag_id_map.insert(external_id, id);
+                summary.tags.record(MergeAction::Skipped);
+                continue;
+            }
+        }
         sqlx::query(
             "INSERT INTO tags (name, parent_id, created_at, updated_at, external_id)
              VALUES (?, NULL, ?, ?, ?)",
@@ -342,6 +459,7 @@ pub async fn import_evernote_from_json(
             .await
             .map_err(|e| e.to_string())?;
         tag_id_map.insert(external_id, row_id.0);
+        summary.tags.record(MergeAction::Created);
     }
 
     let children: Vec<&Value> = tags
@@ -369,6 +487,15 @@ pub async fn import_evernote_from_json(
             .or_else(|| tag.get("id").and_then(value_to_string))
             .unwrap_or_else(|| "Tag".to_string());
         let external_id = tag.get("id").and_then(value_to_string).unwrap_or_default();
+        if merge {
+            if let Some(id) =
+                find_tag_tx(&mut tx, Some(external_id.as_str()), &name, parent_id).await?
+            {
+                tag_id_map.insert(external_id, id);
+                summary.tags.record(MergeAction::Skipped);
+                continue;
+            }
+        }
         sqlx::query(
             "INSERT INTO tags (name, parent_id, created_at, updated_at, external_id)
              VALUES (?, ?, ?, ?, ?)",
@@ -386,6 +513,7 @@ pub async fn import_evernote_from_json(
             .await
             .map_err(|e| e.to_string())?;
         tag_id_map.insert(external_id, row_id.0);
+        summary.tags.record(MergeAction::Created);
     }
 
     for nt in &note_tags {
@@ -428,6 +556,9 @@ pub async fn import_evernote_from_json(
             .and_then(|id| note_id_map.get(id))
             .copied();
         if note_id.is_none() {
+            if merge {
+                summary.attachments.record(MergeAction::Skipped);
+            }
             continue;
         }
         let hash = attachment
@@ -490,13 +621,51 @@ pub async fn import_evernote_from_json(
         if rel_path.is_none() {
             continue;
         }
+        let external_id = fields.get("id").and_then(value_to_string);
+
+        if merge {
+            let existing: Option<(i64,)> = match external_id.as_deref() {
+                Some(external_id) => sqlx::query_as(
+                    "SELECT id FROM attachments WHERE note_id = ? AND external_id = ? LIMIT 1",
+                )
+                .bind(note_id)
+                .bind(external_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?,
+                None => None,
+            };
+            if let Some((id,)) = existing {
+                sqlx::query(
+                    "UPDATE attachments SET hash = ?, filename = ?, mime = ?, size = ?, width = ?, height = ?,
+                       local_path = ?, source_url = ?, is_attachment = ?, updated_at = ?
+                     WHERE id = ?",
+                )
+                .bind(hash)
+                .bind(filename)
+                .bind(mime)
+                .bind(size)
+                .bind(width)
+                .bind(height)
+                .bind(rel_path.unwrap_or_default())
+                .bind(source_url)
+                .bind(is_attachment_value)
+                .bind(updated_at)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+                summary.attachments.record(MergeAction::Updated);
+                continue;
+            }
+        }
 
         sqlx::query(
             "INSERT INTO attachments (note_id, external_id, hash, filename, mime, size, width, height, local_path, source_url, is_attachment, created_at, updated_at)
              VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(note_id)
-        .bind(fields.get("id").and_then(value_to_string))
+        .bind(external_id)
         .bind(hash)
         .bind(filename)
         .bind(mime)
@@ -511,28 +680,33 @@ pub async fn import_evernote_from_json(
         .execute(&mut *tx)
         .await
         .map_err(|e| e.to_string())?;
+        summary.attachments.record(MergeAction::Created);
     }
 
     tx.commit().await.map_err(|e| e.to_string())?;
+    crate::db::refresh_stems(pool)
+        .await
+        .map_err(|e| e.to_string())?;
 
-    let files_dir = state.data_dir.join("files");
-    if files_dir.exists() {
+    let files_dir = data_dir.join("files");
+    if !merge && files_dir.exists() {
         let _ = fs::remove_dir_all(&files_dir);
     }
-    if !assets_dir.trim().is_empty() {
-        let assets_path = PathBuf::from(assets_dir);
-        if assets_path.exists() {
-            copy_dir_recursive(&assets_path, &files_dir)?;
-        }
+    if !assets_dir.as_os_str().is_empty() && assets_dir.exists() {
+        copy_dir_recursive(assets_dir, &files_dir)?;
     }
-    let repo = SqliteRepository {
-        pool: state.pool.clone(),
-    };
-    let _ = repo.backfill_note_files_and_ocr(&state.data_dir).await;
+    let repo = SqliteRepository { pool: pool.clone() };
+    let _ = repo.backfill_note_files_and_ocr(data_dir).await;
     Ok(EvernoteImportResult {
         notes: note_id_map.len() as i64,
         notebooks: notebook_id_map.len() as i64,
         tags: tag_id_map.len() as i64,
-        attachments: attachments.len() as i64,
+        attachments: if merge {
+            summary.attachments.created + summary.attachments.updated
+        } else {
+            attachments.len() as i64
+        },
+        merge: merge.then_some(summary),
+        preflight: None,
     })
 }
@@ -0,0 +1,375 @@
+use crate::services::prelude::*;
+use crate::services::*;
+use std::collections::HashSet;
+
+const IMPORT_STAGING_DIR: &str = "import-staging";
+
+/// Imports a full package followed by a chain of incremental packages, each
+/// exported against the one before it. Every member of every package is
+/// checked first; the library is then replaced in one transaction, with the
+/// new files staged beside it and swapped in once the rows are committed.
+pub async fn import_notes_classic_chain(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    base_path: &Path,
+    incremental_paths: &[PathBuf],
+    report_dir: Option<&Path>,
+    progress: &dyn ProgressSink,
+) -> Result<NotesClassicImportResult, String> {
+    report_database_step(progress, 0, Some("import_notes_classic.step.preparing"));
+    let mut base_package = PackageReader::open(base_path)?;
+    let base = read_package_manifest(&mut base_package, progress)?;
+    if base.base_package_id.is_some() {
+        return Err(
+            "This is an incremental package; import it together with its base package".to_string(),
+        );
+    }
+    let mut expected = base.package_id.clone();
+    let mut chain = Vec::new();
+    for path in incremental_paths {
+        let mut package = PackageReader::open(path)?;
+        let manifest = package.read_manifest()?;
+        if manifest.base_package_id.is_none() || manifest.base_package_id != expected {
+            return Err(format!(
+                "{} was not exported against the previous package",
+                path.to_string_lossy()
+            ));
+        }
+        expected = manifest.package_id.clone();
+        chain.push((package, manifest));
+    }
+    report_database_step(progress, 1, Some("import_notes_classic.step.read_manifest"));
+    base_package.verify_members(&base)?;
+    for (package, manifest) in &mut chain {
+        package.verify_members(manifest)?;
+    }
+
+    let staging = data_dir.join(IMPORT_STAGING_DIR);
+    if staging.exists() {
+        fs::remove_dir_all(&staging).map_err(|e| e.to_string())?;
+    }
+    fs::create_dir_all(staging.join("files")).map_err(|e| e.to_string())?;
+    let report = match import_chain_staged(
+        pool,
+        &staging,
+        &mut base_package,
+        &base,
+        &mut chain,
+        progress,
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(err) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(err);
+        }
+    };
+    for dir in ["files", "ocr"] {
+        let path = data_dir.join(dir);
+        if path.exists() {
+            fs::remove_dir_all(&path).map_err(|e| e.to_string())?;
+        }
+    }
+    fs::rename(staging.join("files"), data_dir.join("files")).map_err(|e| e.to_string())?;
+    let _ = fs::remove_dir_all(&staging);
+    progress.report(PackageProgress {
+        stage: "database".to_string(),
+        current: IMPORT_DATABASE_STEPS,
+        total: IMPORT_DATABASE_STEPS,
+        state: "done".to_string(),
+        message: None,
+    });
+
+    if let Some(report_dir) = report_dir {
+        let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+        fs::write(report_dir.join("import_report.json"), json).map_err(|e| e.to_string())?;
+    }
+    Ok(report)
+}
+
+const IMPORT_DATABASE_STEPS: i64 = 4;
+
+fn report_database_step(progress: &dyn ProgressSink, step: i64, message: Option<&str>) {
+    progress.report(PackageProgress {
+        stage: "database".to_string(),
+        current: step,
+        total: IMPORT_DATABASE_STEPS,
+        state: "running".to_string(),
+        message: message.map(|message| message.to_string()),
+    });
+}
+
+async fn import_chain_staged(
+    pool: &SqlitePool,
+    staging: &Path,
+    base_package: &mut PackageReader,
+    base: &ExportManifest,
+    chain: &mut [(PackageReader, ExportManifest)],
+    progress: &dyn ProgressSink,
+) -> Result<NotesClassicImportResult, String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    report_database_step(progress, 2, Some("import_notes_classic.step.clear_storage"));
+    clear_storage_tx(&mut tx).await?;
+    report_database_step(progress, 3, Some("import_notes_classic.step.prepare_files"));
+    report_database_step(
+        progress,
+        4,
+        Some("import_notes_classic.step.importing_content"),
+    );
+    let mut errors: Vec<String> = Vec::new();
+    import_full_package_tx(&mut tx, base_package, base, staging, progress, &mut errors).await?;
+    let mut report = NotesClassicImportResult {
+        notes: base.notes.len() as i64,
+        notebooks: base.notebooks.len() as i64,
+        tags: base.tags.len() as i64,
+        attachments: base.attachments.len() as i64,
+        images: base.ocr_files.len() as i64,
+        deleted_notes: 0,
+        merge: None,
+        preflight: None,
+        errors: Vec::new(),
+    };
+    let mut removed_paths = Vec::new();
+    for (package, manifest) in chain.iter_mut() {
+        removed_paths.extend(
+            apply_incremental_package(&mut tx, staging, package, manifest, progress, &mut errors)
+                .await?,
+        );
+        report.notes = manifest.note_states.len() as i64;
+        report.notebooks = manifest.notebooks.len() as i64;
+        report.tags = manifest.tags.len() as i64;
+        report.attachments = manifest.attachments.len() as i64;
+        report.images = manifest.ocr_files.len() as i64;
+        report.deleted_notes += manifest.deleted_notes.len() as i64;
+    }
+    // Files the incrementals dropped go the way a deleted note's files do.
+    let repo = SqliteRepository { pool: pool.clone() };
+    let unused = repo
+        .unused_blobs_tx(&mut tx, removed_paths)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    crate::db::refresh_stems(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    for path in unused {
+        let _ = fs::remove_file(staging.join(path));
+    }
+    report.errors = errors;
+    Ok(report)
+}
+
+async fn delete_missing(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    table: &str,
+    key: &str,
+    keep: &HashSet<i64>,
+) -> Result<(), String> {
+    let existing: Vec<i64> = sqlx::query_scalar(&format!("SELECT {} FROM {}", key, table))
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let delete = format!("DELETE FROM {} WHERE {} = ?", table, key);
+    for id in existing.into_iter().filter(|id| !keep.contains(id)) {
+        sqlx::query(&delete)
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Data-dir paths of the rows `delete_missing` is about to drop; `sql`
+/// selects each row's key and path.
+async fn missing_paths(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    sql: &str,
+    keep: &HashSet<i64>,
+) -> Result<Vec<String>, String> {
+    let rows: Vec<(i64, Option<String>)> = sqlx::query_as(sql)
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(rows
+        .into_iter()
+        .filter(|(id, _)| !keep.contains(id))
+        .filter_map(|(_, path)| path.filter(|path| !path.is_empty()))
+        .collect())
+}
+
+/// Applies one incremental package on top of the current library. Changed
+/// notes and files come from the package; notebooks, tags and the link tables
+/// are carried in full and replace the current rows. Returns the paths of
+/// the attachments and files it dropped.
+async fn apply_incremental_package(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    target_dir: &Path,
+    package: &mut PackageReader,
+    manifest: &ExportManifest,
+    progress: &dyn ProgressSink,
+    errors: &mut Vec<String>,
+) -> Result<Vec<String>, String> {
+    let total_notes = manifest.notes.len() as i64;
+    let mut removed_paths = Vec::new();
+
+    for nb in &manifest.notebooks {
+        if let Err(e) = upsert_notebook_tx(tx, nb).await {
+            errors.push(format!("notebook {}: {}", nb.id, e));
+        }
+    }
+    let keep: HashSet<i64> = manifest.notebooks.iter().map(|nb| nb.id).collect();
+    delete_missing(tx, "notebooks", "id", &keep).await?;
+
+    for id in &manifest.deleted_notes {
+        sqlx::query("DELETE FROM notes_text WHERE note_id = ?")
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        sqlx::query("DELETE FROM notes WHERE id = ?")
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut notes_done = 0i64;
+    for note in &manifest.notes {
+        let content = package
+            .read_to_string(&note.content_path, manifest.checksum(&note.content_path))
+            .map_err(|e| format!("note {} html: {}", note.id, e))?;
+        let content = normalize_export_html(&content);
+        if let Err(e) = upsert_note_tx(tx, note, &content).await {
+            errors.push(format!("note {}: {}", note.id, e));
+        }
+        if let Err(e) = upsert_note_text_tx(tx, note.id, &note.title, &content).await {
+            errors.push(format!("notes_text {}: {}", note.id, e));
+        }
+        notes_done += 1;
+        progress.report(PackageProgress {
+            stage: "notes".to_string(),
+            current: notes_done,
+            total: total_notes,
+            state: "running".to_string(),
+            message: None,
+        });
+    }
+
+    for tag in &manifest.tags {
+        if let Err(e) = upsert_tag_tx(tx, tag).await {
+            errors.push(format!("tag {}: {}", tag.id, e));
+        }
+    }
+    let keep: HashSet<i64> = manifest.tags.iter().map(|tag| tag.id).collect();
+    delete_missing(tx, "tags", "id", &keep).await?;
+
+    sqlx::query("DELETE FROM note_tags")
+        .execute(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    for link in &manifest.note_tags {
+        if let Err(e) = insert_note_tag_tx(tx, link.note_id, link.tag_id).await {
+            errors.push(format!("note_tag {}-{}: {}", link.note_id, link.tag_id, e));
+        }
+    }
+
+    let total_files = manifest.checksums.len() as i64;
+    let mut files_done = 0i64;
+    for file in &manifest.ocr_files {
+        let export_path = file.export_path.replace('\\', "/");
+        if manifest.checksums.contains_key(&export_path) {
+            let target = target_dir.join("files").join(&file.file_path);
+            package
+                .extract(&export_path, &target, manifest.checksum(&export_path))
+                .map_err(|e| format!("ocr_file {} copy: {}", file.id, e))?;
+            files_done += 1;
+            progress.report(PackageProgress {
+                stage: "attachments".to_string(),
+                current: files_done,
+                total: total_files,
+                state: "running".to_string(),
+                message: None,
+            });
+        } else if !target_dir.join("files").join(&file.file_path).exists() {
+            return Err(format!("ocr_file {}: {} is missing", file.id, export_path));
+        }
+        if let Err(e) = upsert_ocr_file_tx(tx, file).await {
+            errors.push(format!("ocr_file {}: {}", file.id, e));
+        }
+    }
+    let keep: HashSet<i64> = manifest.ocr_files.iter().map(|file| file.id).collect();
+    removed_paths
+        .extend(missing_paths(tx, "SELECT id, 'files/' || file_path FROM ocr_files", &keep).await?);
+    delete_missing(tx, "ocr_files", "id", &keep).await?;
+
+    for att in &manifest.attachments {
+        let storage_path = attachment_storage_path(att);
+        let export_path = att.export_path.as_ref().map(|p| p.replace('\\', "/"));
+        if let (Some(exp), Some(dest)) = (export_path.as_ref(), storage_path.as_ref()) {
+            if manifest.checksums.contains_key(exp) {
+                let target = target_dir.join(dest);
+                package
+                    .extract(exp, &target, manifest.checksum(exp))
+                    .map_err(|e| format!("attachment {} copy: {}", att.id, e))?;
+                files_done += 1;
+                progress.report(PackageProgress {
+                    stage: "attachments".to_string(),
+                    current: files_done,
+                    total: total_files,
+                    state: "running".to_string(),
+                    message: None,
+                });
+            } else if !target_dir.join(dest).exists() {
+                return Err(format!("attachment {}: {} is missing", att.id, exp));
+            }
+        }
+        if let Err(e) = upsert_attachment_tx(tx, att, storage_path.as_deref()).await {
+            errors.push(format!("attachment {}: {}", att.id, e));
+        }
+    }
+    let keep: HashSet<i64> = manifest.attachments.iter().map(|att| att.id).collect();
+    removed_paths.extend(missing_paths(tx, "SELECT id, local_path FROM attachments", &keep).await?);
+    delete_missing(tx, "attachments", "id", &keep).await?;
+
+    sqlx::query("DELETE FROM note_files")
+        .execute(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    for link in &manifest.note_files {
+        if let Err(e) = insert_note_file_tx(tx, link.note_id, link.file_id).await {
+            errors.push(format!(
+                "note_file {}-{}: {}",
+                link.note_id, link.file_id, e
+            ));
+        }
+    }
+
+    for text in &manifest.ocr_text {
+        if let Err(e) = upsert_ocr_text_tx(tx, text.file_id, text).await {
+            errors.push(format!("ocr_text {}: {}", text.file_id, e));
+        }
+    }
+    let keep: HashSet<i64> = manifest.ocr_text.iter().map(|text| text.file_id).collect();
+    delete_missing(tx, "ocr_text", "file_id", &keep).await?;
+
+    replace_package_extras_tx(tx, manifest, errors).await?;
+    update_import_sequences(tx).await?;
+
+    progress.report(PackageProgress {
+        stage: "notes".to_string(),
+        current: total_notes,
+        total: total_notes,
+        state: "done".to_string(),
+        message: None,
+    });
+    progress.report(PackageProgress {
+        stage: "attachments".to_string(),
+        current: files_done,
+        total: total_files,
+        state: "done".to_string(),
+        message: None,
+    });
+    Ok(removed_paths)
+}
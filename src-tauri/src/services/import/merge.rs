@@ -0,0 +1,806 @@
+use crate::services::prelude::*;
+use crate::services::*;
+use std::collections::{HashMap, HashSet};
+
+type Tx<'a> = sqlx::Transaction<'a, sqlx::Sqlite>;
+
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Wipe the library and load the import as-is, keeping its ids.
+    #[default]
+    Replace,
+    /// Insert into the existing library, remapping ids and reusing matching
+    /// notebooks, tags and notes.
+    Merge,
+}
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MergeAction {
+    Created,
+    Updated,
+    Skipped,
+}
+#[derive(serde::Serialize, Default, Clone, Copy)]
+pub struct MergeCounts {
+    pub created: i64,
+    pub updated: i64,
+    pub skipped: i64,
+}
+impl MergeCounts {
+    pub fn record(&mut self, action: MergeAction) {
+        match action {
+            MergeAction::Created => self.created += 1,
+            MergeAction::Updated => self.updated += 1,
+            MergeAction::Skipped => self.skipped += 1,
+        }
+    }
+}
+#[derive(serde::Serialize, Default)]
+pub struct MergeSummary {
+    pub notebooks: MergeCounts,
+    pub tags: MergeCounts,
+    pub notes: MergeCounts,
+    pub attachments: MergeCounts,
+    pub saved_searches: MergeCounts,
+    pub note_templates: MergeCounts,
+}
+
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.map(|v| v.trim()).filter(|v| !v.is_empty())
+}
+/// Nesting depth of `id` in a parent map, so parents can be merged first.
+fn hierarchy_depth(id: i64, parents: &HashMap<i64, Option<i64>>) -> usize {
+    let mut depth = 0;
+    let mut current = parents.get(&id).copied().flatten();
+    while let Some(parent) = current {
+        depth += 1;
+        if depth > 64 {
+            break;
+        }
+        current = parents.get(&parent).copied().flatten();
+    }
+    depth
+}
+pub async fn find_notebook_tx(
+    tx: &mut Tx<'_>,
+    external_id: Option<&str>,
+    name: &str,
+    notebook_type: &str,
+    parent_id: Option<i64>,
+) -> Result<Option<i64>, String> {
+    if let Some(external_id) = non_empty(external_id) {
+        let row: Option<(i64,)> = sqlx::query_as(
+            "SELECT id FROM notebooks WHERE external_id = ? AND notebook_type = ? ORDER BY id LIMIT 1",
+        )
+        .bind(external_id)
+        .bind(notebook_type)
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?;
+        if let Some((id,)) = row {
+            return Ok(Some(id));
+        }
+    }
+    let row: Option<(i64,)> = sqlx::query_as(
+        "SELECT id FROM notebooks WHERE name = ? AND notebook_type = ? AND parent_id IS ? ORDER BY id LIMIT 1",
+    )
+    .bind(name)
+    .bind(notebook_type)
+    .bind(parent_id)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(row.map(|(id,)| id))
+}
+pub async fn find_tag_tx(
+    tx: &mut Tx<'_>,
+    external_id: Option<&str>,
+    name: &str,
+    parent_id: Option<i64>,
+) -> Result<Option<i64>, String> {
+    if let Some(external_id) = non_empty(external_id) {
+        let row: Option<(i64,)> =
+            sqlx::query_as("SELECT id FROM tags WHERE external_id = ? ORDER BY id LIMIT 1")
+                .bind(external_id)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(|e| e.to_string())?;
+        if let Some((id,)) = row {
+            return Ok(Some(id));
+        }
+    }
+    let row: Option<(i64,)> =
+        sqlx::query_as("SELECT id FROM tags WHERE name = ? AND parent_id IS ? ORDER BY id LIMIT 1")
+            .bind(name)
+            .bind(parent_id)
+            .fetch_optional(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    Ok(row.map(|(id,)| id))
+}
+/// Finds a note already in the library by `external_id`; notes without one
+/// only match a note with the same title, creation time and text, so notes
+/// that merely share a title are never overwritten. Returns the id and
+/// `updated_at`.
+pub async fn find_note_tx(
+    tx: &mut Tx<'_>,
+    external_id: Option<&str>,
+    title: &str,
+    created_at: i64,
+    content: &str,
+) -> Result<Option<(i64, i64)>, String> {
+    let row: Option<(i64, i64)> = match non_empty(external_id) {
+        Some(external_id) => sqlx::query_as(
+            "SELECT id, updated_at FROM notes WHERE external_id = ? ORDER BY id LIMIT 1",
+        )
+        .bind(external_id)
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?,
+        None => sqlx::query_as(
+            "SELECT n.id, n.updated_at FROM notes n
+             JOIN notes_text t ON t.note_id = n.id
+             WHERE (n.external_id IS NULL OR n.external_id = '') AND n.title = ? AND n.created_at = ?
+               AND t.plain_text = ?
+             ORDER BY n.id LIMIT 1",
+        )
+        .bind(title)
+        .bind(created_at)
+        .bind(strip_html(content))
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(|e| e.to_string())?,
+    };
+    Ok(row)
+}
+
+/// Merges a Notes Classic package into the current library instead of
+/// replacing it. Notes already present are updated when the package copy is
+/// newer and skipped otherwise; everything else gets fresh ids.
+pub async fn merge_notes_classic_package(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    manifest_path: &Path,
+    report_dir: Option<&Path>,
+    progress: &dyn ProgressSink,
+) -> Result<NotesClassicImportResult, String> {
+    let mut package = PackageReader::open(manifest_path)?;
+    progress.report(PackageProgress {
+        stage: "package".to_string(),
+        current: 0,
+        total: 1,
+        state: "running".to_string(),
+        message: Some("import_notes_classic.step.parse_manifest".to_string()),
+    });
+    let manifest = package.read_manifest()?;
+    if manifest.base_package_id.is_some() {
+        return Err("Incremental packages cannot be merged".to_string());
+    }
+    package.verify_members(&manifest)?;
+    progress.report(PackageProgress {
+        stage: "package".to_string(),
+        current: 1,
+        total: 1,
+        state: "done".to_string(),
+        message: None,
+    });
+
+    let mut summary = MergeSummary::default();
+    let mut errors: Vec<String> = Vec::new();
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    let notebook_parents: HashMap<i64, Option<i64>> = manifest
+        .notebooks
+        .iter()
+        .map(|nb| (nb.id, nb.parent_id))
+        .collect();
+    let mut notebooks: Vec<&ExportNotebook> = manifest.notebooks.iter().collect();
+    notebooks.sort_by_key(|nb| hierarchy_depth(nb.id, &notebook_parents));
+    let mut notebook_map: HashMap<i64, i64> = HashMap::new();
+    for nb in notebooks {
+        let parent_id = nb
+            .parent_id
+            .and_then(|parent| notebook_map.get(&parent).copied());
+        let existing = find_notebook_tx(
+            &mut tx,
+            nb.external_id.as_deref(),
+            &nb.name,
+            &nb.notebook_type,
+            parent_id,
+        )
+        .await?;
+        if let Some(id) = existing {
+            notebook_map.insert(nb.id, id);
+            summary.notebooks.record(MergeAction::Skipped);
+            continue;
+        }
+        let result = sqlx::query(
+            "INSERT INTO notebooks (name, created_at, parent_id, notebook_type, sort_order, external_id)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&nb.name)
+        .bind(nb.created_at)
+        .bind(parent_id)
+        .bind(&nb.notebook_type)
+        .bind(nb.sort_order)
+        .bind(&nb.external_id)
+        .execute(&mut *tx)
+        .await;
+        match result {
+            Ok(result) => {
+                notebook_map.insert(nb.id, result.last_insert_rowid());
+                summary.notebooks.record(MergeAction::Created);
+            }
+            Err(e) => errors.push(format!("notebook {}: {}", nb.id, e)),
+        }
+    }
+
+    let tag_parents: HashMap<i64, Option<i64>> = manifest
+        .tags
+        .iter()
+        .map(|tag| (tag.id, tag.parent_id))
+        .collect();
+    let mut tags: Vec<&ExportTag> = manifest.tags.iter().collect();
+    tags.sort_by_key(|tag| hierarchy_depth(tag.id, &tag_parents));
+    let mut tag_map: HashMap<i64, i64> = HashMap::new();
+    for tag in tags {
+        let parent_id = tag
+            .parent_id
+            .and_then(|parent| tag_map.get(&parent).copied());
+        if let Some(id) =
+            find_tag_tx(&mut tx, tag.external_id.as_deref(), &tag.name, parent_id).await?
+        {
+            tag_map.insert(tag.id, id);
+            summary.tags.record(MergeAction::Skipped);
+            continue;
+        }
+        let result = sqlx::query(
+            "INSERT INTO tags (name, parent_id, created_at, updated_at, external_id)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(&tag.name)
+        .bind(parent_id)
+        .bind(tag.created_at)
+        .bind(tag.updated_at)
+        .bind(&tag.external_id)
+        .execute(&mut *tx)
+        .await;
+        match result {
+            Ok(result) => {
+                tag_map.insert(tag.id, result.last_insert_rowid());
+                summary.tags.record(MergeAction::Created);
+            }
+            Err(e) => errors.push(format!("tag {}: {}", tag.id, e)),
+        }
+    }
+
+    // Trash groups get ids that are free in this library; a group merged
+    // before is recognized by its root and reused.
+    let mut trash_map: HashMap<i64, i64> = HashMap::new();
+    let mut next_trash_id: i64 = sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT MAX(id) FROM (SELECT id FROM notebooks UNION ALL SELECT id FROM trashed_notebooks)",
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?
+    .unwrap_or(0);
+    let mut new_trash: Vec<&ExportTrashedNotebook> = Vec::new();
+    for root in manifest
+        .trashed_notebooks
+        .iter()
+        .filter(|nb| nb.id == nb.trash_root_id)
+    {
+        let group = manifest
+            .trashed_notebooks
+            .iter()
+            .filter(|nb| nb.trash_root_id == root.id);
+        let existing: Option<(i64,)> = sqlx::query_as(
+            "SELECT id FROM trashed_notebooks
+             WHERE id = trash_root_id AND name = ? AND created_at = ? AND deleted_at = ?
+             ORDER BY id LIMIT 1",
+        )
+        .bind(&root.name)
+        .bind(root.created_at)
+        .bind(root.deleted_at)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+        let Some((existing_root,)) = existing else {
+            for nb in group {
+                next_trash_id += 1;
+                trash_map.insert(nb.id, next_trash_id);
+                new_trash.push(nb);
+            }
+            continue;
+        };
+        for nb in group {
+            let row: Option<(i64,)> = sqlx::query_as(
+                "SELECT id FROM trashed_notebooks
+                 WHERE trash_root_id = ? AND name = ? AND created_at = ?
+                 ORDER BY id LIMIT 1",
+            )
+            .bind(existing_root)
+            .bind(&nb.name)
+            .bind(nb.created_at)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+            if let Some((id,)) = row {
+                trash_map.insert(nb.id, id);
+            }
+        }
+    }
+    for nb in new_trash {
+        let parent_id = nb.parent_id.and_then(|parent| {
+            trash_map
+                .get(&parent)
+                .or_else(|| notebook_map.get(&parent))
+                .copied()
+        });
+        if let Err(e) = insert_trashed_notebook_tx(
+            &mut tx,
+            nb,
+            trash_map[&nb.id],
+            parent_id,
+            trash_map[&nb.trash_root_id],
+        )
+        .await
+        {
+            errors.push(format!("trashed_notebook {}: {}", nb.id, e));
+        }
+    }
+
+    let total_notes = manifest.notes.len() as i64;
+    let mut note_map: HashMap<i64, i64> = HashMap::new();
+    let mut merged_notes: Vec<(i64, String)> = Vec::new();
+    let mut notes_done = 0i64;
+    for note in &manifest.notes {
+        notes_done += 1;
+        progress.report(PackageProgress {
+            stage: "notes".to_string(),
+            current: notes_done,
+            total: total_notes,
+            state: "running".to_string(),
+            message: None,
+        });
+        let content = match package
+            .read_to_string(&note.content_path, manifest.checksum(&note.content_path))
+        {
+            Ok(content) => normalize_export_html(&content),
+            Err(e) => {
+                errors.push(format!("note {} html: {}", note.id, e));
+                continue;
+            }
+        };
+        let existing = find_note_tx(
+            &mut tx,
+            note.external_id.as_deref(),
+            &note.title,
+            note.created_at,
+            &content,
+        )
+        .await?;
+        if let Some((id, updated_at)) = existing {
+            if updated_at >= note.updated_at {
+                summary.notes.record(MergeAction::Skipped);
+                continue;
+            }
+        }
+        let notebook_id = note
+            .notebook_id
+            .and_then(|id| notebook_map.get(&id).copied());
+        let deleted_from = note.deleted_from_notebook_id.and_then(|id| {
+            notebook_map
+                .get(&id)
+                .or_else(|| trash_map.get(&id))
+                .copied()
+        });
+        let deleted_with = note
+            .deleted_with_notebook_id
+            .and_then(|id| trash_map.get(&id).copied());
+        let (id, action) = match existing {
+            Some((id, _)) => {
+                let result = sqlx::query(
+                    "UPDATE notes SET title = ?, content = ?, updated_at = ?, notebook_id = ?, meta = ?,
+                       content_hash = ?, content_size = ?, deleted_at = ?, deleted_from_notebook_id = ?,
+                       deleted_with_notebook_id = ?
+                     WHERE id = ?",
+                )
+                .bind(&note.title)
+                .bind(&content)
+                .bind(note.updated_at)
+                .bind(notebook_id)
+                .bind(&note.meta)
+                .bind(&note.content_hash)
+                .bind(note.content_size)
+                .bind(note.deleted_at)
+                .bind(deleted_from)
+                .bind(deleted_with)
+                .bind(id)
+                .execute(&mut *tx)
+                .await;
+                if let Err(e) = result {
+                    errors.push(format!("note {}: {}", note.id, e));
+                    continue;
+                }
+                (id, MergeAction::Updated)
+            }
+            None => {
+                let result = sqlx::query(
+                    "INSERT INTO notes (title, content, created_at, updated_at, sync_status, remote_id, notebook_id, external_id, meta, content_hash, content_size, deleted_at, deleted_from_notebook_id, deleted_with_notebook_id)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&note.title)
+                .bind(&content)
+                .bind(note.created_at)
+                .bind(note.updated_at)
+                .bind(note.sync_status)
+                .bind(&note.remote_id)
+                .bind(notebook_id)
+                .bind(&note.external_id)
+                .bind(&note.meta)
+                .bind(&note.content_hash)
+                .bind(note.content_size)
+                .bind(note.deleted_at)
+                .bind(deleted_from)
+                .bind(deleted_with)
+                .execute(&mut *tx)
+                .await;
+                match result {
+                    Ok(result) => (result.last_insert_rowid(), MergeAction::Created),
+                    Err(e) => {
+                        errors.push(format!("note {}: {}", note.id, e));
+                        continue;
+                    }
+                }
+            }
+        };
+        if let Err(e) = upsert_note_text_tx(&mut tx, id, &note.title, &content).await {
+            errors.push(format!("notes_text {}: {}", note.id, e));
+        }
+        note_map.insert(note.id, id);
+        merged_notes.push((id, content));
+        summary.notes.record(action);
+    }
+
+    for link in &manifest.note_tags {
+        let (Some(note_id), Some(tag_id)) =
+            (note_map.get(&link.note_id), tag_map.get(&link.tag_id))
+        else {
+            continue;
+        };
+        if let Err(e) = insert_note_tag_tx(&mut tx, *note_id, *tag_id).await {
+            errors.push(format!("note_tag {}-{}: {}", link.note_id, link.tag_id, e));
+        }
+    }
+
+    for search in &manifest.saved_searches {
+        let existing: Option<(i64,)> =
+            sqlx::query_as("SELECT id FROM saved_searches WHERE name = ? AND query = ? LIMIT 1")
+                .bind(&search.name)
+                .bind(&search.query)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+        if existing.is_some() {
+            summary.saved_searches.record(MergeAction::Skipped);
+            continue;
+        }
+        let notebook_id = search
+            .notebook_id
+            .and_then(|id| notebook_map.get(&id).copied());
+        let result = sqlx::query(
+            "INSERT INTO saved_searches (name, query, notebook_id, sort, sort_order, created_at, updated_at)
+             VALUES (?, ?, ?, ?, (SELECT COALESCE(MAX(sort_order), -1) + 1 FROM saved_searches), ?, ?)",
+        )
+        .bind(&search.name)
+        .bind(&search.query)
+        .bind(notebook_id)
+        .bind(search.sort)
+        .bind(search.created_at)
+        .bind(search.updated_at)
+        .execute(&mut *tx)
+        .await;
+        match result {
+            Ok(_) => summary.saved_searches.record(MergeAction::Created),
+            Err(e) => errors.push(format!("saved_search {}: {}", search.id, e)),
+        }
+    }
+
+    for template in &manifest.note_templates {
+        let existing: Option<(i64,)> = sqlx::query_as(
+            "SELECT id FROM note_templates WHERE name = ? AND title = ? AND content = ? LIMIT 1",
+        )
+        .bind(&template.name)
+        .bind(&template.title)
+        .bind(&template.content)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+        if existing.is_some() {
+            summary.note_templates.record(MergeAction::Skipped);
+            continue;
+        }
+        let notebook_id = template
+            .notebook_id
+            .and_then(|id| notebook_map.get(&id).copied());
+        let result = sqlx::query(
+            "INSERT INTO note_templates (name, title, content, notebook_id, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&template.name)
+        .bind(&template.title)
+        .bind(&template.content)
+        .bind(notebook_id)
+        .bind(template.created_at)
+        .bind(template.updated_at)
+        .execute(&mut *tx)
+        .await;
+        let id = match result {
+            Ok(result) => result.last_insert_rowid(),
+            Err(e) => {
+                errors.push(format!("note_template {}: {}", template.id, e));
+                continue;
+            }
+        };
+        summary.note_templates.record(MergeAction::Created);
+        let links = manifest
+            .note_template_tags
+            .iter()
+            .filter(|link| link.template_id == template.id);
+        for link in links {
+            let Some(tag_id) = tag_map.get(&link.tag_id) else {
+                continue;
+            };
+            if let Err(e) = insert_note_template_tag_tx(&mut tx, id, *tag_id).await {
+                errors.push(format!(
+                    "note_template_tag {}-{}: {}",
+                    link.template_id, link.tag_id, e
+                ));
+            }
+        }
+    }
+
+    let needed_files: HashSet<i64> = manifest
+        .note_files
+        .iter()
+        .filter(|link| note_map.contains_key(&link.note_id))
+        .map(|link| link.file_id)
+        .collect();
+    let total_files = (needed_files.len() + manifest.attachments.len()) as i64;
+    let mut files_done = 0i64;
+    let mut file_map: HashMap<i64, i64> = HashMap::new();
+    let mut new_files: HashSet<i64> = HashSet::new();
+    for file in &manifest.ocr_files {
+        if !needed_files.contains(&file.id) {
+            continue;
+        }
+        files_done += 1;
+        progress.report(PackageProgress {
+            stage: "attachments".to_string(),
+            current: files_done,
+            total: total_files,
+            state: "running".to_string(),
+            message: None,
+        });
+        let existing: Option<(i64,)> =
+            sqlx::query_as("SELECT id FROM ocr_files WHERE file_path = ?")
+                .bind(&file.file_path)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+        if let Some((id,)) = existing {
+            file_map.insert(file.id, id);
+            continue;
+        }
+        let export_path = file.export_path.replace('\\', "/");
+        let target = data_dir.join("files").join(&file.file_path);
+        if !target.exists() {
+            if let Err(e) = package.extract(&export_path, &target, manifest.checksum(&export_path))
+            {
+                errors.push(format!("ocr_file {} copy: {}", file.id, e));
+                continue;
+            }
+        }
+        match sqlx::query(
+            "INSERT INTO ocr_files (file_path, attempts_left, last_error)
+             VALUES (?, ?, ?)",
+        )
+        .bind(&file.file_path)
+        .bind(file.attempts_left)
+        .bind(&file.last_error)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(result) => {
+                file_map.insert(file.id, result.last_insert_rowid());
+                new_files.insert(file.id);
+            }
+            Err(e) => errors.push(format!("ocr_file {}: {}", file.id, e)),
+        }
+    }
+
+    // Links come from the merged content, so an updated note also drops the
+    // files its older copy used.
+    let repo = SqliteRepository { pool: pool.clone() };
+    for (id, content) in &merged_notes {
+        if let Err(e) = repo.sync_note_files_tx(&mut tx, *id, content).await {
+            errors.push(format!("note_files {}: {}", id, e));
+        }
+    }
+
+    for text in &manifest.ocr_text {
+        if !new_files.contains(&text.file_id) {
+            continue;
+        }
+        let Some(file_id) = file_map.get(&text.file_id) else {
+            continue;
+        };
+        if let Err(e) = upsert_ocr_text_tx(&mut tx, *file_id, text).await {
+            errors.push(format!("ocr_text {}: {}", text.file_id, e));
+        }
+    }
+
+    let mut attachment_map: HashMap<i64, i64> = HashMap::new();
+    for att in &manifest.attachments {
+        files_done += 1;
+        progress.report(PackageProgress {
+            stage: "attachments".to_string(),
+            current: files_done,
+            total: total_files,
+            state: "running".to_string(),
+            message: None,
+        });
+        let Some(note_id) = note_map.get(&att.note_id).copied() else {
+            summary.attachments.record(MergeAction::Skipped);
+            continue;
+        };
+        let existing: Option<(i64, Option<String>)> = match non_empty(att.external_id.as_deref()) {
+            Some(external_id) => sqlx::query_as(
+                "SELECT id, local_path FROM attachments WHERE note_id = ? AND external_id = ? LIMIT 1",
+            )
+            .bind(note_id)
+            .bind(external_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?,
+            None => sqlx::query_as(
+                "SELECT id, local_path FROM attachments
+                 WHERE note_id = ? AND (external_id IS NULL OR external_id = '') AND hash IS ? AND filename IS ?
+                 LIMIT 1",
+            )
+            .bind(note_id)
+            .bind(&att.hash)
+            .bind(&att.filename)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?,
+        };
+        let (id, current_path, action) = match existing {
+            Some((id, local_path)) => (id, local_path, MergeAction::Updated),
+            None => {
+                let result = sqlx::query(
+                    "INSERT INTO attachments (note_id, external_id, hash, filename, mime, size, width, height, source_url, is_attachment, created_at, updated_at)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(note_id)
+                .bind(&att.external_id)
+                .bind(&att.hash)
+                .bind(&att.filename)
+                .bind(&att.mime)
+                .bind(att.size)
+                .bind(att.width)
+                .bind(att.height)
+                .bind(&att.source_url)
+                .bind(att.is_attachment)
+                .bind(att.created_at)
+                .bind(att.updated_at)
+                .execute(&mut *tx)
+                .await;
+                match result {
+                    Ok(result) => (result.last_insert_rowid(), None, MergeAction::Created),
+                    Err(e) => {
+                        errors.push(format!("attachment {}: {}", att.id, e));
+                        continue;
+                    }
+                }
+            }
+        };
+        attachment_map.insert(att.id, id);
+        // Per-attachment folders are keyed by the source id, which means
+        // nothing here; content-addressed paths are safe to share.
+        let storage_path = attachment_storage_path(att).map(|path| {
+            match path.strip_prefix("files/attachments/") {
+                Some(rest) => {
+                    let name = rest.rsplit('/').next().unwrap_or(rest);
+                    format!("files/attachments/{}/{}", id, name)
+                }
+                None => path,
+            }
+        });
+        let local_path = current_path.or(storage_path);
+        if let (Some(exp), Some(dest)) = (att.export_path.as_ref(), local_path.as_ref()) {
+            let exp = exp.replace('\\', "/");
+            let target = data_dir.join(dest);
+            if action == MergeAction::Updated || !target.exists() {
+                if let Err(e) = package.extract(&exp, &target, manifest.checksum(&exp)) {
+                    errors.push(format!("attachment {} copy: {}", att.id, e));
+                }
+            }
+        }
+        if let Err(e) = sqlx::query(
+            "UPDATE attachments SET hash = ?, filename = ?, mime = ?, size = ?, width = ?, height = ?,
+               local_path = ?, source_url = ?, is_attachment = ?, updated_at = ?
+             WHERE id = ?",
+        )
+        .bind(&att.hash)
+        .bind(&att.filename)
+        .bind(&att.mime)
+        .bind(att.size)
+        .bind(att.width)
+        .bind(att.height)
+        .bind(&local_path)
+        .bind(&att.source_url)
+        .bind(att.is_attachment)
+        .bind(att.updated_at)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        {
+            errors.push(format!("attachment {}: {}", att.id, e));
+        }
+        summary.attachments.record(action);
+    }
+
+    if !attachment_map.is_empty() {
+        for (id, content) in &merged_notes {
+            let remapped = replace_attachment_ids(content, &attachment_map);
+            if &remapped == content {
+                continue;
+            }
+            sqlx::query("UPDATE notes SET content = ? WHERE id = ?")
+                .bind(&remapped)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    crate::db::refresh_stems(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    progress.report(PackageProgress {
+        stage: "notes".to_string(),
+        current: total_notes,
+        total: total_notes,
+        state: "done".to_string(),
+        message: None,
+    });
+    progress.report(PackageProgress {
+        stage: "attachments".to_string(),
+        current: total_files,
+        total: total_files,
+        state: "done".to_string(),
+        message: None,
+    });
+
+    let report = NotesClassicImportResult {
+        notes: summary.notes.created + summary.notes.updated,
+        notebooks: summary.notebooks.created,
+        tags: summary.tags.created,
+        attachments: summary.attachments.created + summary.attachments.updated,
+        images: new_files.len() as i64,
+        deleted_notes: 0,
+        merge: Some(summary),
+        preflight: None,
+        errors,
+    };
+    if let Some(report_dir) = report_dir {
+        let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+        fs::write(report_dir.join("import_report.json"), json).map_err(|e| e.to_string())?;
+    }
+    Ok(report)
+}
@@ -1,9 +1,15 @@
 mod common;
 mod evernote;
+mod incremental;
+mod merge;
 mod notes_classic;
+mod preflight;
 mod selectors;
 
 pub use common::*;
 pub use evernote::*;
+pub use incremental::*;
+pub use merge::*;
 pub use notes_classic::*;
+pub use preflight::*;
 pub use selectors::*;
@@ -0,0 +1,367 @@
+use crate::services::prelude::*;
+use crate::services::*;
+use std::collections::{HashMap, HashSet};
+
+/// What a dry-run import found. Nothing in the library is touched while
+/// building it.
+#[derive(serde::Serialize, Default)]
+pub struct ImportPreflightReport {
+    pub missing_files: Vec<String>,
+    pub missing_assets: Vec<String>,
+    pub dangling_references: Vec<String>,
+    pub duplicate_ids: Vec<String>,
+    pub estimated_bytes: u64,
+}
+impl ImportPreflightReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing_files.is_empty()
+            && self.missing_assets.is_empty()
+            && self.dangling_references.is_empty()
+            && self.duplicate_ids.is_empty()
+    }
+}
+
+fn find_duplicates<K: std::hash::Hash + Eq + std::fmt::Display>(
+    kind: &str,
+    ids: impl Iterator<Item = K>,
+    out: &mut Vec<String>,
+) {
+    let mut seen = HashSet::new();
+    let mut reported = HashSet::new();
+    for id in ids {
+        let label = format!("{} {}", kind, id);
+        if !seen.insert(id) && reported.insert(label.clone()) {
+            out.push(label);
+        }
+    }
+}
+/// Relative paths under `files/` that note HTML points at.
+fn html_asset_refs(content: &str) -> Vec<String> {
+    let re = Regex::new(r#"src=["']files/([^"']+)["']"#).expect("valid regex");
+    let mut refs: Vec<String> = re
+        .captures_iter(content)
+        .map(|caps| caps[1].to_string())
+        .collect();
+    refs.sort();
+    refs.dedup();
+    refs
+}
+fn html_attachment_refs(content: &str) -> Vec<i64> {
+    let re = Regex::new(r#"data-attachment-id=["'](\d+)["']"#).expect("valid regex");
+    re.captures_iter(content)
+        .filter_map(|caps| caps[1].parse().ok())
+        .collect()
+}
+
+/// Checks a Notes Classic package, or a full package followed by its
+/// incrementals, without importing it. Members left out of an incremental
+/// because they were unchanged may come from any earlier package in the chain.
+pub fn preflight_notes_classic_chain(
+    base_path: &Path,
+    incremental_paths: &[PathBuf],
+) -> Result<NotesClassicImportResult, String> {
+    let mut report = ImportPreflightReport::default();
+    let mut result = NotesClassicImportResult {
+        notes: 0,
+        notebooks: 0,
+        tags: 0,
+        attachments: 0,
+        images: 0,
+        deleted_notes: 0,
+        merge: None,
+        preflight: None,
+        errors: Vec::new(),
+    };
+    let mut available: HashSet<String> = HashSet::new();
+    let paths = std::iter::once(base_path).chain(incremental_paths.iter().map(|p| p.as_path()));
+    for (index, path) in paths.enumerate() {
+        let mut package = PackageReader::open(path)?;
+        let manifest = package.read_manifest()?;
+        if index == 0 && manifest.base_package_id.is_some() {
+            return Err(
+                "This is an incremental package; import it together with its base package"
+                    .to_string(),
+            );
+        }
+        result.notes += manifest.notes.len() as i64;
+        result.notebooks += manifest.notebooks.len() as i64;
+        result.tags += manifest.tags.len() as i64;
+        result.attachments += manifest.attachments.len() as i64;
+        result.images += manifest.ocr_files.len() as i64;
+        result.deleted_notes += manifest.deleted_notes.len() as i64;
+        preflight_manifest(&mut package, &manifest, &mut available, &mut report);
+    }
+    result.preflight = Some(report);
+    Ok(result)
+}
+
+/// Counts a member towards the disk estimate once; a member missing from this
+/// package is fine if an earlier package in the chain carried it.
+fn check_member(
+    package: &mut PackageReader,
+    available: &mut HashSet<String>,
+    report: &mut ImportPreflightReport,
+    rel: &str,
+) -> bool {
+    let rel = rel.replace('\\', "/");
+    match package.member_size(&rel) {
+        Some(size) => {
+            if available.insert(rel) {
+                report.estimated_bytes += size;
+            }
+            true
+        }
+        None => available.contains(&rel),
+    }
+}
+fn preflight_manifest(
+    package: &mut PackageReader,
+    manifest: &ExportManifest,
+    available: &mut HashSet<String>,
+    report: &mut ImportPreflightReport,
+) {
+    let mut contents: Vec<(i64, String)> = Vec::new();
+    for note in &manifest.notes {
+        if !check_member(package, available, report, &note.content_path) {
+            report
+                .missing_files
+                .push(format!("note {}: {}", note.id, note.content_path));
+            continue;
+        }
+        match package.read_to_string(&note.content_path, None) {
+            Ok(content) => contents.push((note.id, normalize_export_html(&content))),
+            Err(e) => report
+                .missing_files
+                .push(format!("note {}: {}", note.id, e)),
+        }
+    }
+    for att in &manifest.attachments {
+        if let Some(export_path) = att.export_path.as_ref() {
+            if !check_member(package, available, report, export_path) {
+                report
+                    .missing_files
+                    .push(format!("attachment {}: {}", att.id, export_path));
+            }
+        }
+    }
+    for file in &manifest.ocr_files {
+        if !check_member(package, available, report, &file.export_path) {
+            report
+                .missing_files
+                .push(format!("file {}: {}", file.id, file.export_path));
+        }
+    }
+    for (note_id, content) in &contents {
+        for rel in html_asset_refs(content) {
+            let member = format!("files/{}", rel);
+            if !check_member(package, available, report, &member) {
+                report
+                    .missing_assets
+                    .push(format!("note {}: files/{}", note_id, rel));
+            }
+        }
+    }
+
+    let duplicates = &mut report.duplicate_ids;
+    find_duplicates(
+        "notebook",
+        manifest.notebooks.iter().map(|nb| nb.id),
+        duplicates,
+    );
+    find_duplicates(
+        "note",
+        manifest.notes.iter().map(|note| note.id),
+        duplicates,
+    );
+    find_duplicates("tag", manifest.tags.iter().map(|tag| tag.id), duplicates);
+    find_duplicates(
+        "attachment",
+        manifest.attachments.iter().map(|att| att.id),
+        duplicates,
+    );
+    find_duplicates(
+        "file",
+        manifest.ocr_files.iter().map(|file| file.id),
+        duplicates,
+    );
+    find_duplicates(
+        "file_path",
+        manifest.ocr_files.iter().map(|file| file.file_path.clone()),
+        duplicates,
+    );
+
+    // Incrementals only carry what changed, so their rows may point into
+    // earlier packages.
+    if manifest.base_package_id.is_some() {
+        return;
+    }
+    let notebooks: HashSet<i64> = manifest.notebooks.iter().map(|nb| nb.id).collect();
+    let notes: HashSet<i64> = manifest.notes.iter().map(|note| note.id).collect();
+    let tags: HashSet<i64> = manifest.tags.iter().map(|tag| tag.id).collect();
+    let files: HashSet<i64> = manifest.ocr_files.iter().map(|file| file.id).collect();
+    let attachments: HashMap<i64, i64> = manifest
+        .attachments
+        .iter()
+        .map(|att| (att.id, att.note_id))
+        .collect();
+    let dangling = &mut report.dangling_references;
+    for nb in &manifest.notebooks {
+        if let Some(parent) = nb.parent_id.filter(|id| !notebooks.contains(id)) {
+            dangling.push(format!("notebook {}: parent {}", nb.id, parent));
+        }
+    }
+    for note in &manifest.notes {
+        if let Some(notebook) = note.notebook_id.filter(|id| !notebooks.contains(id)) {
+            dangling.push(format!("note {}: notebook {}", note.id, notebook));
+        }
+    }
+    for tag in &manifest.tags {
+        if let Some(parent) = tag.parent_id.filter(|id| !tags.contains(id)) {
+            dangling.push(format!("tag {}: parent {}", tag.id, parent));
+        }
+    }
+    for link in &manifest.note_tags {
+        if !notes.contains(&link.note_id) || !tags.contains(&link.tag_id) {
+            dangling.push(format!("note_tag {}-{}", link.note_id, link.tag_id));
+        }
+    }
+    for link in &manifest.note_files {
+        if !notes.contains(&link.note_id) || !files.contains(&link.file_id) {
+            dangling.push(format!("note_file {}-{}", link.note_id, link.file_id));
+        }
+    }
+    for att in &manifest.attachments {
+        if !notes.contains(&att.note_id) {
+            dangling.push(format!("attachment {}: note {}", att.id, att.note_id));
+        }
+    }
+    for (note_id, content) in &contents {
+        for id in html_attachment_refs(content) {
+            if attachments.get(&id) != Some(note_id) {
+                dangling.push(format!("note {}: attachment {}", note_id, id));
+            }
+        }
+    }
+}
+
+/// Checks an Evernote JSON export and its assets folder without importing it.
+pub fn preflight_evernote_data(data: &Value, assets_dir: &Path) -> ImportPreflightReport {
+    let list = |key: &str| -> Vec<Value> {
+        data.get(key)
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default()
+    };
+    let notebooks = list("notebooks");
+    let notes = list("notes");
+    let tags = list("tags");
+    let note_tags = list("noteTags");
+    let attachments = list("attachments");
+    let id_of = |item: &Value| item.get("id").and_then(value_to_string);
+
+    let mut report = ImportPreflightReport::default();
+    let duplicates = &mut report.duplicate_ids;
+    find_duplicates("notebook", notebooks.iter().filter_map(id_of), duplicates);
+    find_duplicates("note", notes.iter().filter_map(id_of), duplicates);
+    find_duplicates("tag", tags.iter().filter_map(id_of), duplicates);
+    find_duplicates(
+        "attachment",
+        attachments
+            .iter()
+            .filter_map(|att| id_of(att.get("attachmentFields").unwrap_or(att))),
+        duplicates,
+    );
+
+    let notebook_ids: HashSet<String> = notebooks.iter().filter_map(id_of).collect();
+    let note_ids: HashSet<String> = notes.iter().filter_map(id_of).collect();
+    let tag_ids: HashSet<String> = tags.iter().filter_map(id_of).collect();
+    for note in &notes {
+        let id = id_of(note).unwrap_or_default();
+        let notebook = note
+            .get("notebookId")
+            .and_then(value_to_string)
+            .or_else(|| {
+                note.get("noteFields")
+                    .and_then(|v| v.get("parent_Notebook_id"))
+                    .and_then(value_to_string)
+            });
+        if let Some(notebook) = notebook.filter(|nb| !notebook_ids.contains(nb)) {
+            report
+                .dangling_references
+                .push(format!("note {}: notebook {}", id, notebook));
+        }
+        let content = note
+            .get("contentNormalized")
+            .and_then(|v| v.as_str())
+            .or_else(|| note.get("content").and_then(|v| v.as_str()))
+            .unwrap_or("");
+        report.estimated_bytes += content.len() as u64;
+        for rel in html_asset_refs(content) {
+            if !assets_dir.join(&rel).is_file() {
+                report
+                    .missing_assets
+                    .push(format!("note {}: files/{}", id, rel));
+            }
+        }
+    }
+    for tag in &tags {
+        let parent = tag
+            .get("parentId")
+            .and_then(value_to_string)
+            .or_else(|| tag.get("parent_Tag_id").and_then(value_to_string));
+        if let Some(parent) = parent.filter(|p| !tag_ids.contains(p)) {
+            report.dangling_references.push(format!(
+                "tag {}: parent {}",
+                id_of(tag).unwrap_or_default(),
+                parent
+            ));
+        }
+    }
+    for link in &note_tags {
+        let note = link
+            .get("note_id")
+            .or_else(|| link.get("noteId"))
+            .or_else(|| link.get("Note_id"))
+            .and_then(value_to_string)
+            .unwrap_or_default();
+        let tag = link
+            .get("tag_id")
+            .or_else(|| link.get("tagId"))
+            .or_else(|| link.get("Tag_id"))
+            .and_then(value_to_string)
+            .unwrap_or_default();
+        if !note_ids.contains(&note) || !tag_ids.contains(&tag) {
+            report
+                .dangling_references
+                .push(format!("note_tag {}-{}", note, tag));
+        }
+    }
+    for attachment in &attachments {
+        let fields = attachment.get("attachmentFields").unwrap_or(attachment);
+        let id = id_of(fields).unwrap_or_default();
+        let note = attachment
+            .get("noteId")
+            .or_else(|| fields.get("parent_Note_id"))
+            .and_then(value_to_string)
+            .unwrap_or_default();
+        if !note_ids.contains(&note) {
+            report
+                .dangling_references
+                .push(format!("attachment {}: note {}", id, note));
+        }
+        let rel = attachment
+            .get("localFile")
+            .and_then(|v| v.get("relPath"))
+            .and_then(|v| v.as_str());
+        if let Some(rel) = rel {
+            if !assets_dir.join(rel).is_file() {
+                report
+                    .missing_files
+                    .push(format!("attachment {}: {}", id, rel));
+            }
+        }
+    }
+    // The importer copies the whole assets folder, referenced or not.
+    report.estimated_bytes += get_dir_size(assets_dir.to_string_lossy().to_string()).unwrap_or(0);
+    report
+}
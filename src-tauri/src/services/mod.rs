@@ -1,4 +1,6 @@
+mod api;
 mod app;
+mod attachment_text;
 mod export;
 mod files;
 mod history;
@@ -8,13 +10,20 @@ mod menu;
 mod notebooks;
 mod notes;
 mod ocr;
+mod package;
 mod pdf;
 pub mod prelude;
+mod revisions;
+mod saved_searches;
 mod storage;
 mod tags;
+mod templates;
+mod trash;
 mod utils;
 
+pub use api::*;
 pub use app::*;
+pub use attachment_text::*;
 pub use export::*;
 pub use files::*;
 pub use history::*;
@@ -24,7 +33,12 @@ pub use menu::*;
 pub use notebooks::*;
 pub use notes::*;
 pub use ocr::*;
+pub use package::*;
 pub use pdf::*;
+pub use revisions::*;
+pub use saved_searches::*;
 pub use storage::*;
 pub use tags::*;
+pub use templates::*;
+pub use trash::*;
 pub use utils::*;
@@ -18,7 +18,7 @@ pub async fn create_notebook(
     let repo = SqliteRepository {
         pool: state.pool.clone(),
     };
-    repo.create_notebook(&name, parentId)
+    repo.create_notebook(&name, parentId, notebook_layout(&state.settings_dir))
         .await
         .map_err(|e| e.to_string())
 }
@@ -42,6 +42,33 @@ pub async fn delete_notebook(id: i64, state: State<'_, AppState>) -> Result<(),
     };
     repo.delete_notebook(id).await.map_err(|e| e.to_string())
 }
+#[tauri::command]
+pub async fn get_trashed_notebooks(
+    state: State<'_, AppState>,
+) -> Result<Vec<TrashedNotebook>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.get_trashed_notebooks()
+        .await
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+pub async fn restore_notebook(id: i64, state: State<'_, AppState>) -> Result<(), String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.restore_notebook(id).await.map_err(|e| e.to_string())
+}
+#[tauri::command]
+pub async fn delete_trashed_notebook(id: i64, state: State<'_, AppState>) -> Result<(), String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.delete_trashed_notebook(id, &state.data_dir)
+        .await
+        .map_err(|e| e.to_string())
+}
 #[allow(non_snake_case)]
 #[tauri::command]
 pub async fn move_notebook(
@@ -53,7 +80,12 @@ pub async fn move_notebook(
     let repo = SqliteRepository {
         pool: state.pool.clone(),
     };
-    repo.move_notebook(notebookId, parentId, index)
-        .await
-        .map_err(|e| e.to_string())
+    repo.move_notebook(
+        notebookId,
+        parentId,
+        index,
+        notebook_layout(&state.settings_dir),
+    )
+    .await
+    .map_err(|e| e.to_string())
 }
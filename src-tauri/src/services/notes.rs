@@ -41,6 +41,20 @@ pub async fn get_notes_by_tag(
         .await
         .map_err(|e| e.to_string())
 }
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn get_notes_by_tags(
+    tagIds: Vec<i64>,
+    matchAll: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<Vec<NoteListItem>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.get_notes_by_tags(&tagIds, matchAll.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
 #[tauri::command]
 pub async fn get_trashed_notes(state: State<'_, AppState>) -> Result<Vec<NoteListItem>, String> {
     let repo = SqliteRepository {
@@ -53,14 +67,14 @@ pub async fn get_trashed_notes(state: State<'_, AppState>) -> Result<Vec<NoteLis
 pub async fn search_notes(
     query: String,
     notebookId: Option<i64>,
+    sort: Option<SearchSort>,
     state: State<'_, AppState>,
-) -> Result<Vec<NoteListItem>, String> {
+) -> Result<Vec<SearchResult>, SearchError> {
     let repo = SqliteRepository {
         pool: state.pool.clone(),
     };
-    repo.search_notes(&query, notebookId)
+    repo.search_notes(&query, notebookId, sort.unwrap_or_default())
         .await
-        .map_err(|e| e.to_string())
 }
 #[tauri::command]
 pub async fn get_note(id: i64, state: State<'_, AppState>) -> Result<Option<Note>, String> {
@@ -110,6 +124,23 @@ pub async fn set_note_external_id(
         .await
         .map_err(|e| e.to_string())
 }
+/// Applies the `searchTokenizer` and `language` settings, rebuilding the
+/// index or restemming notes if they changed.
+#[tauri::command]
+pub async fn rebuild_search_index(state: State<'_, AppState>) -> Result<bool, String> {
+    let rebuilt =
+        crate::db::ensure_fts_tokenizer(&state.pool, search_tokenizer(&state.settings_dir)).await?;
+    let language = crate::db::StemLanguage::from_code(&resolve_language(&state.settings_dir));
+    crate::db::set_stem_language(&state.pool, language).await?;
+    Ok(rebuilt)
+}
+#[tauri::command]
+pub async fn reindex_notes_text(state: State<'_, AppState>) -> Result<i64, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.reindex_notes_text().await.map_err(|e| e.to_string())
+}
 #[tauri::command]
 pub async fn get_note_counts(state: State<'_, AppState>) -> Result<NoteCounts, String> {
     let repo = SqliteRepository {
@@ -135,9 +166,17 @@ pub async fn upsert_note(
     };
     match id {
         Some(id) => {
-            repo.update_note(id, &title, &content, notebookId, &state.data_dir)
-                .await
-                .map_err(|e| e.to_string())?;
+            let revisions = read_revision_policy(&state.settings_dir);
+            repo.update_note(
+                id,
+                &title,
+                &content,
+                notebookId,
+                &state.data_dir,
+                &revisions,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
             Ok(id)
         }
         None => repo
@@ -169,6 +208,126 @@ pub async fn restore_note(id: i64, state: State<'_, AppState>) -> Result<(), Str
     };
     repo.restore_note(id).await.map_err(|e| e.to_string())
 }
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn move_notes(
+    noteIds: Vec<i64>,
+    notebookId: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<BulkNoteResult>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.move_notes(&noteIds, notebookId)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn trash_notes(
+    noteIds: Vec<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<BulkNoteResult>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.trash_notes(&noteIds).await.map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn restore_notes(
+    noteIds: Vec<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<BulkNoteResult>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.restore_notes(&noteIds)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn delete_notes(
+    noteIds: Vec<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<BulkNoteResult>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.delete_notes(&noteIds, &state.data_dir)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn tag_notes(
+    noteIds: Vec<i64>,
+    tagId: i64,
+    state: State<'_, AppState>,
+) -> Result<Vec<BulkNoteResult>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.tag_notes(tagId, &noteIds)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn untag_notes(
+    noteIds: Vec<i64>,
+    tagId: i64,
+    state: State<'_, AppState>,
+) -> Result<Vec<BulkNoteResult>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.untag_notes(tagId, &noteIds)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn duplicate_notes(
+    noteIds: Vec<i64>,
+    state: State<'_, AppState>,
+) -> Result<Vec<BulkNoteResult>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    let content_addressed = content_addressed_files(&state.settings_dir);
+    repo.duplicate_notes(&noteIds, &state.data_dir, |filename, mime, bytes| {
+        store_note_bytes(&state.data_dir, filename, mime, bytes, content_addressed)
+            .map(|stored| stored.rel_path)
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+/// Copies a note together with its own copies of its images and attachments;
+/// returns the new note's id.
+#[tauri::command]
+pub async fn duplicate_note(
+    id: i64,
+    title: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<i64, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    let content_addressed = content_addressed_files(&state.settings_dir);
+    repo.duplicate_note(
+        id,
+        title.as_deref(),
+        &state.data_dir,
+        |filename, mime, bytes| {
+            store_note_bytes(&state.data_dir, filename, mime, bytes, content_addressed)
+                .map(|stored| stored.rel_path)
+        },
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
 #[tauri::command]
 pub async fn restore_all_notes(state: State<'_, AppState>) -> Result<(), String> {
     let repo = SqliteRepository {
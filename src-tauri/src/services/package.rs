@@ -0,0 +1,241 @@
+use crate::services::prelude::*;
+use crate::services::ExportManifest;
+use std::io::Write;
+
+pub const PACKAGE_ARCHIVE_EXTENSION: &str = "ncx";
+pub const PACKAGE_MANIFEST_NAME: &str = "manifest.json";
+
+fn copy_hashed(reader: &mut dyn Read, writer: &mut dyn Write) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0u8; 65536];
+    loop {
+        let size = reader.read(&mut chunk)?;
+        if size == 0 {
+            break;
+        }
+        hasher.update(&chunk[..size]);
+        writer.write_all(&chunk[..size])?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn verify_checksum(rel: &str, actual: &str, expected: Option<&str>) -> Result<(), String> {
+    match expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(actual) => {
+            Err(format!("{}: checksum mismatch", rel))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn is_package_archive(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            ext.eq_ignore_ascii_case(PACKAGE_ARCHIVE_EXTENSION) || ext.eq_ignore_ascii_case("zip")
+        })
+        .unwrap_or(false)
+}
+
+/// Destination of an export package: either the classic folder tree or a
+/// single `.ncx` zip archive. Members are streamed and hashed as they are written.
+pub enum PackageWriter {
+    Dir(PathBuf),
+    Archive {
+        path: PathBuf,
+        zip: zip::ZipWriter<fs::File>,
+    },
+}
+
+impl PackageWriter {
+    pub fn create_dir(root: PathBuf) -> Result<Self, String> {
+        for sub in ["notes", "attachments", "files"] {
+            fs::create_dir_all(root.join(sub)).map_err(|e| e.to_string())?;
+        }
+        Ok(PackageWriter::Dir(root))
+    }
+    pub fn create_archive(path: PathBuf) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let file = fs::File::create(&path).map_err(|e| e.to_string())?;
+        Ok(PackageWriter::Archive {
+            path,
+            zip: zip::ZipWriter::new(file),
+        })
+    }
+    pub fn root(&self) -> &Path {
+        match self {
+            PackageWriter::Dir(root) => root,
+            PackageWriter::Archive { path, .. } => path,
+        }
+    }
+    pub fn manifest_path(&self) -> PathBuf {
+        match self {
+            PackageWriter::Dir(root) => root.join(PACKAGE_MANIFEST_NAME),
+            PackageWriter::Archive { path, .. } => path.clone(),
+        }
+    }
+    fn write_from(
+        &mut self,
+        rel: &str,
+        reader: &mut dyn Read,
+        size: u64,
+    ) -> Result<String, String> {
+        match self {
+            PackageWriter::Dir(root) => {
+                let target = root.join(rel);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+                let mut file = fs::File::create(&target).map_err(|e| e.to_string())?;
+                copy_hashed(reader, &mut file).map_err(|e| e.to_string())
+            }
+            PackageWriter::Archive { zip, .. } => {
+                let options = zip::write::FileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .large_file(size >= u32::MAX as u64);
+                zip.start_file(rel, options).map_err(|e| e.to_string())?;
+                copy_hashed(reader, zip).map_err(|e| e.to_string())
+            }
+        }
+    }
+    pub fn write_bytes(&mut self, rel: &str, bytes: &[u8]) -> Result<String, String> {
+        self.write_from(rel, &mut &bytes[..], bytes.len() as u64)
+    }
+    pub fn copy_file(&mut self, rel: &str, source: &Path) -> Result<String, String> {
+        let mut file = fs::File::open(source).map_err(|e| e.to_string())?;
+        let size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        self.write_from(rel, &mut file, size)
+    }
+    pub fn finish(self) -> Result<(), String> {
+        if let PackageWriter::Archive { mut zip, .. } = self {
+            let file = zip.finish().map_err(|e| e.to_string())?;
+            file.sync_all().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Source of an import package. Accepts a `manifest.json` path, the export
+/// folder itself or a `.ncx` archive.
+pub enum PackageReader {
+    Dir { root: PathBuf, manifest: String },
+    Archive(zip::ZipArchive<fs::File>),
+}
+
+impl PackageReader {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if path.as_os_str().is_empty() {
+            return Err("Manifest path is empty".to_string());
+        }
+        if !path.exists() {
+            return Err("Manifest file not found".to_string());
+        }
+        if path.is_dir() {
+            return Ok(PackageReader::Dir {
+                root: path.to_path_buf(),
+                manifest: PACKAGE_MANIFEST_NAME.to_string(),
+            });
+        }
+        if is_package_archive(path) {
+            let file = fs::File::open(path).map_err(|e| e.to_string())?;
+            let archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+            return Ok(PackageReader::Archive(archive));
+        }
+        let root = path
+            .parent()
+            .ok_or_else(|| "Export root not found".to_string())?;
+        let manifest = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| PACKAGE_MANIFEST_NAME.to_string());
+        Ok(PackageReader::Dir {
+            root: root.to_path_buf(),
+            manifest,
+        })
+    }
+    pub fn open_manifest(&mut self) -> Result<(Box<dyn Read + '_>, u64), String> {
+        let manifest = match self {
+            PackageReader::Dir { manifest, .. } => manifest.clone(),
+            PackageReader::Archive(_) => PACKAGE_MANIFEST_NAME.to_string(),
+        };
+        self.open_member(&manifest)
+    }
+    pub fn read_manifest(&mut self) -> Result<ExportManifest, String> {
+        let (reader, _) = self.open_manifest()?;
+        serde_json::from_reader(std::io::BufReader::new(reader)).map_err(|e| e.to_string())
+    }
+    pub fn open_member(&mut self, rel: &str) -> Result<(Box<dyn Read + '_>, u64), String> {
+        match self {
+            PackageReader::Dir { root, .. } => {
+                let file = fs::File::open(root.join(rel)).map_err(|e| e.to_string())?;
+                let size = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+                Ok((Box::new(file), size))
+            }
+            PackageReader::Archive(archive) => {
+                let member = archive
+                    .by_name(rel)
+                    .map_err(|e| format!("{}: {}", rel, e))?;
+                let size = member.size();
+                Ok((Box::new(member), size))
+            }
+        }
+    }
+    pub fn member_size(&mut self, rel: &str) -> Option<u64> {
+        match self {
+            PackageReader::Dir { root, .. } => fs::metadata(root.join(rel))
+                .ok()
+                .filter(|meta| meta.is_file())
+                .map(|meta| meta.len()),
+            PackageReader::Archive(archive) => {
+                archive.by_name(rel).ok().map(|member| member.size())
+            }
+        }
+    }
+    pub fn read_to_string(&mut self, rel: &str, expected: Option<&str>) -> Result<String, String> {
+        let (mut reader, size) = self.open_member(rel)?;
+        let mut buffer = Vec::with_capacity(size as usize);
+        let actual = copy_hashed(&mut reader, &mut buffer).map_err(|e| e.to_string())?;
+        verify_checksum(rel, &actual, expected)?;
+        String::from_utf8(buffer).map_err(|e| e.to_string())
+    }
+    /// Checks every member the manifest has a checksum for, and that every
+    /// note's HTML is present, before anything is written. A damaged package
+    /// is refused as a whole instead of importing some notes blank.
+    pub fn verify_members(&mut self, manifest: &ExportManifest) -> Result<(), String> {
+        for (rel, expected) in &manifest.checksums {
+            let (mut reader, _) = self.open_member(rel)?;
+            let actual =
+                copy_hashed(&mut reader, &mut std::io::sink()).map_err(|e| e.to_string())?;
+            verify_checksum(rel, &actual, Some(expected))?;
+        }
+        for note in &manifest.notes {
+            if !manifest.checksums.contains_key(&note.content_path)
+                && self.member_size(&note.content_path).is_none()
+            {
+                return Err(format!("{}: missing from the package", note.content_path));
+            }
+        }
+        Ok(())
+    }
+    pub fn extract(
+        &mut self,
+        rel: &str,
+        target: &Path,
+        expected: Option<&str>,
+    ) -> Result<(), String> {
+        let (mut reader, _) = self.open_member(rel)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut file = fs::File::create(target).map_err(|e| e.to_string())?;
+        let actual = copy_hashed(&mut reader, &mut file).map_err(|e| e.to_string())?;
+        if let Err(err) = verify_checksum(rel, &actual, expected) {
+            drop(file);
+            let _ = fs::remove_file(target);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
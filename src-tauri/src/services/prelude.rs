@@ -1,6 +1,8 @@
 pub use crate::db::{
-    Attachment, Note, NoteCounts, NoteHistoryItem, NoteListItem, Notebook, OcrFileItem, OcrStats,
-    SqliteRepository, Tag,
+    Attachment, AttachmentTextStats, BulkNoteResult, DedupeReport, FtsTokenizer, Note, NoteCounts,
+    NoteHistoryItem, NoteListItem, NoteRevision, NoteRevisionItem, NoteTemplate, Notebook,
+    NotebookLayout, OcrFileItem, OcrStats, RevisionPolicy, SavedSearch, SearchError, SearchResult,
+    SearchSort, SqliteRepository, Tag, TrashedNotebook,
 };
 pub use futures::StreamExt;
 pub use http::{Request, Response, StatusCode, Uri};
@@ -8,7 +10,7 @@ pub use regex::Regex;
 pub use reqwest;
 pub use serde_json::Value;
 pub use sha2::{Digest, Sha256};
-pub use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+pub use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
 pub use std::fs;
 pub use std::io::Read;
 pub use std::path::{Path, PathBuf};
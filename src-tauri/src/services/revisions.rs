@@ -0,0 +1,134 @@
+use super::*;
+use crate::services::prelude::*;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionDiffChunk {
+    pub kind: String,
+    pub text: String,
+}
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteRevisionDiff {
+    pub from_title: String,
+    pub to_title: String,
+    pub from_created_at: i64,
+    pub to_created_at: i64,
+    pub chunks: Vec<RevisionDiffChunk>,
+}
+pub fn read_revision_policy(settings_dir: &Path) -> RevisionPolicy {
+    let value = read_settings_file(settings_dir).unwrap_or(Value::Null);
+    let mut policy = RevisionPolicy::default();
+    let Some(obj) = value.as_object() else {
+        return policy;
+    };
+    if let Some(enabled) = obj.get("revisionsEnabled").and_then(|v| v.as_bool()) {
+        policy.enabled = enabled;
+    }
+    if let Some(minutes) = obj.get("revisionIntervalMinutes").and_then(|v| v.as_i64()) {
+        policy.min_interval_seconds = minutes.max(0) * 60;
+    }
+    if let Some(count) = obj.get("revisionMaxPerNote").and_then(|v| v.as_i64()) {
+        policy.max_per_note = count.max(0);
+    }
+    if let Some(days) = obj.get("revisionRetentionDays").and_then(|v| v.as_i64()) {
+        policy.max_age_days = days.max(0);
+    }
+    policy
+}
+pub fn diff_note_text(from: &str, to: &str) -> Vec<RevisionDiffChunk> {
+    let from_text = strip_html(from);
+    let to_text = strip_html(to);
+    let diff = similar::TextDiff::from_words(from_text.as_str(), to_text.as_str());
+    let mut chunks: Vec<RevisionDiffChunk> = Vec::new();
+    for change in diff.iter_all_changes() {
+        let kind = match change.tag() {
+            similar::ChangeTag::Equal => "equal",
+            similar::ChangeTag::Insert => "insert",
+            similar::ChangeTag::Delete => "delete",
+        };
+        match chunks.last_mut() {
+            Some(last) if last.kind == kind => last.text.push_str(change.value()),
+            _ => chunks.push(RevisionDiffChunk {
+                kind: kind.to_string(),
+                text: change.value().to_string(),
+            }),
+        }
+    }
+    chunks
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn get_note_revisions(
+    noteId: i64,
+    state: State<'_, AppState>,
+) -> Result<Vec<NoteRevisionItem>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.get_note_revisions(noteId)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+pub async fn get_note_revision(
+    id: i64,
+    state: State<'_, AppState>,
+) -> Result<Option<NoteRevision>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.get_note_revision(id).await.map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn diff_note_revisions(
+    fromId: i64,
+    toId: Option<i64>,
+    state: State<'_, AppState>,
+) -> Result<NoteRevisionDiff, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    let from = repo
+        .get_note_revision(fromId)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Revision not found".to_string())?;
+    let (to_title, to_content, to_created_at) = match toId {
+        Some(id) => {
+            let to = repo
+                .get_note_revision(id)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| "Revision not found".to_string())?;
+            (to.title, to.content, to.created_at)
+        }
+        None => {
+            let note = repo
+                .get_note(from.note_id)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| "Note not found".to_string())?;
+            (note.title, note.content, note.updated_at)
+        }
+    };
+    Ok(NoteRevisionDiff {
+        chunks: diff_note_text(&from.content, &to_content),
+        from_title: from.title,
+        to_title,
+        from_created_at: from.created_at,
+        to_created_at,
+    })
+}
+#[tauri::command]
+pub async fn restore_note_revision(id: i64, state: State<'_, AppState>) -> Result<i64, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    let policy = read_revision_policy(&state.settings_dir);
+    repo.restore_note_revision(id, &state.data_dir, &policy)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Revision not found".to_string())
+}
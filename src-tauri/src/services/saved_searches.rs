@@ -0,0 +1,70 @@
+use super::*;
+use crate::services::prelude::*;
+
+#[tauri::command]
+pub async fn get_saved_searches(state: State<'_, AppState>) -> Result<Vec<SavedSearch>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.get_saved_searches().await.map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn create_saved_search(
+    name: String,
+    query: String,
+    notebookId: Option<i64>,
+    sort: Option<SearchSort>,
+    state: State<'_, AppState>,
+) -> Result<i64, SearchError> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.create_saved_search(&name, &query, notebookId, sort.unwrap_or_default())
+        .await
+}
+#[tauri::command]
+pub async fn rename_saved_search(
+    id: i64,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.rename_saved_search(id, &name)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+pub async fn move_saved_search(
+    id: i64,
+    index: usize,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.move_saved_search(id, index)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+pub async fn delete_saved_search(id: i64, state: State<'_, AppState>) -> Result<(), String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.delete_saved_search(id)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+pub async fn run_saved_search(
+    id: i64,
+    state: State<'_, AppState>,
+) -> Result<Vec<SearchResult>, SearchError> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.run_saved_search(id).await
+}
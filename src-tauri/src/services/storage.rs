@@ -50,6 +50,48 @@ pub fn read_storage_override(settings_dir: &Path) -> Result<Option<PathBuf>, Str
     }
     Ok(Some(PathBuf::from(path_str)))
 }
+pub fn content_addressed_files(settings_dir: &Path) -> bool {
+    read_settings_file(settings_dir)
+        .ok()
+        .and_then(|value| value.get("contentAddressedFiles").and_then(|v| v.as_bool()))
+        .unwrap_or(false)
+}
+/// `nestedNotebooks` in `app.json` opts into notebooks inside notebooks.
+pub fn notebook_layout(settings_dir: &Path) -> NotebookLayout {
+    let nested = read_settings_file(settings_dir)
+        .ok()
+        .and_then(|value| value.get("nestedNotebooks").and_then(|v| v.as_bool()))
+        .unwrap_or(false);
+    if nested {
+        NotebookLayout::Nested
+    } else {
+        NotebookLayout::Stacks
+    }
+}
+pub fn search_tokenizer(settings_dir: &Path) -> FtsTokenizer {
+    read_settings_file(settings_dir)
+        .ok()
+        .and_then(|value| {
+            value
+                .get("searchTokenizer")
+                .and_then(|v| v.as_str())
+                .and_then(|v| v.parse().ok())
+        })
+        .unwrap_or_default()
+}
+/// Opens the library in `data_dir` with the notebook layout, search tokenizer
+/// and stemming language from `settings_dir`. The app and the CLI both open
+/// libraries through here.
+pub async fn open_library(data_dir: &Path, settings_dir: &Path) -> Result<SqlitePool, String> {
+    let options = crate::db::DbOptions {
+        layout: notebook_layout(settings_dir),
+        tokenizer: search_tokenizer(settings_dir),
+    };
+    let pool = crate::db::init_db_with_options(data_dir, options).await?;
+    let language = crate::db::StemLanguage::from_code(&resolve_language(settings_dir));
+    crate::db::set_stem_language(&pool, language).await?;
+    Ok(pool)
+}
 #[tauri::command]
 pub fn get_storage_override(state: State<'_, AppState>) -> Result<Option<String>, String> {
     let override_dir = read_storage_override(&state.settings_dir)?;
@@ -94,3 +94,45 @@ pub async fn rename_tag(
         .await
         .map_err(|e| e.to_string())
 }
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn merge_tags(
+    sourceId: i64,
+    targetId: i64,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.merge_tags(sourceId, targetId)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn add_tag_to_notes(
+    tagId: i64,
+    noteIds: Vec<i64>,
+    state: State<'_, AppState>,
+) -> Result<u64, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.add_tag_to_notes(tagId, &noteIds)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn remove_tag_from_notes(
+    tagId: i64,
+    noteIds: Vec<i64>,
+    state: State<'_, AppState>,
+) -> Result<u64, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.remove_tag_from_notes(tagId, &noteIds)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,73 @@
+use super::*;
+use crate::services::prelude::*;
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+#[tauri::command]
+pub async fn get_note_templates(state: State<'_, AppState>) -> Result<Vec<NoteTemplate>, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.get_note_templates().await.map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn create_note_template(
+    name: String,
+    title: String,
+    content: String,
+    notebookId: Option<i64>,
+    tagIds: Vec<i64>,
+    state: State<'_, AppState>,
+) -> Result<i64, String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.create_note_template(&name, &title, &content, notebookId, &tagIds)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn update_note_template(
+    id: i64,
+    name: String,
+    title: String,
+    content: String,
+    notebookId: Option<i64>,
+    tagIds: Vec<i64>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.update_note_template(id, &name, &title, &content, notebookId, &tagIds)
+        .await
+        .map_err(|e| e.to_string())
+}
+#[tauri::command]
+pub async fn delete_note_template(id: i64, state: State<'_, AppState>) -> Result<(), String> {
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.delete_note_template(id)
+        .await
+        .map_err(|e| e.to_string())
+}
+/// `{{clipboard}}` is filled from the system clipboard here, so the frontend
+/// only picks the template and, optionally, a notebook other than its default.
+#[allow(non_snake_case)]
+#[tauri::command]
+pub async fn create_note_from_template(
+    templateId: i64,
+    notebookId: Option<i64>,
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<i64, String> {
+    let clipboard = app_handle.clipboard().read_text().unwrap_or_default();
+    let repo = SqliteRepository {
+        pool: state.pool.clone(),
+    };
+    repo.create_note_from_template(templateId, notebookId, &clipboard, &state.data_dir)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,85 @@
+use super::*;
+use crate::services::prelude::*;
+
+const TRASH_PURGE_INTERVAL_SECS: u64 = 60 * 60;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashPurgePreview {
+    pub retention_days: i64,
+    /// Notes trashed before this time are purged; `None` while retention is off.
+    pub deleted_before: Option<i64>,
+    pub notes: Vec<NoteListItem>,
+}
+
+/// `trashRetentionDays` from `app.json`; 0 (the default) keeps trashed notes
+/// until the trash is emptied by hand.
+pub fn read_trash_retention_days(settings_dir: &Path) -> i64 {
+    read_settings_file(settings_dir)
+        .ok()
+        .and_then(|value| value.get("trashRetentionDays").and_then(|v| v.as_i64()))
+        .unwrap_or(0)
+        .max(0)
+}
+
+fn trash_cutoff(settings_dir: &Path) -> Option<i64> {
+    let days = read_trash_retention_days(settings_dir);
+    (days > 0).then(|| {
+        chrono::Utc::now()
+            .timestamp()
+            .saturating_sub(days.saturating_mul(24 * 60 * 60))
+    })
+}
+
+/// Deletes notes that have been in the trash longer than the retention
+/// period. Returns the number of notes deleted.
+pub async fn purge_expired_trash(
+    pool: &SqlitePool,
+    settings_dir: &Path,
+    data_dir: &Path,
+) -> Result<i64, String> {
+    let Some(cutoff) = trash_cutoff(settings_dir) else {
+        return Ok(0);
+    };
+    let repo = SqliteRepository { pool: pool.clone() };
+    repo.purge_trashed_notes(cutoff, data_dir)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Purges expired trash at startup and then hourly; the setting is re-read
+/// each time, so changing it needs no restart.
+pub fn start_trash_purger(pool: SqlitePool, settings_dir: PathBuf, data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            if let Err(err) = purge_expired_trash(&pool, &settings_dir, &data_dir).await {
+                eprintln!("trash purge failed: {}", err);
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(TRASH_PURGE_INTERVAL_SECS)).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_trash_purge_preview(
+    state: State<'_, AppState>,
+) -> Result<TrashPurgePreview, String> {
+    let retention_days = read_trash_retention_days(&state.settings_dir);
+    let deleted_before = trash_cutoff(&state.settings_dir);
+    let notes = match deleted_before {
+        Some(cutoff) => {
+            let repo = SqliteRepository {
+                pool: state.pool.clone(),
+            };
+            repo.get_trashed_notes_before(cutoff)
+                .await
+                .map_err(|e| e.to_string())?
+        }
+        None => Vec::new(),
+    };
+    Ok(TrashPurgePreview {
+        retention_days,
+        deleted_before,
+        notes,
+    })
+}
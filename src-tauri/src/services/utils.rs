@@ -1,26 +1,7 @@
 use super::*;
 use crate::services::prelude::*;
 
-pub fn strip_html(input: &str) -> String {
-    let mut output = String::with_capacity(input.len());
-    let mut in_tag = false;
-    for ch in input.chars() {
-        match ch {
-            '<' => in_tag = true,
-            '>' => in_tag = false,
-            _ => {
-                if !in_tag {
-                    output.push(ch);
-                }
-            }
-        }
-    }
-    output
-        .replace('\u{00a0}', " ")
-        .split_whitespace()
-        .collect::<Vec<_>>()
-        .join(" ")
-}
+pub use crate::db::{replace_attachment_ids, strip_html};
 pub fn path_to_file_url(path: &Path) -> String {
     let raw = path.to_string_lossy().replace('\\', "/");
     format!("file:///{}", urlencoding::encode(&raw))
@@ -0,0 +1,174 @@
+mod common;
+
+use common::{seeded_repo, TempDir};
+use notes_classic_lib::services::serve_api;
+use serde_json::Value;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const TOKEN: &str = "secret-token";
+
+struct Reply {
+    status: u16,
+    head: String,
+    body: String,
+}
+
+impl Reply {
+    fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+    fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+}
+
+async fn send(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Reply {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await.unwrap();
+    let raw = String::from_utf8_lossy(&raw).to_string();
+    let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    Reply {
+        status,
+        head: head.to_string(),
+        body: body.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn api_requires_the_token_and_serves_notes() {
+    let data_dir = TempDir::new("api");
+    let settings_dir = TempDir::new("api-settings");
+    let repo = seeded_repo(
+        &data_dir,
+        &[
+            "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+             VALUES (1, 'Inbox', 0, NULL, 'notebook', 0)",
+        ],
+    )
+    .await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(serve_api(
+        listener,
+        repo.pool.clone(),
+        settings_dir.to_path_buf(),
+        data_dir.to_path_buf(),
+        TOKEN.to_string(),
+    ));
+    let bearer = format!("Bearer {}", TOKEN);
+    let auth = [("Authorization", bearer.as_str())];
+
+    assert_eq!(
+        send(addr, "GET", "/api/notebooks", &[], b"").await.status,
+        401
+    );
+    let wrong = send(
+        addr,
+        "GET",
+        "/api/notebooks",
+        &[("X-Api-Token", "secret-tokeN")],
+        b"",
+    )
+    .await;
+    assert_eq!(wrong.status, 401);
+
+    // Preflights carry no token and must still be answered.
+    let preflight = send(addr, "OPTIONS", "/api/notes", &[], b"").await;
+    assert_eq!(preflight.status, 204);
+    assert_eq!(preflight.header("access-control-allow-origin"), Some("*"));
+    assert!(preflight
+        .header("access-control-allow-headers")
+        .unwrap()
+        .contains("Authorization"));
+
+    let notebooks = send(addr, "GET", "/api/notebooks", &auth, b"").await;
+    assert_eq!(notebooks.status, 200);
+    assert_eq!(notebooks.header("access-control-allow-origin"), Some("*"));
+    assert_eq!(notebooks.json()[0]["name"], "Inbox");
+
+    let created = send(
+        addr,
+        "POST",
+        "/api/notes",
+        &[("X-Api-Token", TOKEN), ("Content-Type", "application/json")],
+        br#"{"title":"Clipped","content":"<p>Saved from the web</p>","notebookId":1}"#,
+    )
+    .await;
+    assert_eq!(created.status, 201);
+    let id = created.json()["id"].as_i64().unwrap();
+    let path = format!("/api/notes/{}", id);
+
+    let note = send(addr, "GET", &path, &auth, b"").await;
+    assert_eq!(note.status, 200);
+    assert_eq!(note.json()["title"], "Clipped");
+    assert_eq!(note.json()["notebookId"], 1);
+
+    let updated = send(addr, "PUT", &path, &auth, br#"{"title":"Clipped page"}"#).await;
+    assert_eq!(updated.status, 200);
+    let note = repo.get_note(id).await.unwrap().unwrap();
+    assert_eq!(note.title, "Clipped page");
+    assert_eq!(note.content, "<p>Saved from the web</p>");
+
+    let hits = send(addr, "GET", "/api/search?q=web", &auth, b"").await;
+    assert_eq!(hits.status, 200);
+    assert_eq!(hits.json()[0]["id"], id);
+    let bad_sort = send(addr, "GET", "/api/search?q=web&sort=size", &auth, b"").await;
+    assert_eq!(bad_sort.status, 400);
+
+    let upload = send(
+        addr,
+        "POST",
+        "/api/files?filename=shot.png&mime=image/png",
+        &auth,
+        b"png-bytes",
+    )
+    .await;
+    assert_eq!(upload.status, 201);
+    let src = upload.json()["src"].as_str().unwrap().to_string();
+    assert!(src.starts_with("files/"), "{}", src);
+    assert_eq!(std::fs::read(data_dir.join(&src)).unwrap(), b"png-bytes");
+
+    assert_eq!(
+        send(addr, "GET", "/api/notes/999", &auth, b"").await.status,
+        404
+    );
+    assert_eq!(
+        send(addr, "GET", "/api/notes/abc", &auth, b"").await.status,
+        400
+    );
+    assert_eq!(
+        send(addr, "GET", "/api/unknown", &auth, b"").await.status,
+        404
+    );
+
+    let deleted = send(addr, "DELETE", &path, &auth, b"").await;
+    assert_eq!(deleted.status, 200);
+    assert_eq!(repo.get_trashed_notes().await.unwrap().len(), 1);
+
+    server.abort();
+    repo.pool.close().await;
+}
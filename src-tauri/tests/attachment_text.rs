@@ -0,0 +1,118 @@
+mod common;
+
+use common::{seeded_repo, write_file, TempDir};
+use notes_classic_lib::db::SearchSort;
+use notes_classic_lib::services::{extract_document_text, index_attachment_texts};
+use std::io::Write;
+
+fn zipped(parts: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, content) in parts {
+        zip.start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[test]
+fn extracts_text_from_office_and_text_documents() {
+    let docx = zipped(&[
+        (
+            "word/document.xml",
+            r#"<w:document xmlns:w="w"><w:body>
+                <w:p><w:r><w:t>Quarterly</w:t></w:r><w:r><w:t xml:space="preserve"> rev</w:t></w:r><w:r><w:t>enue</w:t></w:r></w:p>
+                <w:p><w:r><w:instrText>PAGE</w:instrText><w:t>Fish &amp; chips</w:t></w:r></w:p>
+            </w:body></w:document>"#,
+        ),
+        (
+            "word/styles.xml",
+            r#"<w:styles xmlns:w="w"><w:t>ignored</w:t></w:styles>"#,
+        ),
+    ]);
+    assert_eq!(
+        extract_document_text(&docx, "report.docx", "").unwrap(),
+        "Quarterly revenue Fish & chips"
+    );
+
+    let xlsx = zipped(&[(
+        "xl/sharedStrings.xml",
+        r#"<sst><si><t>Budget</t></si><si><t>Travel</t></si></sst>"#,
+    )]);
+    assert_eq!(
+        extract_document_text(
+            &xlsx,
+            "sheet",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+        )
+        .unwrap(),
+        "Budget Travel"
+    );
+
+    let odt = zipped(&[(
+        "content.xml",
+        r#"<office:document-content xmlns:office="o" xmlns:text="t"><office:body><office:text>
+            <text:h>Minutes</text:h><text:p>Next<text:s/>steps</text:p>
+        </office:text></office:body></office:document-content>"#,
+    )]);
+    assert_eq!(
+        extract_document_text(&odt, "minutes.odt", "").unwrap(),
+        "Minutes Next steps"
+    );
+
+    assert_eq!(
+        extract_document_text(b"<p>end</p><p>start</p>", "page.html", "").unwrap(),
+        "end start"
+    );
+    assert_eq!(
+        extract_document_text(b"a,b\n1,2\n", "data", "text/csv").unwrap(),
+        "a,b 1,2"
+    );
+    assert!(extract_document_text(b"PK", "broken.docx", "").is_err());
+    assert!(extract_document_text(b"\x89PNG", "photo.png", "image/png").is_err());
+}
+
+#[tokio::test]
+async fn indexes_pending_attachments_for_search() {
+    let data_dir = TempDir::new("attachment-text");
+    write_file(
+        &data_dir,
+        "files/attachments/1/notes.txt",
+        b"Invoices are due on Friday",
+    );
+    let repo = seeded_repo(
+        &data_dir,
+        &[
+            "INSERT INTO notes (id, title, content, created_at, updated_at)
+             VALUES (1, 'Accounts', '<p>See attachments</p>', 0, 0)",
+            "INSERT INTO attachments (id, note_id, filename, mime, size, local_path, is_attachment, created_at, updated_at)
+             VALUES (1, 1, 'notes.txt', 'text/plain', 26, 'files/attachments/1/notes.txt', 1, 0, 0),
+                    (2, 1, 'missing.pdf', 'application/pdf', 10, 'files/attachments/1/missing.pdf', 1, 0, 0),
+                    (3, 1, 'photo.png', 'image/png', 10, 'files/attachments/1/photo.png', 0, 0, 0)",
+        ],
+    )
+    .await;
+
+    assert_eq!(
+        index_attachment_texts(&repo.pool, &data_dir, 10)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        index_attachment_texts(&repo.pool, &data_dir, 10)
+            .await
+            .unwrap(),
+        0
+    );
+    let stats = repo.get_attachment_text_stats().await.unwrap();
+    assert_eq!((stats.total, stats.done, stats.failed), (2, 1, 1));
+    let results = repo
+        .search_notes("invoice", None, SearchSort::Relevance)
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].attachment_match);
+
+    repo.pool.close().await;
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::{seeded_repo, write_file, TempDir};
+use notes_classic_lib::db::{RevisionPolicy, SqliteRepository};
+use sha2::{Digest, Sha256};
+
+fn blob_path(bytes: &[u8], ext: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(bytes));
+    format!("{}/{}.{}", &hash[0..2], hash, ext)
+}
+
+async fn note_content(repo: &SqliteRepository, id: i64) -> String {
+    repo.get_note(id).await.unwrap().unwrap().content
+}
+
+#[tokio::test]
+async fn dedupe_points_notes_and_revisions_at_one_blob() {
+    let data_dir = TempDir::new("blobs-dedupe");
+    let repo = seeded_repo(
+        &data_dir,
+        &[
+            "INSERT INTO notes (id, title, content, created_at, updated_at)
+             VALUES (1, 'Plain', '<img src=\"files/a/one.png\"><p>see files/a/one.png</p>', 0, 0),
+                    (2, 'Asset', '<img src=''http://asset.localhost/C%3A%2Fdata%2Ffiles%2Fb%2Ftwo%20copy.png''>', 0, 0)",
+            "INSERT INTO note_revisions (id, note_id, title, content, created_at)
+             VALUES (1, 1, 'Plain', '<img src=\"notes-file://files/a/one.png\">', 0)",
+            "INSERT INTO ocr_files (id, file_path) VALUES (1, 'a/one.png'), (2, 'b/two copy.png')",
+            "INSERT INTO note_files (note_id, file_id) VALUES (1, 1), (2, 2)",
+            "INSERT INTO note_revision_files (revision_id, file_id) VALUES (1, 1)",
+            "INSERT INTO attachments (id, note_id, filename, mime, size, local_path)
+             VALUES (1, 1, 'empty.txt', 'text/plain', 0, 'files/c/empty.txt')",
+        ],
+    )
+    .await;
+    write_file(&data_dir, "files/a/one.png", b"same-bytes");
+    write_file(&data_dir, "files/b/two copy.png", b"same-bytes");
+    write_file(&data_dir, "files/c/empty.txt", b"");
+
+    let report = repo.deduplicate_note_files(&data_dir).await.unwrap();
+    assert_eq!(report.files_scanned, 3);
+    assert_eq!(report.files_missing, 0);
+    assert_eq!(report.files_unreadable, 0);
+    assert_eq!(report.files_removed, 3);
+    assert_eq!(report.bytes_reclaimed, 10);
+    assert_eq!(report.notes_rewritten, 2);
+
+    let png = blob_path(b"same-bytes", "png");
+    assert_eq!(
+        note_content(&repo, 1).await,
+        format!("<img src=\"files/{}\"><p>see files/a/one.png</p>", png)
+    );
+    assert_eq!(
+        note_content(&repo, 2).await,
+        format!(
+            "<img src='http://asset.localhost/C%3A%2Fdata%2Ffiles%2F{}'>",
+            urlencoding::encode(&png)
+        )
+    );
+    let revision = repo.get_note_revision(1).await.unwrap().unwrap();
+    assert_eq!(
+        revision.content,
+        format!("<img src=\"notes-file://files/{}\">", png)
+    );
+
+    let files: Vec<(i64, String)> = sqlx::query_as("SELECT id, file_path FROM ocr_files")
+        .fetch_all(&repo.pool)
+        .await
+        .unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].1, png);
+    let (linked,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM note_files WHERE file_id = ?")
+        .bind(files[0].0)
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+    assert_eq!(linked, 2);
+
+    let empty = format!("files/{}", blob_path(b"", "txt"));
+    let attachment = repo.get_attachment(1).await.unwrap().unwrap();
+    assert_eq!(attachment.local_path, empty);
+    assert!(data_dir.join(&empty).exists());
+    assert!(data_dir.join("files").join(&png).exists());
+    assert!(!data_dir.join("files/a/one.png").exists());
+    assert!(!data_dir.join("files/b/two copy.png").exists());
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn shared_blobs_outlive_all_but_their_last_reference() {
+    let data_dir = TempDir::new("blobs-shared");
+    let png = blob_path(b"image", "png");
+    let pdf = format!("files/{}", blob_path(b"document", "pdf"));
+    let seed = [
+        format!(
+            "INSERT INTO notes (id, title, content, created_at, updated_at)
+             VALUES (1, 'First', '<img src=\"files/{png}\"><div data-attachment-id=\"1\"></div>', 0, 0),
+                    (2, 'Second', '<img src=\"files/{png}\"><div data-attachment-id=\"2\"></div>', 0, 0)",
+            png = png
+        ),
+        format!("INSERT INTO ocr_files (id, file_path) VALUES (1, '{}')", png),
+        "INSERT INTO note_files (note_id, file_id) VALUES (1, 1), (2, 1)".to_string(),
+        format!(
+            "INSERT INTO attachments (id, note_id, filename, mime, size, local_path)
+             VALUES (1, 1, 'doc.pdf', 'application/pdf', 8, '{pdf}'),
+                    (2, 2, 'doc.pdf', 'application/pdf', 8, '{pdf}')",
+            pdf = pdf
+        ),
+    ];
+    let seed: Vec<&str> = seed.iter().map(String::as_str).collect();
+    let repo = seeded_repo(&data_dir, &seed).await;
+    write_file(&data_dir, &format!("files/{}", png), b"image");
+    write_file(&data_dir, &pdf, b"document");
+    let blob_exists = |rel: &str| data_dir.join(rel).exists();
+
+    repo.delete_note(1, &data_dir).await.unwrap();
+    assert!(blob_exists(&format!("files/{}", png)));
+    assert!(blob_exists(&pdf));
+
+    // Dropping the image and attachment from the HTML keeps them for the
+    // revision that still shows them.
+    let policy = RevisionPolicy {
+        min_interval_seconds: 0,
+        ..RevisionPolicy::default()
+    };
+    repo.update_note(2, "Second", "<p>Text only</p>", None, &data_dir, &policy)
+        .await
+        .unwrap();
+    assert_eq!(repo.get_note_revisions(2).await.unwrap().len(), 1);
+    assert!(blob_exists(&format!("files/{}", png)));
+    assert!(blob_exists(&pdf));
+
+    repo.delete_note(2, &data_dir).await.unwrap();
+    assert!(!blob_exists(&format!("files/{}", png)));
+    assert!(!blob_exists(&pdf));
+
+    repo.pool.close().await;
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use common::{seeded_repo, write_file, TempDir};
+use notes_classic_lib::db::{BulkNoteResult, SqliteRepository};
+use std::fs;
+use std::path::Path;
+
+/// Work (1) holds notes 1 and 2, Home (2) holds note 3, note 4 was trashed
+/// from Work. Note 2 has an attachment stored at `files/aa/report.pdf`.
+const SEED: &[&str] = &[
+    "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+     VALUES (1, 'Work', 0, NULL, 'notebook', 0), (2, 'Home', 0, NULL, 'notebook', 1)",
+    "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+     VALUES (1, 'Plan', '<p>plan</p>', 0, 1, 1),
+            (2, 'Report', '<p>report</p><div data-attachment-id=\"1\"></div>', 0, 2, 1),
+            (3, 'Groceries', '<p>milk</p>', 0, 3, 2),
+            (4, 'Old plan', '<p>old</p>', 0, 4, NULL)",
+    "UPDATE notes SET deleted_at = 10, deleted_from_notebook_id = 1 WHERE id = 4",
+    "INSERT INTO tags (id, name, parent_id, created_at, updated_at)
+     VALUES (1, 'Urgent', NULL, 0, 0)",
+    "INSERT INTO note_tags (note_id, tag_id) VALUES (1, 1)",
+    "INSERT INTO attachments (id, note_id, filename, mime, size, local_path)
+     VALUES (1, 2, 'report.pdf', 'application/pdf', 3, 'files/aa/report.pdf')",
+];
+
+fn outcomes(results: &[BulkNoteResult]) -> Vec<(i64, Option<&str>)> {
+    results
+        .iter()
+        .map(|result| (result.note_id, result.error.as_deref()))
+        .collect()
+}
+
+/// Stands in for `store_note_bytes`: writes each file under `files/copies/`.
+fn store_copy(data_dir: &Path) -> impl FnMut(&str, &str, &[u8]) -> Result<String, String> + Send {
+    let data_dir = data_dir.to_path_buf();
+    let mut count = 0;
+    move |filename, _mime, bytes| {
+        count += 1;
+        let rel = format!("copies/{}-{}", count, filename);
+        let path = data_dir.join("files").join(&rel);
+        fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+        fs::write(path, bytes).map_err(|e| e.to_string())?;
+        Ok(rel)
+    }
+}
+
+async fn notebook_of(repo: &SqliteRepository, id: i64) -> Option<i64> {
+    repo.get_note(id).await.unwrap().unwrap().notebook_id
+}
+
+#[tokio::test]
+async fn bulk_operations_report_each_note() {
+    let data_dir = TempDir::new("bulk-notes");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    write_file(&data_dir, "files/aa/report.pdf", b"pdf");
+
+    let moved = repo.move_notes(&[1, 3, 4, 99, 1], Some(2)).await.unwrap();
+    assert_eq!(
+        outcomes(&moved),
+        vec![
+            (1, None),
+            (3, None),
+            (4, Some("Note is in the trash")),
+            (99, Some("Note not found")),
+        ]
+    );
+    assert_eq!(notebook_of(&repo, 1).await, Some(2));
+    assert!(repo.move_notes(&[1], Some(42)).await.is_err());
+    assert_eq!(notebook_of(&repo, 1).await, Some(2));
+
+    let trashed = repo.trash_notes(&[1, 4]).await.unwrap();
+    assert_eq!(
+        outcomes(&trashed),
+        vec![(1, None), (4, Some("Note is in the trash"))]
+    );
+    let restored = repo.restore_notes(&[1, 3, 4]).await.unwrap();
+    assert_eq!(
+        outcomes(&restored),
+        vec![(1, None), (3, Some("Note is not in the trash")), (4, None)]
+    );
+    assert_eq!(notebook_of(&repo, 1).await, Some(2));
+    assert_eq!(notebook_of(&repo, 4).await, Some(1));
+
+    let tagged = repo.tag_notes(1, &[1, 3, 99]).await.unwrap();
+    assert_eq!(
+        outcomes(&tagged),
+        vec![(1, None), (3, None), (99, Some("Note not found"))]
+    );
+    assert!(repo.tag_notes(42, &[1]).await.is_err());
+    let untagged = repo.untag_notes(1, &[3]).await.unwrap();
+    assert_eq!(outcomes(&untagged), vec![(3, None)]);
+    assert!(repo.get_note_tags(3).await.unwrap().is_empty());
+
+    let duplicated = repo
+        .duplicate_notes(&[1], &data_dir, store_copy(&data_dir))
+        .await
+        .unwrap();
+    let copy = duplicated[0].new_note_id.unwrap();
+    let copy_note = repo.get_note(copy).await.unwrap().unwrap();
+    assert_eq!(copy_note.title, "Plan");
+    assert_eq!(copy_note.notebook_id, Some(2));
+    let tags = repo.get_note_tags(copy).await.unwrap();
+    assert_eq!(tags.len(), 1);
+    assert_eq!(tags[0].name, "Urgent");
+
+    let deleted = repo.delete_notes(&[2, 3, 99], &data_dir).await.unwrap();
+    assert_eq!(
+        outcomes(&deleted),
+        vec![(2, None), (3, None), (99, Some("Note not found"))]
+    );
+    assert!(repo.get_note(2).await.unwrap().is_none());
+    assert!(!data_dir.join("files/aa/report.pdf").exists());
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn duplicate_note_copies_files_and_attachments() {
+    let data_dir = TempDir::new("duplicate-note");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    write_file(&data_dir, "files/aa/report.pdf", b"pdf");
+    sqlx::query(
+        "UPDATE notes SET content = '<p>report</p><img src=\"files/bb/chart.png\">
+         <div data-attachment-id=\"1\"></div>' WHERE id = 2",
+    )
+    .execute(&repo.pool)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO note_tags (note_id, tag_id) VALUES (2, 1)")
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+    write_file(&data_dir, "files/bb/chart.png", b"png");
+
+    let copy = repo
+        .duplicate_note(2, Some("Report copy"), &data_dir, store_copy(&data_dir))
+        .await
+        .unwrap();
+    let note = repo.get_note(copy).await.unwrap().unwrap();
+    assert_eq!(note.title, "Report copy");
+    assert_eq!(note.notebook_id, Some(1));
+    let attachments: Vec<(i64, String, String)> =
+        sqlx::query_as("SELECT id, filename, local_path FROM attachments WHERE note_id = ?")
+            .bind(copy)
+            .fetch_all(&repo.pool)
+            .await
+            .unwrap();
+    assert_eq!(attachments.len(), 1);
+    let (attachment_id, filename, local_path) = &attachments[0];
+    assert_ne!(*attachment_id, 1);
+    assert_eq!(filename, "report.pdf");
+    assert_eq!(local_path, "files/copies/2-report.pdf");
+    assert_eq!(
+        note.content,
+        format!(
+            "<p>report</p><img src=\"files/copies/1-chart.png\">
+         <div data-attachment-id=\"{}\"></div>",
+            attachment_id
+        )
+    );
+    let files: Vec<(String,)> = sqlx::query_as(
+        "SELECT f.file_path FROM note_files nf JOIN ocr_files f ON f.id = nf.file_id
+         WHERE nf.note_id = ?",
+    )
+    .bind(copy)
+    .fetch_all(&repo.pool)
+    .await
+    .unwrap();
+    assert_eq!(files, vec![("copies/1-chart.png".to_string(),)]);
+    assert_eq!(repo.get_note_tags(copy).await.unwrap()[0].name, "Urgent");
+
+    // Deleting the original leaves the copy's files in place.
+    repo.delete_note(2, &data_dir).await.unwrap();
+    assert!(!data_dir.join("files/aa/report.pdf").exists());
+    assert_eq!(
+        fs::read(data_dir.join("files/copies/2-report.pdf")).unwrap(),
+        b"pdf"
+    );
+    assert_eq!(
+        fs::read(data_dir.join("files/copies/1-chart.png")).unwrap(),
+        b"png"
+    );
+    assert!(repo
+        .duplicate_note(2, None, &data_dir, store_copy(&data_dir))
+        .await
+        .is_err());
+
+    repo.pool.close().await;
+}
@@ -0,0 +1,201 @@
+mod common;
+
+use common::{seeded_repo, TempDir};
+use notes_classic_lib::cli::run_cli_with;
+use notes_classic_lib::db::init_db;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const SEED: &[&str] = &[
+    "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+     VALUES (1, 'Inbox', 0, NULL, 'notebook', 0)",
+    "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+     VALUES (1, 'Tram', '<p>Tram 28</p>', 0, 0, 1)",
+];
+
+/// Runs the CLI against `data_dir` with an empty settings folder.
+fn cli(data_dir: &Path, settings_dir: &Path, args: &[&str]) -> ExitCode {
+    let mut raw = vec![
+        "--data-dir".to_string(),
+        data_dir.to_string_lossy().to_string(),
+        "--settings-dir".to_string(),
+        settings_dir.to_string_lossy().to_string(),
+    ];
+    raw.extend(args.iter().map(|arg| arg.to_string()));
+    run_cli_with(raw)
+}
+
+/// Runs `sql` against the library between CLI invocations.
+fn query(data_dir: &Path, sql: &str) -> Vec<(String,)> {
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let pool = init_db(data_dir).await.unwrap();
+        let rows = sqlx::query_as(sql).fetch_all(&pool).await.unwrap();
+        pool.close().await;
+        rows
+    })
+}
+
+fn titles(data_dir: &Path) -> Vec<String> {
+    query(data_dir, "SELECT title FROM notes ORDER BY id")
+        .into_iter()
+        .map(|(title,)| title)
+        .collect()
+}
+
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn cli_reports_usage_errors_with_exit_codes() {
+    let data_dir = TempDir::new("cli-usage");
+    let settings_dir = TempDir::new("cli-usage-settings");
+
+    assert_eq!(run_cli_with(Vec::<String>::new()), ExitCode::from(2));
+    assert_eq!(run_cli_with(vec!["--help".to_string()]), ExitCode::SUCCESS);
+    assert_eq!(
+        run_cli_with(vec!["--data-dir".to_string()]),
+        ExitCode::from(2)
+    );
+
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["frobnicate"]),
+        ExitCode::FAILURE
+    );
+    // Unknown commands are rejected before the library is created.
+    assert!(!data_dir.join("notes.db").exists());
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["export"]),
+        ExitCode::FAILURE
+    );
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["import"]),
+        ExitCode::FAILURE
+    );
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["search"]),
+        ExitCode::FAILURE
+    );
+    assert_eq!(
+        cli(
+            &data_dir,
+            &settings_dir,
+            &["search", "tram", "--sort", "size"]
+        ),
+        ExitCode::FAILURE
+    );
+    assert_eq!(
+        cli(
+            &data_dir,
+            &settings_dir,
+            &["search", "tram", "--notebook", "x"]
+        ),
+        ExitCode::FAILURE
+    );
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["search", "tram"]),
+        ExitCode::SUCCESS
+    );
+}
+
+#[test]
+fn cli_exports_imports_and_maintains_the_library() {
+    let data_dir = TempDir::new("cli-library");
+    let settings_dir = TempDir::new("cli-library-settings");
+    let export_dir = TempDir::new("cli-library-export");
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        seeded_repo(&data_dir, SEED).await.pool.close().await;
+    });
+
+    assert_eq!(
+        cli(
+            &data_dir,
+            &settings_dir,
+            &["export", &export_dir.to_string_lossy()]
+        ),
+        ExitCode::SUCCESS
+    );
+    let packages = entries(&export_dir);
+    assert_eq!(packages.len(), 1);
+    let manifest = packages[0].join("manifest.json");
+    assert!(manifest.is_file());
+    let manifest = manifest.to_string_lossy().to_string();
+
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["backup"]),
+        ExitCode::SUCCESS
+    );
+    let backups = entries(&data_dir.join("backups"));
+    assert_eq!(backups.len(), 1);
+    assert!(backups[0].join("notes.db").is_file());
+
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["vacuum"]),
+        ExitCode::SUCCESS
+    );
+
+    query(&data_dir, "DELETE FROM notes_text");
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["reindex"]),
+        ExitCode::SUCCESS
+    );
+    assert_eq!(
+        query(&data_dir, "SELECT plain_text FROM notes_text"),
+        vec![("Tram 28".to_string(),)]
+    );
+
+    query(&data_dir, "UPDATE notes SET title = 'Changed'");
+    assert_eq!(
+        cli(
+            &data_dir,
+            &settings_dir,
+            &["import", &manifest, "--dry-run"]
+        ),
+        ExitCode::SUCCESS
+    );
+    assert_eq!(titles(&data_dir), vec!["Changed"]);
+
+    assert_eq!(
+        cli(&data_dir, &settings_dir, &["import", &manifest]),
+        ExitCode::SUCCESS
+    );
+    assert_eq!(titles(&data_dir), vec!["Tram"]);
+    assert!(entries(&data_dir.join("backups"))
+        .iter()
+        .any(|dir| dir.to_string_lossy().contains("cli-import-")));
+
+    assert_eq!(
+        cli(
+            &data_dir,
+            &settings_dir,
+            &["import", &manifest, "--merge", "--no-backup"]
+        ),
+        ExitCode::SUCCESS
+    );
+    assert_eq!(titles(&data_dir), vec!["Tram"]);
+
+    assert_eq!(
+        cli(
+            &data_dir,
+            &settings_dir,
+            &["import", &manifest, &manifest, "--merge", "--no-backup"]
+        ),
+        ExitCode::FAILURE
+    );
+    let missing = export_dir.join("missing/manifest.json");
+    assert_eq!(
+        cli(
+            &data_dir,
+            &settings_dir,
+            &["import", &missing.to_string_lossy(), "--no-backup"]
+        ),
+        ExitCode::FAILURE
+    );
+    assert_eq!(titles(&data_dir), vec!["Tram"]);
+}
@@ -0,0 +1,221 @@
+mod common;
+
+use common::{seeded_repo, write_file, TempDir};
+use notes_classic_lib::services::{import_evernote_json, EvernoteImportResult, ImportMode};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use std::fs;
+use std::path::Path;
+
+/// A library with a note, saved search and template of its own.
+const LOCAL: &[&str] = &[
+    "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+     VALUES (1, 'Inbox', 0, NULL, 'notebook', 0)",
+    "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+     VALUES (1, 'Local', '<p>Local note</p>', 50, 50, 1)",
+    "INSERT INTO saved_searches (id, name, query, created_at, updated_at)
+     VALUES (1, 'Open tasks', 'todo:false', 0, 0)",
+    "INSERT INTO note_templates (id, name, created_at, updated_at) VALUES (1, 'Meeting', 0, 0)",
+];
+
+/// An Evernote export with two notes in Travel / Trips; Lisbon has a tag
+/// and an image stored under `evernote/map.png`.
+fn export(lisbon_updated_at: i64, lisbon: &str) -> Value {
+    json!({
+        "stacks": [{ "id": "Stack:Travel", "name": "Travel" }],
+        "notebooks": [{ "id": "nb-1", "name": "Trips", "personal_Stack_id": "Stack:Travel" }],
+        "notes": [
+            {
+                "id": "note-1", "title": "Lisbon", "content": lisbon,
+                "createdAt": 100, "updatedAt": lisbon_updated_at, "notebookId": "nb-1"
+            },
+            {
+                "id": "note-2", "title": "Packing", "content": "<p>Socks</p>",
+                "createdAt": 100, "updatedAt": 100, "notebookId": "nb-1"
+            }
+        ],
+        "tags": [{ "id": "tag-1", "name": "travel" }],
+        "noteTags": [{ "noteId": "note-1", "tagId": "tag-1" }],
+        "attachments": [{
+            "noteId": "note-1", "filename": "map.png", "mime": "image/png", "dataSize": 3,
+            "localFile": { "relPath": "evernote/map.png" },
+            "attachmentFields": { "id": "att-1" }
+        }]
+    })
+}
+
+async fn import(
+    pool: &SqlitePool,
+    data_dir: &Path,
+    data: &Value,
+    assets_dir: &Path,
+    mode: ImportMode,
+) -> EvernoteImportResult {
+    let json_path = data_dir.join("evernote.json");
+    fs::write(&json_path, data.to_string()).unwrap();
+    import_evernote_json(pool, data_dir, &json_path, assets_dir, mode, false)
+        .await
+        .unwrap()
+}
+
+async fn count(pool: &SqlitePool, table: &str) -> i64 {
+    let (count,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    count
+}
+
+#[tokio::test]
+async fn evernote_merge_adds_new_notes_and_updates_newer_copies() {
+    let data_dir = TempDir::new("evernote-merge");
+    let assets_dir = TempDir::new("evernote-merge-assets");
+    write_file(&assets_dir, "evernote/map.png", b"png");
+    let repo = seeded_repo(&data_dir, LOCAL).await;
+
+    let first = import(
+        &repo.pool,
+        &data_dir,
+        &export(100, "<p>Tram 28</p>"),
+        &assets_dir,
+        ImportMode::Merge,
+    )
+    .await;
+    let summary = first.merge.unwrap();
+    assert_eq!(summary.notebooks.created, 2);
+    assert_eq!(summary.notes.created, 2);
+    assert_eq!(summary.tags.created, 1);
+    assert_eq!(summary.attachments.created, 1);
+    assert_eq!(count(&repo.pool, "notes").await, 3);
+    assert_eq!(repo.get_note(1).await.unwrap().unwrap().title, "Local");
+    assert_eq!(
+        fs::read(data_dir.join("files/evernote/map.png")).unwrap(),
+        b"png"
+    );
+
+    // The same export again matches every note by its Evernote id.
+    let again = import(
+        &repo.pool,
+        &data_dir,
+        &export(100, "<p>Tram 28</p>"),
+        &assets_dir,
+        ImportMode::Merge,
+    )
+    .await;
+    let summary = again.merge.unwrap();
+    assert_eq!(summary.notes.created + summary.notes.updated, 0);
+    assert_eq!(summary.notes.skipped, 2);
+    assert_eq!(summary.notebooks.skipped, 2);
+    assert_eq!(summary.tags.skipped, 1);
+    assert_eq!(count(&repo.pool, "notes").await, 3);
+
+    let edited = import(
+        &repo.pool,
+        &data_dir,
+        &export(200, "<p>Tram 28 and 15</p>"),
+        &assets_dir,
+        ImportMode::Merge,
+    )
+    .await;
+    let summary = edited.merge.unwrap();
+    assert_eq!(summary.notes.updated, 1);
+    assert_eq!(summary.notes.skipped, 1);
+    assert_eq!(summary.attachments.updated, 1);
+    let (content,): (String,) = sqlx::query_as("SELECT content FROM notes WHERE title = 'Lisbon'")
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+    assert_eq!(content, "<p>Tram 28 and 15</p>");
+    assert_eq!(count(&repo.pool, "notes").await, 3);
+    assert_eq!(count(&repo.pool, "attachments").await, 1);
+    assert_eq!(count(&repo.pool, "saved_searches").await, 1);
+    assert_eq!(count(&repo.pool, "note_templates").await, 1);
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn evernote_replace_clears_the_whole_library() {
+    let data_dir = TempDir::new("evernote-replace");
+    let assets_dir = TempDir::new("evernote-replace-assets");
+    write_file(&assets_dir, "evernote/map.png", b"png");
+    let repo = seeded_repo(&data_dir, LOCAL).await;
+
+    let result = import(
+        &repo.pool,
+        &data_dir,
+        &export(100, "<p>Tram 28</p>"),
+        &assets_dir,
+        ImportMode::Replace,
+    )
+    .await;
+    assert!(result.merge.is_none());
+    assert_eq!(result.notes, 2);
+    let titles: Vec<(String,)> = sqlx::query_as("SELECT title FROM notes ORDER BY title")
+        .fetch_all(&repo.pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        titles,
+        vec![("Lisbon".to_string(),), ("Packing".to_string(),)]
+    );
+    assert_eq!(count(&repo.pool, "saved_searches").await, 0);
+    assert_eq!(count(&repo.pool, "note_templates").await, 0);
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn evernote_dry_run_reports_problems_without_importing() {
+    let data_dir = TempDir::new("evernote-preflight");
+    let assets_dir = TempDir::new("evernote-preflight-assets");
+    let repo = seeded_repo(&data_dir, LOCAL).await;
+
+    let mut data = export(100, "<p>Tram 28</p><img src='files/evernote/tram.png'>");
+    data["notes"][1]["notebookId"] = json!("nb-9");
+    data["noteTags"]
+        .as_array_mut()
+        .unwrap()
+        .push(json!({ "noteId": "note-1", "tagId": "tag-9" }));
+    let json_path = data_dir.join("evernote.json");
+    fs::write(&json_path, data.to_string()).unwrap();
+
+    let result = import_evernote_json(
+        &repo.pool,
+        &data_dir,
+        &json_path,
+        &assets_dir,
+        ImportMode::Merge,
+        true,
+    )
+    .await
+    .unwrap();
+    assert_eq!(result.notes, 2);
+    assert!(result.merge.is_none());
+    let report = result.preflight.unwrap();
+    assert!(!report.is_clean());
+    assert_eq!(
+        report.missing_files,
+        vec!["attachment att-1: evernote/map.png".to_string()]
+    );
+    assert_eq!(
+        report.missing_assets,
+        vec!["note note-1: files/evernote/tram.png".to_string()]
+    );
+    assert_eq!(
+        report.dangling_references,
+        vec![
+            "note note-2: notebook nb-9".to_string(),
+            "note_tag note-1-tag-9".to_string()
+        ]
+    );
+    assert!(report.duplicate_ids.is_empty());
+
+    assert_eq!(count(&repo.pool, "notes").await, 1);
+    assert_eq!(count(&repo.pool, "notebooks").await, 1);
+    assert_eq!(count(&repo.pool, "tags").await, 0);
+    assert_eq!(count(&repo.pool, "attachments").await, 0);
+    assert!(!data_dir.join("files").exists());
+
+    repo.pool.close().await;
+}
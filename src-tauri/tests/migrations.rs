@@ -0,0 +1,180 @@
+mod common;
+
+use common::TempDir;
+use notes_classic_lib::db::{
+    init_db, init_db_with_options, DbOptions, FtsTokenizer, SearchSort, SqliteRepository,
+};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use std::fs;
+use std::path::Path;
+
+async fn open_raw(data_dir: &Path) -> SqlitePool {
+    let options = SqliteConnectOptions::new()
+        .filename(data_dir.join("notes.db"))
+        .create_if_missing(true);
+    SqlitePool::connect_with(options).await.unwrap()
+}
+
+/// Writes `notes.db` the way an older build left it: only notebooks and a
+/// bare notes table, and `schema_version` at `version` when the build kept one.
+async fn legacy_library(data_dir: &Path, version: Option<i64>) {
+    let pool = open_raw(data_dir).await;
+    for sql in [
+        "CREATE TABLE notebooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            parent_id INTEGER,
+            notebook_type TEXT NOT NULL DEFAULT 'stack',
+            sort_order INTEGER NOT NULL DEFAULT 0
+        )",
+        "CREATE TABLE notes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            notebook_id INTEGER
+        )",
+        "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type)
+         VALUES (1, 'Work', 0, NULL, 'stack'), (2, 'Projects', 0, 1, 'notebook'),
+                (3, 'Drafts', 0, 2, 'notebook')",
+        "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+         VALUES (1, 'Roadmap', '<p>Legacy plan</p><img src=\"notes-file://files/a.png\">', 0, 0, 3)",
+    ] {
+        sqlx::query(sql).execute(&pool).await.unwrap();
+    }
+    if let Some(version) = version {
+        sqlx::query("CREATE TABLE schema_version (version INTEGER NOT NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO schema_version (version) VALUES (?)")
+            .bind(version)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    pool.close().await;
+}
+
+async fn schema_version(pool: &SqlitePool) -> i64 {
+    sqlx::query_as::<_, (i64,)>("SELECT version FROM schema_version")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .0
+}
+
+fn backups(data_dir: &Path) -> Vec<String> {
+    fs::read_dir(data_dir.join("backups"))
+        .map(|entries| {
+            entries
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn fresh_library_records_every_migration_without_backup() {
+    let data_dir = TempDir::new("migrations-fresh");
+    let pool = init_db(&data_dir).await.unwrap();
+    let (latest, applied): (i64, i64) =
+        sqlx::query_as("SELECT MAX(version), COUNT(*) FROM schema_migrations")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(schema_version(&pool).await, latest);
+    assert!(applied > 1);
+    pool.close().await;
+
+    let pool = init_db(&data_dir).await.unwrap();
+    assert_eq!(schema_version(&pool).await, latest);
+    assert!(backups(&data_dir).is_empty());
+    pool.close().await;
+}
+
+#[tokio::test]
+async fn legacy_libraries_upgrade_from_before_numbered_migrations() {
+    for (label, version, from) in [
+        ("migrations-v1", Some(1), 1),
+        ("migrations-v2", Some(2), 2),
+        ("migrations-unversioned", None, 1),
+    ] {
+        let data_dir = TempDir::new(label);
+        legacy_library(&data_dir, version).await;
+
+        let pool = init_db(&data_dir).await.unwrap();
+        let fresh_dir = TempDir::new("migrations-reference");
+        let fresh = init_db(&fresh_dir).await.unwrap();
+        assert_eq!(schema_version(&pool).await, schema_version(&fresh).await);
+        fresh.close().await;
+
+        let repo = SqliteRepository { pool };
+        let note = repo.get_note(1).await.unwrap().unwrap();
+        assert_eq!(note.content, "<p>Legacy plan</p><img src=\"files/a.png\">");
+        let hits = repo
+            .search_notes("legacy", None, SearchSort::Relevance)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 1, "{}", label);
+        let (ocr_files,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM ocr_files")
+            .fetch_one(&repo.pool)
+            .await
+            .unwrap();
+        assert_eq!(ocr_files, 0);
+        // The one-time flattening moved Drafts up under the Work stack.
+        let (parent,): (Option<i64>,) =
+            sqlx::query_as("SELECT parent_id FROM notebooks WHERE id = 3")
+                .fetch_one(&repo.pool)
+                .await
+                .unwrap();
+        assert_eq!(parent, Some(1));
+
+        let backups = backups(&data_dir);
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].starts_with(&format!("pre-migration-v{}-", from)));
+        repo.pool.close().await;
+    }
+}
+
+async fn notes_fts_sql(pool: &SqlitePool) -> String {
+    sqlx::query_as::<_, (String,)>("SELECT sql FROM sqlite_master WHERE name = 'notes_fts'")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .0
+}
+
+#[tokio::test]
+async fn search_index_is_built_with_the_configured_tokenizer() {
+    let data_dir = TempDir::new("migrations-tokenizer");
+    let options = DbOptions {
+        tokenizer: FtsTokenizer::Trigram,
+        ..DbOptions::default()
+    };
+    let pool = init_db_with_options(&data_dir, options).await.unwrap();
+    assert!(notes_fts_sql(&pool).await.contains("trigram"));
+    pool.close().await;
+
+    // A setting changed since the last launch is applied on open.
+    let pool = init_db(&data_dir).await.unwrap();
+    assert!(notes_fts_sql(&pool).await.contains("unicode61"));
+    pool.close().await;
+}
+
+#[tokio::test]
+async fn newer_library_is_refused() {
+    let data_dir = TempDir::new("migrations-newer");
+    let pool = init_db(&data_dir).await.unwrap();
+    sqlx::query("UPDATE schema_version SET version = version + 1")
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let err = init_db(&data_dir).await.unwrap_err();
+    assert!(err.contains("newer than this build supports"), "{}", err);
+    assert!(backups(&data_dir).is_empty());
+}
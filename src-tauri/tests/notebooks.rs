@@ -0,0 +1,149 @@
+mod common;
+
+use common::TempDir;
+use notes_classic_lib::db::{
+    init_db, init_db_with_options, DbOptions, NotebookLayout, RepoError, SearchSort,
+    SqliteRepository,
+};
+
+async fn search(repo: &SqliteRepository, query: &str) -> Vec<i64> {
+    let mut ids: Vec<i64> = repo
+        .search_notes(query, None, SearchSort::Updated)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|note| note.id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+async fn parent_of(repo: &SqliteRepository, id: i64) -> Option<i64> {
+    repo.get_notebooks()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|notebook| notebook.id == id)
+        .unwrap()
+        .parent_id
+}
+
+#[tokio::test]
+async fn nested_layout_allows_deep_notebooks_without_cycles() {
+    let data_dir = TempDir::new("notebooks-nested");
+    let repo = SqliteRepository {
+        pool: init_db(&data_dir).await.unwrap(),
+    };
+    let nested = NotebookLayout::Nested;
+    let work = repo.create_notebook("Work", None, nested).await.unwrap();
+    let clients = repo
+        .create_notebook("Clients", Some(work), nested)
+        .await
+        .unwrap();
+    let acme = repo
+        .create_notebook("Acme", Some(clients), nested)
+        .await
+        .unwrap();
+    let invoices = repo
+        .create_notebook("Invoices", Some(acme), nested)
+        .await
+        .unwrap();
+    assert!(repo
+        .create_notebook("Drafts", Some(clients), NotebookLayout::Stacks)
+        .await
+        .is_err());
+
+    let deep = repo
+        .create_note("Acme invoice", "<p>invoice</p>", Some(invoices), &data_dir)
+        .await
+        .unwrap();
+    let shallow = repo
+        .create_note(
+            "Client list",
+            "<p>invoice list</p>",
+            Some(clients),
+            &data_dir,
+        )
+        .await
+        .unwrap();
+    let loose = repo
+        .create_note("Loose invoice", "<p>invoice</p>", None, &data_dir)
+        .await
+        .unwrap();
+
+    let mut in_work: Vec<i64> = repo
+        .get_all_notes(Some(work))
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|note| note.id)
+        .collect();
+    in_work.sort();
+    assert_eq!(in_work, vec![deep, shallow]);
+    assert_eq!(search(&repo, "stack:work").await, vec![deep, shallow]);
+    assert_eq!(search(&repo, "notebook:acme").await, vec![deep]);
+    assert_eq!(
+        search(&repo, "invoice -notebook:clients").await,
+        vec![loose]
+    );
+
+    assert!(matches!(
+        repo.move_notebook(clients, Some(invoices), 0, nested).await,
+        Err(RepoError::Invalid(_))
+    ));
+    assert!(matches!(
+        repo.move_notebook(clients, Some(clients), 0, nested).await,
+        Err(RepoError::Invalid(_))
+    ));
+    assert_eq!(parent_of(&repo, clients).await, Some(work));
+
+    repo.move_notebook(invoices, Some(clients), 0, nested)
+        .await
+        .unwrap();
+    assert_eq!(parent_of(&repo, invoices).await, Some(clients));
+    // The classic layout leaves notebooks under notebooks where they are.
+    repo.move_notebook(invoices, Some(acme), 0, NotebookLayout::Stacks)
+        .await
+        .unwrap();
+    assert_eq!(parent_of(&repo, invoices).await, Some(clients));
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn switching_back_to_stacks_flattens_nested_notebooks() {
+    let data_dir = TempDir::new("notebooks-switch");
+    let nested = DbOptions {
+        layout: NotebookLayout::Nested,
+        ..DbOptions::default()
+    };
+    let repo = SqliteRepository {
+        pool: init_db_with_options(&data_dir, nested).await.unwrap(),
+    };
+    let work = repo
+        .create_notebook("Work", None, NotebookLayout::Nested)
+        .await
+        .unwrap();
+    let clients = repo
+        .create_notebook("Clients", Some(work), NotebookLayout::Nested)
+        .await
+        .unwrap();
+    let acme = repo
+        .create_notebook("Acme", Some(clients), NotebookLayout::Nested)
+        .await
+        .unwrap();
+    repo.pool.close().await;
+
+    let repo = SqliteRepository {
+        pool: init_db_with_options(&data_dir, nested).await.unwrap(),
+    };
+    assert_eq!(parent_of(&repo, acme).await, Some(clients));
+    repo.pool.close().await;
+
+    let repo = SqliteRepository {
+        pool: init_db(&data_dir).await.unwrap(),
+    };
+    assert_eq!(parent_of(&repo, acme).await, Some(work));
+    assert_eq!(parent_of(&repo, clients).await, Some(work));
+    repo.pool.close().await;
+}
@@ -0,0 +1,1003 @@
+mod common;
+
+use common::{write_file, TempDir};
+use notes_classic_lib::db::init_db;
+use notes_classic_lib::services::{
+    export_notes_classic_package, import_notes_classic_chain, import_notes_classic_package,
+    merge_notes_classic_package, preflight_notes_classic_chain, ExportOptions, PackageProgress,
+};
+use sqlx::sqlite::{SqlitePool, SqliteRow};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+async fn exec(pool: &SqlitePool, sql: &str) {
+    sqlx::query(sql).execute(pool).await.unwrap();
+}
+
+async fn rows<T>(pool: &SqlitePool, sql: &str) -> Vec<T>
+where
+    T: for<'r> sqlx::FromRow<'r, SqliteRow> + Send + Unpin,
+{
+    sqlx::query_as(sql).fetch_all(pool).await.unwrap()
+}
+
+async fn seed(pool: &SqlitePool, data_dir: &Path) {
+    exec(
+        pool,
+        "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order, external_id)
+         VALUES (1, 'Work', 10, NULL, 'stack', 0, NULL),
+                (2, 'Projects', 11, 1, 'notebook', 1, 'nb-ext-2')",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO notes (id, title, content, created_at, updated_at, sync_status, remote_id, notebook_id, external_id, meta, content_hash, content_size, deleted_at, deleted_from_notebook_id)
+         VALUES (1, 'Plan', '<p>Quarterly plan <img src=\"files/images/chart.png\"></p>', 100, 200, 0, NULL, 2, 'note-ext-1', '{\"source\":\"test\"}', 'abc', 64, NULL, NULL),
+                (2, 'Old idea', '<p>Discarded</p>', 110, 120, 1, 'remote-2', NULL, NULL, NULL, NULL, NULL, 300, 4)",
+    )
+    .await;
+    // The Archive stack went to the trash with its notebook and Old idea.
+    exec(
+        pool,
+        "INSERT INTO trashed_notebooks (id, name, created_at, parent_id, notebook_type, sort_order, external_id, deleted_at, trash_root_id)
+         VALUES (3, 'Archive', 12, NULL, 'stack', 2, NULL, 300, 3),
+                (4, 'Old projects', 13, 3, 'notebook', 0, 'nb-ext-4', 300, 3)",
+    )
+    .await;
+    exec(
+        pool,
+        "UPDATE notes SET deleted_with_notebook_id = 3 WHERE id = 2",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO notes_text (note_id, title, plain_text)
+         VALUES (1, 'Plan', 'Quarterly plan'), (2, 'Old idea', 'Discarded')",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO tags (id, name, parent_id, created_at, updated_at, external_id)
+         VALUES (1, 'work', NULL, 10, 10, NULL), (2, 'urgent', 1, 12, 13, 'tag-ext-2')",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO note_tags (note_id, tag_id) VALUES (1, 1), (1, 2), (2, 1)",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO attachments (id, note_id, external_id, hash, filename, mime, size, width, height, local_path, source_url, is_attachment, created_at, updated_at)
+         VALUES (1, 1, 'att-ext-1', 'h1', 'report.txt', 'text/plain', 6, NULL, NULL, 'files/attachments/1/report.txt', NULL, 1, 100, 101)",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO ocr_files (id, file_path, attempts_left, last_error)
+         VALUES (1, 'images/chart.png', 2, 'timeout')",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO note_files (note_id, file_id) VALUES (1, 1)",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO ocr_text (file_id, lang, text, hash, updated_at)
+         VALUES (1, 'eng', 'revenue chart', 'ocr-hash', 150)",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO note_history (id, note_id, opened_at, note_title, notebook_id, notebook_name, stack_id, stack_name)
+         VALUES (1, 1, 500, 'Plan', 2, 'Projects', 1, 'Work'),
+                (2, 2, 510, 'Old idea', NULL, NULL, NULL, NULL)",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO saved_searches (id, name, query, notebook_id, sort, sort_order, created_at, updated_at)
+         VALUES (1, 'Plans', 'intitle:plan', 2, 'updated', 0, 600, 601)",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO note_templates (id, name, title, content, notebook_id, created_at, updated_at)
+         VALUES (1, 'Standup', 'Standup {{date}}', '<p>{{clipboard}}</p>', 2, 700, 701)",
+    )
+    .await;
+    exec(
+        pool,
+        "INSERT INTO note_template_tags (template_id, tag_id) VALUES (1, 2)",
+    )
+    .await;
+    write_file(data_dir, "files/attachments/1/report.txt", b"report");
+    write_file(data_dir, "files/images/chart.png", b"\x89PNG chart");
+}
+
+type NoteRow = (
+    i64,
+    String,
+    String,
+    i64,
+    i64,
+    Option<i64>,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+);
+type TrashedNotebookRow = (
+    i64,
+    String,
+    i64,
+    Option<i64>,
+    String,
+    i64,
+    Option<String>,
+    i64,
+    i64,
+);
+type TagRow = (i64, String, Option<i64>, i64, i64, Option<String>);
+type SavedSearchRow = (i64, String, String, Option<i64>, String, i64, i64, i64);
+type AttachmentRow = (
+    i64,
+    i64,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+);
+type TemplateRow = (i64, String, String, String, Option<i64>, Option<i64>);
+type HistoryRow = (
+    i64,
+    i64,
+    i64,
+    String,
+    Option<i64>,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+);
+
+const NOTES_SQL: &str = "SELECT id, title, content, created_at, updated_at, sync_status, remote_id, notebook_id, external_id, meta, content_hash, content_size, deleted_at, deleted_from_notebook_id, deleted_with_notebook_id FROM notes ORDER BY id";
+const TRASHED_NOTEBOOKS_SQL: &str = "SELECT id, name, created_at, parent_id, notebook_type, sort_order, external_id, deleted_at, trash_root_id FROM trashed_notebooks ORDER BY id";
+const TAGS_SQL: &str =
+    "SELECT id, name, parent_id, created_at, updated_at, external_id FROM tags ORDER BY id";
+const NOTE_TAGS_SQL: &str = "SELECT note_id, tag_id FROM note_tags ORDER BY note_id, tag_id";
+const ATTACHMENTS_SQL: &str = "SELECT id, note_id, external_id, hash, filename, mime, size, local_path, is_attachment, created_at, updated_at FROM attachments ORDER BY id";
+const OCR_TEXT_SQL: &str =
+    "SELECT file_id, lang, text, hash, updated_at FROM ocr_text ORDER BY file_id";
+const SAVED_SEARCHES_SQL: &str = "SELECT id, name, query, notebook_id, sort, sort_order, created_at, updated_at FROM saved_searches ORDER BY id";
+const TEMPLATES_SQL: &str = "SELECT t.id, t.name, t.title, t.content, t.notebook_id, tt.tag_id
+     FROM note_templates t LEFT JOIN note_template_tags tt ON tt.template_id = t.id ORDER BY t.id";
+const HISTORY_SQL: &str = "SELECT id, note_id, opened_at, note_title, notebook_id, notebook_name, stack_id, stack_name FROM note_history ORDER BY id";
+
+async fn assert_tables_match(source: &SqlitePool, target: &SqlitePool, target_dir: &Path) {
+    assert_eq!(
+        rows::<NoteRow>(source, NOTES_SQL).await,
+        rows::<NoteRow>(target, NOTES_SQL).await
+    );
+    assert_eq!(
+        rows::<TrashedNotebookRow>(source, TRASHED_NOTEBOOKS_SQL).await,
+        rows::<TrashedNotebookRow>(target, TRASHED_NOTEBOOKS_SQL).await
+    );
+    assert_eq!(
+        rows::<TagRow>(source, TAGS_SQL).await,
+        rows::<TagRow>(target, TAGS_SQL).await
+    );
+    assert_eq!(
+        rows::<(i64, i64)>(source, NOTE_TAGS_SQL).await,
+        rows::<(i64, i64)>(target, NOTE_TAGS_SQL).await
+    );
+    assert_eq!(
+        rows::<AttachmentRow>(source, ATTACHMENTS_SQL).await,
+        rows::<AttachmentRow>(target, ATTACHMENTS_SQL).await
+    );
+    assert_eq!(
+        rows::<(i64, String, String, String, i64)>(source, OCR_TEXT_SQL).await,
+        rows::<(i64, String, String, String, i64)>(target, OCR_TEXT_SQL).await
+    );
+    assert_eq!(
+        rows::<HistoryRow>(source, HISTORY_SQL).await,
+        rows::<HistoryRow>(target, HISTORY_SQL).await
+    );
+    assert_eq!(
+        rows::<SavedSearchRow>(source, SAVED_SEARCHES_SQL).await,
+        rows::<SavedSearchRow>(target, SAVED_SEARCHES_SQL).await
+    );
+    assert_eq!(
+        rows::<TemplateRow>(source, TEMPLATES_SQL).await,
+        rows::<TemplateRow>(target, TEMPLATES_SQL).await
+    );
+
+    assert_eq!(
+        fs::read(target_dir.join("files/attachments/1/report.txt")).unwrap(),
+        b"report"
+    );
+    assert_eq!(
+        fs::read(target_dir.join("files/images/chart.png")).unwrap(),
+        b"\x89PNG chart"
+    );
+
+    let hits: Vec<(i64,)> =
+        sqlx::query_as("SELECT rowid FROM notes_fts WHERE notes_fts MATCH 'quarterly'")
+            .fetch_all(target)
+            .await
+            .unwrap();
+    assert_eq!(hits, vec![(1,)]);
+}
+
+#[tokio::test]
+async fn export_then_import_preserves_every_table() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+    let target_dir = TempDir::new("target");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+
+    let export_events = Mutex::new(Vec::new());
+    let export = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions::default(),
+        &|event: PackageProgress| export_events.lock().unwrap().push(event.stage),
+    )
+    .await
+    .unwrap();
+    assert!(export.errors.is_empty(), "{:?}", export.errors);
+    assert_eq!(export.notes, 2);
+    assert!(!export_events.lock().unwrap().is_empty());
+
+    let target = init_db(&target_dir).await.unwrap();
+    let import_events = Mutex::new(Vec::new());
+    let import = import_notes_classic_package(
+        &target,
+        &target_dir,
+        Path::new(&export.manifest_path),
+        None,
+        &|event: PackageProgress| {
+            if event.state == "done" {
+                import_events.lock().unwrap().push(event.stage);
+            }
+        },
+    )
+    .await
+    .unwrap();
+    assert!(import.errors.is_empty(), "{:?}", import.errors);
+    assert_eq!(import.notes, 2);
+    assert_eq!(import.attachments, 1);
+    assert_eq!(import.images, 1);
+    assert!(import_events
+        .lock()
+        .unwrap()
+        .contains(&"database".to_string()));
+
+    assert_tables_match(&source, &target, &target_dir).await;
+
+    source.close().await;
+    target.close().await;
+}
+
+#[tokio::test]
+async fn archive_package_round_trips() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+    let target_dir = TempDir::new("target");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+
+    let export = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions {
+            archive: true,
+            ..ExportOptions::default()
+        },
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(export.errors.is_empty(), "{:?}", export.errors);
+    assert!(export.manifest_path.ends_with(".ncx"));
+    assert_eq!(fs::read_dir(&export_dir).unwrap().count(), 1);
+
+    let target = init_db(&target_dir).await.unwrap();
+    let import = import_notes_classic_package(
+        &target,
+        &target_dir,
+        Path::new(&export.manifest_path),
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(import.errors.is_empty(), "{:?}", import.errors);
+
+    assert_tables_match(&source, &target, &target_dir).await;
+
+    source.close().await;
+    target.close().await;
+}
+
+/// A library with one note and its image, for imports that must leave it be.
+async fn seed_local_library(pool: &SqlitePool, data_dir: &Path) {
+    exec(
+        pool,
+        "INSERT INTO notes (id, title, content, created_at, updated_at)
+         VALUES (1, 'Local', '<p>Kept <img src=\"files/local.png\"></p>', 0, 0)",
+    )
+    .await;
+    write_file(data_dir, "files/local.png", b"local");
+}
+
+async fn assert_local_library_kept(pool: &SqlitePool, data_dir: &Path) {
+    let notes: Vec<(i64, String)> = rows(pool, "SELECT id, title FROM notes").await;
+    assert_eq!(notes, vec![(1, "Local".to_string())]);
+    assert_eq!(
+        fs::read(data_dir.join("files/local.png")).unwrap(),
+        b"local"
+    );
+    assert!(!data_dir.join("files/attachments/1/report.txt").exists());
+}
+
+#[tokio::test]
+async fn import_rejects_members_with_bad_checksums() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+    let target_dir = TempDir::new("target");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+    let export = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions::default(),
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    fs::write(
+        Path::new(&export.export_root).join("attachments/1/report.txt"),
+        b"tampered",
+    )
+    .unwrap();
+
+    let target = init_db(&target_dir).await.unwrap();
+    seed_local_library(&target, &target_dir).await;
+    let err = import_notes_classic_package(
+        &target,
+        &target_dir,
+        Path::new(&export.manifest_path),
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap_err();
+    assert!(err.contains("checksum mismatch"), "{}", err);
+    assert_local_library_kept(&target, &target_dir).await;
+
+    source.close().await;
+    target.close().await;
+}
+
+/// Rewrites one member of a `.ncx` archive, leaving its manifest checksums as
+/// they were.
+fn replace_archive_member(archive_path: &Path, member: &str, bytes: &[u8]) {
+    let mut archive = zip::ZipArchive::new(fs::File::open(archive_path).unwrap()).unwrap();
+    let mut members = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).unwrap();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        members.push((entry.name().to_string(), data));
+    }
+    drop(archive);
+    let mut writer = zip::ZipWriter::new(fs::File::create(archive_path).unwrap());
+    for (name, data) in members {
+        writer
+            .start_file(name.as_str(), zip::write::FileOptions::default())
+            .unwrap();
+        let data = if name == member { bytes } else { &data[..] };
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+#[tokio::test]
+async fn archive_import_rejects_members_with_bad_checksums() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+    let target_dir = TempDir::new("target");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+    let export = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions {
+            archive: true,
+            ..ExportOptions::default()
+        },
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    let archive_path = Path::new(&export.manifest_path);
+    replace_archive_member(archive_path, "attachments/1/report.txt", b"tampered");
+
+    let target = init_db(&target_dir).await.unwrap();
+    seed_local_library(&target, &target_dir).await;
+    let err = import_notes_classic_package(
+        &target,
+        &target_dir,
+        archive_path,
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap_err();
+    assert!(err.contains("checksum mismatch"), "{}", err);
+    assert_local_library_kept(&target, &target_dir).await;
+
+    source.close().await;
+    target.close().await;
+}
+
+#[tokio::test]
+async fn incremental_chain_reconstructs_latest_state() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+    let target_dir = TempDir::new("target");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+    let full = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions::default(),
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+
+    exec(
+        &source,
+        "UPDATE notes SET content = '<p>Quarterly plan v2 <img src=\"files/images/chart.png\"></p>', updated_at = 400 WHERE id = 1",
+    )
+    .await;
+    exec(&source, "DELETE FROM notes WHERE id = 2").await;
+    exec(
+        &source,
+        "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+         VALUES (3, 'Fresh', '<p>New note</p>', 410, 410, 2)",
+    )
+    .await;
+    exec(
+        &source,
+        "INSERT INTO attachments (id, note_id, filename, mime, size, local_path, is_attachment, created_at, updated_at)
+         VALUES (2, 3, 'new.txt', 'text/plain', 3, 'files/attachments/2/new.txt', 1, 410, 410)",
+    )
+    .await;
+    exec(&source, "UPDATE tags SET name = 'priority' WHERE id = 2").await;
+    exec(&source, "DELETE FROM attachments WHERE id = 1").await;
+    write_file(&source_dir, "files/attachments/2/new.txt", b"new");
+
+    let incremental = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions {
+            archive: true,
+            base: Some(PathBuf::from(&full.manifest_path)),
+        },
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(incremental.errors.is_empty(), "{:?}", incremental.errors);
+    assert!(incremental.incremental);
+    assert_eq!(incremental.notes, 2);
+    assert_eq!(incremental.deleted_notes, 1);
+
+    let target = init_db(&target_dir).await.unwrap();
+    let import = import_notes_classic_package(
+        &target,
+        &target_dir,
+        Path::new(&incremental.manifest_path),
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await;
+    assert!(import.is_err());
+
+    let import = import_notes_classic_chain(
+        &target,
+        &target_dir,
+        Path::new(&full.manifest_path),
+        &[PathBuf::from(&incremental.manifest_path)],
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(import.errors.is_empty(), "{:?}", import.errors);
+    assert_eq!(import.notes, 2);
+    assert_eq!(import.deleted_notes, 1);
+
+    assert_tables_match(&source, &target, &target_dir).await;
+    assert_eq!(
+        fs::read(target_dir.join("files/attachments/2/new.txt")).unwrap(),
+        b"new"
+    );
+    // The attachment the incremental dropped leaves no blob behind.
+    assert!(!target_dir.join("files/attachments/1/report.txt").exists());
+    assert!(!target_dir.join("import-staging").exists());
+
+    source.close().await;
+    target.close().await;
+}
+
+#[tokio::test]
+async fn incremental_chain_rejects_packages_against_another_base() {
+    let source_dir = TempDir::new("source");
+    let first_dir = TempDir::new("export-first");
+    let second_dir = TempDir::new("export-second");
+    let target_dir = TempDir::new("target");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+    let first = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &first_dir,
+        &ExportOptions::default(),
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    exec(
+        &source,
+        "UPDATE notes SET title = 'Plan v2', updated_at = 400 WHERE id = 1",
+    )
+    .await;
+    let second = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &second_dir,
+        &ExportOptions::default(),
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    exec(
+        &source,
+        "UPDATE notes SET title = 'Plan v3', updated_at = 500 WHERE id = 1",
+    )
+    .await;
+    let incremental = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &second_dir,
+        &ExportOptions {
+            archive: false,
+            base: Some(PathBuf::from(&second.manifest_path)),
+        },
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(incremental.incremental);
+
+    let target = init_db(&target_dir).await.unwrap();
+    exec(
+        &target,
+        "INSERT INTO notes (id, title, content, created_at, updated_at)
+         VALUES (1, 'Local', '<p>Kept</p>', 0, 0)",
+    )
+    .await;
+    let err = import_notes_classic_chain(
+        &target,
+        &target_dir,
+        Path::new(&first.manifest_path),
+        &[PathBuf::from(&incremental.manifest_path)],
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        err.contains("was not exported against the previous package"),
+        "{}",
+        err
+    );
+    // The chain is checked before anything is replaced.
+    let titles: Vec<(String,)> = rows(&target, "SELECT title FROM notes").await;
+    assert_eq!(titles, vec![("Local".to_string(),)]);
+
+    let import = import_notes_classic_chain(
+        &target,
+        &target_dir,
+        Path::new(&second.manifest_path),
+        &[PathBuf::from(&incremental.manifest_path)],
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(import.errors.is_empty(), "{:?}", import.errors);
+    assert_tables_match(&source, &target, &target_dir).await;
+
+    source.close().await;
+    target.close().await;
+}
+
+#[tokio::test]
+async fn merge_keeps_existing_notes_and_skips_duplicates() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+    let target_dir = TempDir::new("target");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+    let export = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions::default(),
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+
+    let target = init_db(&target_dir).await.unwrap();
+    exec(
+        &target,
+        "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order, external_id)
+         VALUES (1, 'Inbox', 5, NULL, 'notebook', 0, NULL)",
+    )
+    .await;
+    exec(
+        &target,
+        "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+         VALUES (1, 'Local', '<p>Local note</p>', 50, 50, 1)",
+    )
+    .await;
+
+    let merged = merge_notes_classic_package(
+        &target,
+        &target_dir,
+        Path::new(&export.manifest_path),
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(merged.errors.is_empty(), "{:?}", merged.errors);
+    let summary = merged.merge.unwrap();
+    assert_eq!(summary.notebooks.created, 2);
+    assert_eq!(summary.tags.created, 2);
+    assert_eq!(summary.notes.created, 2);
+    assert_eq!(summary.attachments.created, 1);
+    assert_eq!(summary.saved_searches.created, 1);
+    assert_eq!(summary.note_templates.created, 1);
+
+    let titles: Vec<(String,)> = rows(&target, "SELECT title FROM notes ORDER BY id").await;
+    assert_eq!(
+        titles,
+        vec![
+            ("Local".to_string(),),
+            ("Plan".to_string(),),
+            ("Old idea".to_string(),)
+        ]
+    );
+    let plan_tags: Vec<(String,)> = rows(
+        &target,
+        "SELECT t.name FROM note_tags nt JOIN tags t ON t.id = nt.tag_id
+         JOIN notes n ON n.id = nt.note_id WHERE n.title = 'Plan' ORDER BY t.name",
+    )
+    .await;
+    assert_eq!(
+        plan_tags,
+        vec![("urgent".to_string(),), ("work".to_string(),)]
+    );
+    let paths: Vec<(String,)> = rows(&target, "SELECT local_path FROM attachments").await;
+    assert_eq!(fs::read(target_dir.join(&paths[0].0)).unwrap(), b"report");
+    // The trash group gets ids past the library's notebooks and still
+    // restores Old idea into Old projects.
+    let trash_sql = "SELECT t.id, t.parent_id, t.trash_root_id, n.deleted_from_notebook_id, n.deleted_with_notebook_id
+                     FROM trashed_notebooks t JOIN notes n ON n.title = 'Old idea' ORDER BY t.id";
+    let trash: Vec<(i64, Option<i64>, i64, Option<i64>, Option<i64>)> =
+        rows(&target, trash_sql).await;
+    assert_eq!(
+        trash,
+        vec![
+            (4, None, 4, Some(5), Some(4)),
+            (5, Some(4), 4, Some(5), Some(4))
+        ]
+    );
+
+    let again = merge_notes_classic_package(
+        &target,
+        &target_dir,
+        Path::new(&export.manifest_path),
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(again.errors.is_empty(), "{:?}", again.errors);
+    let summary = again.merge.unwrap();
+    assert_eq!(summary.notes.created + summary.notes.updated, 0);
+    assert_eq!(summary.notes.skipped, 2);
+    assert_eq!(summary.notebooks.skipped, 2);
+    assert_eq!(summary.tags.skipped, 2);
+    assert_eq!(summary.attachments.skipped, 1);
+    assert_eq!(summary.saved_searches.skipped, 1);
+    assert_eq!(summary.note_templates.skipped, 1);
+    let counts: Vec<(i64,)> = rows(
+        &target,
+        "SELECT COUNT(*) FROM notes UNION ALL SELECT COUNT(*) FROM attachments",
+    )
+    .await;
+    assert_eq!(counts, vec![(3,), (1,)]);
+    assert_eq!(
+        rows::<(i64, Option<i64>, i64, Option<i64>, Option<i64>)>(&target, trash_sql).await,
+        trash
+    );
+
+    source.close().await;
+    target.close().await;
+}
+
+#[tokio::test]
+async fn merge_updates_older_copies_and_keeps_newer_local_edits() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+    let target_dir = TempDir::new("target");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+    let first = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions::default(),
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    let target = init_db(&target_dir).await.unwrap();
+    let merged = merge_notes_classic_package(
+        &target,
+        &target_dir,
+        Path::new(&first.manifest_path),
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(merged.errors.is_empty(), "{:?}", merged.errors);
+    let plan_files = "SELECT COUNT(*) FROM note_files nf JOIN notes n ON n.id = nf.note_id
+                      WHERE n.title = 'Plan'";
+    assert_eq!(rows::<(i64,)>(&target, plan_files).await, vec![(1,)]);
+
+    // Both sides edit after the first merge: the package wins for Plan. Old
+    // idea has no external id, and with its text changed on both sides it no
+    // longer matches, so the package copy arrives next to the local rewrite.
+    exec(
+        &target,
+        "UPDATE notes SET content = '<p>Local rewrite</p>', updated_at = 900 WHERE title = 'Old idea'",
+    )
+    .await;
+    exec(
+        &source,
+        "UPDATE notes SET content = '<p>Quarterly plan without chart</p>', updated_at = 400 WHERE id = 1",
+    )
+    .await;
+    exec(
+        &source,
+        "UPDATE notes SET content = '<p>Discarded again</p>', updated_at = 450 WHERE id = 2",
+    )
+    .await;
+    let second = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions::default(),
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    let merged = merge_notes_classic_package(
+        &target,
+        &target_dir,
+        Path::new(&second.manifest_path),
+        None,
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    assert!(merged.errors.is_empty(), "{:?}", merged.errors);
+    let summary = merged.merge.unwrap();
+    assert_eq!(summary.notes.created, 1);
+    assert_eq!(summary.notes.updated, 1);
+    assert_eq!(summary.notes.skipped, 0);
+
+    let contents: Vec<(String, String, i64)> = rows(
+        &target,
+        "SELECT title, content, updated_at FROM notes ORDER BY id",
+    )
+    .await;
+    assert_eq!(
+        contents,
+        vec![
+            (
+                "Plan".to_string(),
+                "<p>Quarterly plan without chart</p>".to_string(),
+                400
+            ),
+            (
+                "Old idea".to_string(),
+                "<p>Local rewrite</p>".to_string(),
+                900
+            ),
+            (
+                "Old idea".to_string(),
+                "<p>Discarded again</p>".to_string(),
+                450
+            ),
+        ]
+    );
+    assert_eq!(rows::<(i64,)>(&target, plan_files).await, vec![(0,)]);
+    let hits: Vec<(String,)> = rows(
+        &target,
+        "SELECT title FROM notes_text WHERE plain_text LIKE '%without chart%'",
+    )
+    .await;
+    assert_eq!(hits, vec![("Plan".to_string(),)]);
+
+    source.close().await;
+    target.close().await;
+}
+
+#[tokio::test]
+async fn preflight_reports_missing_members_without_importing() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+    let export = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions::default(),
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+
+    let clean = preflight_notes_classic_chain(Path::new(&export.manifest_path), &[]).unwrap();
+    assert_eq!(clean.notes, 2);
+    assert_eq!(clean.attachments, 1);
+    let report = clean.preflight.unwrap();
+    assert!(report.is_clean());
+    assert!(report.estimated_bytes > 0);
+
+    let root = Path::new(&export.export_root);
+    fs::remove_file(root.join("attachments/1/report.txt")).unwrap();
+    fs::remove_file(root.join("files/images/chart.png")).unwrap();
+    let broken = preflight_notes_classic_chain(Path::new(&export.manifest_path), &[]).unwrap();
+    let report = broken.preflight.unwrap();
+    assert_eq!(
+        report.missing_files,
+        vec![
+            "attachment 1: attachments/1/report.txt".to_string(),
+            "file 1: files/images/chart.png".to_string()
+        ]
+    );
+    assert_eq!(
+        report.missing_assets,
+        vec!["note 1: files/images/chart.png".to_string()]
+    );
+    assert!(report.dangling_references.is_empty());
+    assert!(report.duplicate_ids.is_empty());
+
+    source.close().await;
+}
+
+/// Every file under `root` with its bytes, in path order.
+fn snapshot(root: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                let bytes = fs::read(&path).unwrap();
+                files.push((path, bytes));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[tokio::test]
+async fn preflight_of_an_archive_chain_writes_nothing() {
+    let source_dir = TempDir::new("source");
+    let export_dir = TempDir::new("export");
+
+    let source = init_db(&source_dir).await.unwrap();
+    seed(&source, &source_dir).await;
+    let full = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions {
+            archive: true,
+            ..ExportOptions::default()
+        },
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    exec(
+        &source,
+        "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+         VALUES (3, 'Fresh', '<p>New note</p>', 410, 410, 2)",
+    )
+    .await;
+    let incremental = export_notes_classic_package(
+        &source,
+        &source_dir,
+        &export_dir,
+        &ExportOptions {
+            archive: true,
+            base: Some(PathBuf::from(&full.manifest_path)),
+        },
+        &|_: PackageProgress| {},
+    )
+    .await
+    .unwrap();
+    let packages = snapshot(&export_dir);
+
+    let report = preflight_notes_classic_chain(
+        Path::new(&full.manifest_path),
+        &[PathBuf::from(&incremental.manifest_path)],
+    )
+    .unwrap();
+    assert_eq!(report.notes, 2 + incremental.notes);
+    assert!(report.preflight.unwrap().is_clean());
+    assert!(snapshot(&export_dir) == packages);
+
+    // An incremental on its own is refused up front.
+    assert!(preflight_notes_classic_chain(Path::new(&incremental.manifest_path), &[]).is_err());
+    assert!(snapshot(&export_dir) == packages);
+
+    source.close().await;
+}
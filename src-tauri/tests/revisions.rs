@@ -0,0 +1,151 @@
+mod common;
+
+use common::{seeded_repo, TempDir};
+use notes_classic_lib::db::{RevisionPolicy, SqliteRepository};
+use notes_classic_lib::services::{diff_note_text, read_revision_policy};
+use std::fs;
+use std::path::Path;
+
+const SEED: &[&str] = &[
+    "INSERT INTO notes (id, title, content, created_at, updated_at)
+     VALUES (1, 'v0', '<p>v0</p>', 0, 0)",
+];
+
+async fn edit(repo: &SqliteRepository, data_dir: &Path, version: &str, policy: &RevisionPolicy) {
+    repo.update_note(
+        1,
+        version,
+        &format!("<p>{}</p>", version),
+        None,
+        data_dir,
+        policy,
+    )
+    .await
+    .unwrap();
+}
+
+async fn revision_titles(repo: &SqliteRepository) -> Vec<String> {
+    repo.get_note_revisions(1)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|revision| revision.title)
+        .collect()
+}
+
+#[tokio::test]
+async fn revisions_are_throttled_and_pruned() {
+    let data_dir = TempDir::new("revisions-policy");
+    let repo = seeded_repo(&data_dir, SEED).await;
+
+    let throttled = RevisionPolicy::default();
+    edit(&repo, &data_dir, "v1", &throttled).await;
+    edit(&repo, &data_dir, "v2", &throttled).await;
+    assert_eq!(revision_titles(&repo).await, vec!["v0"]);
+
+    let disabled = RevisionPolicy {
+        enabled: false,
+        ..RevisionPolicy::default()
+    };
+    edit(&repo, &data_dir, "v3", &disabled).await;
+    assert_eq!(revision_titles(&repo).await, vec!["v0"]);
+
+    let limited = RevisionPolicy {
+        min_interval_seconds: 0,
+        max_per_note: 2,
+        ..RevisionPolicy::default()
+    };
+    edit(&repo, &data_dir, "v4", &limited).await;
+    edit(&repo, &data_dir, "v5", &limited).await;
+    edit(&repo, &data_dir, "v6", &limited).await;
+    assert_eq!(revision_titles(&repo).await, vec!["v5", "v4"]);
+
+    // Saving unchanged text takes no snapshot.
+    edit(&repo, &data_dir, "v6", &limited).await;
+    assert_eq!(revision_titles(&repo).await, vec!["v5", "v4"]);
+
+    sqlx::query("UPDATE note_revisions SET created_at = 0")
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+    let recent = RevisionPolicy {
+        min_interval_seconds: 0,
+        max_age_days: 30,
+        ..RevisionPolicy::default()
+    };
+    edit(&repo, &data_dir, "v7", &recent).await;
+    assert_eq!(revision_titles(&repo).await, vec!["v6"]);
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn restoring_a_revision_snapshots_the_current_text() {
+    let data_dir = TempDir::new("revisions-restore");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    let policy = RevisionPolicy::default();
+    edit(&repo, &data_dir, "v1", &policy).await;
+    let revisions = repo.get_note_revisions(1).await.unwrap();
+    assert_eq!(revisions.len(), 1);
+
+    // Restoring ignores the interval so the text it replaces is kept.
+    let restored = repo
+        .restore_note_revision(revisions[0].id, &data_dir, &policy)
+        .await
+        .unwrap();
+    assert_eq!(restored, Some(1));
+    let note = repo.get_note(1).await.unwrap().unwrap();
+    assert_eq!(
+        (note.title.as_str(), note.content.as_str()),
+        ("v0", "<p>v0</p>")
+    );
+    assert_eq!(revision_titles(&repo).await, vec!["v1", "v0"]);
+
+    assert_eq!(
+        repo.restore_note_revision(999, &data_dir, &policy)
+            .await
+            .unwrap(),
+        None
+    );
+
+    repo.pool.close().await;
+}
+
+#[test]
+fn revision_diff_marks_changed_words() {
+    let chunks: Vec<(String, String)> =
+        diff_note_text("<p>The quick fox</p>", "<p>The slow fox</p><p>jumps</p>")
+            .into_iter()
+            .map(|chunk| (chunk.kind, chunk.text))
+            .collect();
+    assert_eq!(
+        chunks,
+        vec![
+            ("equal".to_string(), "The ".to_string()),
+            ("delete".to_string(), "quick".to_string()),
+            ("insert".to_string(), "slow".to_string()),
+            ("equal".to_string(), " fox".to_string()),
+            ("insert".to_string(), " jumps".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn revision_policy_reads_app_settings() {
+    let settings_dir = TempDir::new("revisions-settings");
+    let defaults = read_revision_policy(&settings_dir);
+    assert!(defaults.enabled);
+    assert_eq!(defaults.min_interval_seconds, 600);
+    assert_eq!(defaults.max_per_note, 50);
+
+    fs::write(
+        settings_dir.join("app.json"),
+        r#"{"revisionsEnabled":false,"revisionIntervalMinutes":5,"revisionMaxPerNote":-3,"revisionRetentionDays":14}"#,
+    )
+    .unwrap();
+    let policy = read_revision_policy(&settings_dir);
+    assert!(!policy.enabled);
+    assert_eq!(policy.min_interval_seconds, 300);
+    assert_eq!(policy.max_per_note, 0);
+    assert_eq!(policy.max_age_days, 14);
+}
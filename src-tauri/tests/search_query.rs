@@ -0,0 +1,388 @@
+mod common;
+
+use common::{seeded_repo, TempDir};
+use notes_classic_lib::db::{
+    ensure_fts_tokenizer, optimize_fts_tables, refresh_stems, set_stem_language, strip_html,
+    FtsTokenizer, SearchError, SearchFilter, SearchQuery, SearchSort, SearchTodo, SqliteRepository,
+    StemLanguage,
+};
+
+const SEED: &[&str] = &[
+    "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+     VALUES (1, 'Work', 0, NULL, 'stack', 0),
+            (2, 'Projects', 0, 1, 'notebook', 0),
+            (3, 'Home', 0, NULL, 'notebook', 1)",
+    "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+     VALUES (1, 'Quarterly report', '<p>Revenue grew in the north-east region</p>', 1700000000, 1700000000, 2),
+            (2, 'Shopping', '<ul data-en-todo=\"true\"><li data-en-checked=\"true\">milk</li><li data-en-checked=\"false\">bread</li></ul>', 1710000000, 1710000000, 3),
+            (3, 'Report draft', '<p>Revenue forecast</p>', 1720000000, 1720000000, 3)",
+    "INSERT INTO notes_text (note_id, title, plain_text)
+     VALUES (1, 'Quarterly report', 'Revenue grew in the north-east region'),
+            (2, 'Shopping', 'milk bread'),
+            (3, 'Report draft', 'Revenue forecast')",
+    "INSERT INTO tags (id, name, parent_id, created_at, updated_at)
+     VALUES (1, 'Finance', NULL, 0, 0), (2, 'errands', NULL, 0, 0)",
+    "INSERT INTO note_tags (note_id, tag_id) VALUES (1, 1), (3, 1), (2, 2)",
+    "INSERT INTO attachments (note_id, filename, mime, size, local_path, is_attachment, created_at, updated_at)
+     VALUES (1, 'chart.png', 'image/png', 10, 'files/attachments/1/chart.png', 0, 0, 0)",
+];
+
+async fn ids(repo: &SqliteRepository, query: &str, notebook_id: Option<i64>) -> Vec<i64> {
+    let mut ids: Vec<i64> = repo
+        .search_notes(query, notebook_id, SearchSort::Updated)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|note| note.id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn parses_operators_phrases_and_negation() {
+    let query =
+        SearchQuery::parse("intitle:report \"north east\" -tag:Finance todo:false AND rev*")
+            .unwrap();
+    let filters: Vec<(bool, SearchFilter)> = query
+        .terms
+        .into_iter()
+        .map(|term| (term.negated, term.filter))
+        .collect();
+    assert_eq!(
+        filters,
+        vec![
+            (false, SearchFilter::InTitle("report".to_string())),
+            (false, SearchFilter::Phrase("north east".to_string())),
+            (true, SearchFilter::Tag("Finance".to_string())),
+            (false, SearchFilter::Todo(SearchTodo::Unchecked)),
+            (false, SearchFilter::Text("rev*".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn rejects_malformed_queries_with_position() {
+    for (input, position) in [
+        ("report \"open", 7),
+        ("tag:", 0),
+        ("alpha created:yesterday", 6),
+        ("todo:maybe", 0),
+        ("word - other", 5),
+    ] {
+        let err = SearchQuery::parse(input).unwrap_err();
+        assert_eq!(err.position, position, "{}: {}", input, err);
+    }
+}
+
+#[tokio::test]
+async fn structured_queries_filter_notes() {
+    let data_dir = TempDir::new("search");
+    let repo = seeded_repo(&data_dir, SEED).await;
+
+    assert_eq!(ids(&repo, "revenue", None).await, vec![1, 3]);
+    assert_eq!(ids(&repo, "revenue -forecast", None).await, vec![1]);
+    assert_eq!(ids(&repo, "\"north east\"", None).await, vec![1]);
+    assert_eq!(ids(&repo, "north-east", None).await, vec![1]);
+    assert_eq!(ids(&repo, "intitle:report", None).await, vec![1, 3]);
+    assert_eq!(ids(&repo, "tag:finance", None).await, vec![1, 3]);
+    assert_eq!(ids(&repo, "tag:fin* -notebook:home", None).await, vec![1]);
+    assert_eq!(ids(&repo, "stack:work", None).await, vec![1]);
+    assert_eq!(ids(&repo, "created:20240301", None).await, vec![2, 3]);
+    assert_eq!(ids(&repo, "resource:image/*", None).await, vec![1]);
+    assert_eq!(ids(&repo, "todo:false", None).await, vec![2]);
+    assert_eq!(ids(&repo, "revenue", Some(3)).await, vec![3]);
+    assert_eq!(ids(&repo, "\"it's\" OR) (", None).await, Vec::<i64>::new());
+
+    let err = repo
+        .search_notes("tag:\"open", None, SearchSort::Relevance)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, SearchError::Query(_)));
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn ranks_title_hits_above_ocr_and_returns_snippets() {
+    let data_dir = TempDir::new("search-rank");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    for sql in [
+        "INSERT INTO ocr_files (id, file_path) VALUES (1, 'files/receipt.png')",
+        "INSERT INTO note_files (note_id, file_id) VALUES (2, 1)",
+        "INSERT INTO ocr_text (file_id, lang, text, hash, updated_at)
+         VALUES (1, 'eng', 'expense report total', 'h', 0)",
+    ] {
+        sqlx::query(sql).execute(&repo.pool).await.unwrap();
+    }
+
+    let results = repo
+        .search_notes("report", None, SearchSort::Relevance)
+        .await
+        .unwrap();
+    let order: Vec<i64> = results.iter().map(|note| note.id).collect();
+    assert_eq!(order.len(), 3);
+    assert_eq!(order[2], 2);
+    assert!(results[1].score > results[2].score);
+    let ocr_hit = &results[2];
+    assert!(ocr_hit.ocr_match);
+    let ocr_snippet = ocr_hit.ocr_snippet.as_ref().unwrap();
+    assert_eq!(ocr_snippet.text, "expense report total");
+    assert_eq!(ocr_snippet.highlights, vec![[8, 14]]);
+
+    let results = repo
+        .search_notes("forecast", None, SearchSort::Relevance)
+        .await
+        .unwrap();
+    let snippet = results[0].snippet.as_ref().unwrap();
+    assert_eq!(snippet.text, "Revenue forecast");
+    assert_eq!(snippet.highlights, vec![[8, 16]]);
+
+    let titles: Vec<String> = repo
+        .search_notes("revenue", None, SearchSort::Title)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|note| note.title)
+        .collect();
+    assert_eq!(titles, vec!["Quarterly report", "Report draft"]);
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn saved_searches_run_reorder_and_count() {
+    let data_dir = TempDir::new("saved-search");
+    let repo = seeded_repo(&data_dir, SEED).await;
+
+    let err = repo
+        .create_saved_search("Broken", "tag:", None, SearchSort::Relevance)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, SearchError::Query(_)));
+
+    let reports = repo
+        .create_saved_search("Reports", "intitle:report", None, SearchSort::Created)
+        .await
+        .unwrap();
+    let home = repo
+        .create_saved_search("Home revenue", "revenue", Some(3), SearchSort::Relevance)
+        .await
+        .unwrap();
+    repo.move_saved_search(home, 0).await.unwrap();
+    repo.rename_saved_search(reports, "All reports")
+        .await
+        .unwrap();
+    let saved: Vec<(i64, String)> = repo
+        .get_saved_searches()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|search| (search.id, search.name))
+        .collect();
+    assert_eq!(
+        saved,
+        vec![
+            (home, "Home revenue".to_string()),
+            (reports, "All reports".to_string())
+        ]
+    );
+
+    let results: Vec<i64> = repo
+        .run_saved_search(reports)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|note| note.id)
+        .collect();
+    assert_eq!(results, vec![3, 1]);
+
+    let counts: Vec<(i64, i64)> = repo
+        .get_note_counts()
+        .await
+        .unwrap()
+        .saved_searches
+        .into_iter()
+        .map(|item| (item.saved_search_id, item.count))
+        .collect();
+    assert_eq!(counts, vec![(home, 1), (reports, 2)]);
+
+    repo.delete_saved_search(home).await.unwrap();
+    assert_eq!(repo.get_saved_searches().await.unwrap().len(), 1);
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn folds_diacritics_and_switches_tokenizer() {
+    let data_dir = TempDir::new("search-fold");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    for sql in [
+        "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+         VALUES (4, 'Ёлка', '<p>Café crème на Новый год</p>', 1730000000, 1730000000, 3)",
+        "INSERT INTO notes_text (note_id, title, plain_text)
+         VALUES (4, 'Ёлка', 'Café crème на Новый год')",
+    ] {
+        sqlx::query(sql).execute(&repo.pool).await.unwrap();
+    }
+
+    assert_eq!(ids(&repo, "cafe creme", None).await, vec![4]);
+    assert_eq!(ids(&repo, "елка", None).await, vec![4]);
+    assert_eq!(ids(&repo, "ЁЛКА", None).await, vec![4]);
+    assert_eq!(ids(&repo, "новый", None).await, vec![4]);
+
+    assert!(!ensure_fts_tokenizer(&repo.pool, FtsTokenizer::Unicode61)
+        .await
+        .unwrap());
+    assert!(ensure_fts_tokenizer(&repo.pool, FtsTokenizer::Trigram)
+        .await
+        .unwrap());
+    assert_eq!(ids(&repo, "venu", None).await, vec![1, 3]);
+    assert_eq!(ids(&repo, "лка", None).await, vec![4]);
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn stemmed_words_match_inflected_forms() {
+    let data_dir = TempDir::new("search-stem");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    set_stem_language(&repo.pool, StemLanguage::Russian)
+        .await
+        .unwrap();
+    for sql in [
+        "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+         VALUES (4, 'Training', '<p>Running every morning</p>', 1730000000, 1730000000, 3),
+                (5, 'Список', '<p>Заметка про покупки</p>', 1730000001, 1730000001, 3)",
+        "INSERT INTO notes_text (note_id, title, plain_text)
+         VALUES (4, 'Training', 'Running every morning'),
+                (5, 'Список', 'Заметка про покупки')",
+    ] {
+        sqlx::query(sql).execute(&repo.pool).await.unwrap();
+    }
+    // Rows written without stems, as by an import, are stemmed afterwards
+    // rather than on the next search.
+    assert_eq!(ids(&repo, "runs", None).await, Vec::<i64>::new());
+    assert_eq!(refresh_stems(&repo.pool).await.unwrap(), 2);
+
+    assert_eq!(ids(&repo, "run", None).await, vec![4]);
+    assert_eq!(ids(&repo, "runs", None).await, vec![4]);
+    assert_eq!(ids(&repo, "заметки", None).await, vec![5]);
+    assert_eq!(ids(&repo, "\"run\"", None).await, Vec::<i64>::new());
+    assert_eq!(ids(&repo, "\"running\"", None).await, vec![4]);
+    assert_eq!(ids(&repo, "-заметки", Some(3)).await, vec![2, 3, 4]);
+
+    sqlx::query("UPDATE notes_text SET plain_text = 'Walking every evening' WHERE note_id = 4")
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+    refresh_stems(&repo.pool).await.unwrap();
+    assert_eq!(ids(&repo, "run", None).await, Vec::<i64>::new());
+    assert_eq!(ids(&repo, "walk", None).await, vec![4]);
+
+    // `vacuum` merges the index segments; stemmed lookups keep working.
+    optimize_fts_tables(&repo.pool).await.unwrap();
+    assert_eq!(ids(&repo, "walks", None).await, vec![4]);
+    assert_eq!(ids(&repo, "заметки", None).await, vec![5]);
+
+    repo.pool.close().await;
+}
+
+#[test]
+fn strips_html_to_searchable_text() {
+    assert_eq!(strip_html("<p>end</p><p>start</p>"), "end start");
+    assert_eq!(
+        strip_html("Fish &amp; chips&nbsp;<b>to</b>go"),
+        "Fish & chips togo"
+    );
+    assert_eq!(
+        strip_html("<style>p { color: red }</style><script>if (a < b) {}</script><p>Body</p>"),
+        "Body"
+    );
+    assert_eq!(
+        strip_html("<div>Open</div><div data-secure=\"true\"><div>secret</div></div><en-crypt>c2VjcmV0</en-crypt>Tail"),
+        "Open Tail"
+    );
+    assert_eq!(
+        strip_html("<p>Chart<img src=\"files/a.png\" alt=\"Sales chart\" title=\"Q3\"></p>"),
+        "Chart Sales chart Q3"
+    );
+    assert_eq!(strip_html("<p>one<br>two<!-- note --></p>"), "one two");
+}
+
+#[tokio::test]
+async fn reindex_rebuilds_note_text_from_html() {
+    let data_dir = TempDir::new("search-reindex");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    sqlx::query("UPDATE notes SET content = '<p>Fish &amp; chips</p><p>dinner</p>' WHERE id = 2")
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+    assert_eq!(ids(&repo, "dinner", None).await, Vec::<i64>::new());
+
+    assert_eq!(repo.reindex_notes_text().await.unwrap(), 3);
+    let plain: String = sqlx::query_scalar("SELECT plain_text FROM notes_text WHERE note_id = 2")
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+    assert_eq!(plain, "Fish & chips dinner");
+    assert_eq!(ids(&repo, "dinner", None).await, vec![2]);
+    assert_eq!(ids(&repo, "chip", None).await, vec![2]);
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn attachment_text_matches_are_flagged() {
+    let data_dir = TempDir::new("search-attachment");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    sqlx::query(
+        "INSERT INTO attachments (id, note_id, filename, mime, size, local_path, is_attachment, created_at, updated_at)
+         VALUES (7, 3, 'budget.pdf', 'application/pdf', 10, 'files/attachments/7/budget.pdf', 1, 0, 0)",
+    )
+    .execute(&repo.pool)
+    .await
+    .unwrap();
+
+    let pending: Vec<i64> = repo
+        .get_attachment_text_pending(10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|attachment| attachment.id)
+        .collect();
+    assert_eq!(pending, vec![7]);
+    repo.upsert_attachment_text(7, "Projected invoices for the spring quarter", None)
+        .await
+        .unwrap();
+    assert!(repo
+        .get_attachment_text_pending(10)
+        .await
+        .unwrap()
+        .is_empty());
+    let stats = repo.get_attachment_text_stats().await.unwrap();
+    assert_eq!((stats.total, stats.done, stats.pending), (1, 1, 0));
+
+    let results = repo
+        .search_notes("invoice", None, SearchSort::Relevance)
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].id, 3);
+    assert!(results[0].attachment_match);
+    assert!(!results[0].ocr_match);
+    let snippet = results[0].attachment_snippet.as_ref().unwrap();
+    assert!(snippet.text.contains("invoices"));
+    assert_eq!(ids(&repo, "revenue -invoices", None).await, vec![1]);
+
+    sqlx::query(
+        "UPDATE attachments SET local_path = 'files/attachments/7/budget-v2.pdf' WHERE id = 7",
+    )
+    .execute(&repo.pool)
+    .await
+    .unwrap();
+    assert_eq!(ids(&repo, "invoice", None).await, Vec::<i64>::new());
+    assert_eq!(repo.get_attachment_text_pending(10).await.unwrap().len(), 1);
+
+    repo.pool.close().await;
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::{seeded_repo, TempDir};
+use notes_classic_lib::db::{RepoError, SearchSort};
+
+/// Projects (1) > Alpha (2) > Alpha docs (3), Projects > Beta (4), Home (5).
+const SEED: &[&str] = &[
+    "INSERT INTO notes (id, title, content, created_at, updated_at)
+     VALUES (1, 'Alpha plan', '', 0, 1), (2, 'Alpha spec', '', 0, 2),
+            (3, 'Beta plan', '', 0, 3), (4, 'Groceries', '', 0, 4),
+            (5, 'Old alpha', '', 0, 5)",
+    "UPDATE notes SET deleted_at = 10 WHERE id = 5",
+    "INSERT INTO tags (id, name, parent_id, created_at, updated_at)
+     VALUES (1, 'Projects', NULL, 0, 0), (2, 'Alpha', 1, 0, 0),
+            (3, 'Alpha docs', 2, 0, 0), (4, 'Beta', 1, 0, 0), (5, 'Home', NULL, 0, 0)",
+    "INSERT INTO note_tags (note_id, tag_id)
+     VALUES (1, 2), (2, 3), (2, 5), (3, 4), (3, 1), (4, 5), (5, 2)",
+];
+
+fn ids(notes: Vec<notes_classic_lib::db::NoteListItem>) -> Vec<i64> {
+    let mut ids: Vec<i64> = notes.into_iter().map(|note| note.id).collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn tag_queries_include_descendant_tags() {
+    let data_dir = TempDir::new("tags-tree");
+    let repo = seeded_repo(&data_dir, SEED).await;
+
+    assert_eq!(ids(repo.get_notes_by_tag(1).await.unwrap()), vec![1, 2, 3]);
+    assert_eq!(ids(repo.get_notes_by_tag(2).await.unwrap()), vec![1, 2]);
+    assert_eq!(ids(repo.get_notes_by_tag(3).await.unwrap()), vec![2]);
+    assert_eq!(
+        ids(repo.get_notes_by_tags(&[2, 5], false).await.unwrap()),
+        vec![1, 2, 4]
+    );
+    assert_eq!(
+        ids(repo.get_notes_by_tags(&[2, 5], true).await.unwrap()),
+        vec![2]
+    );
+    assert_eq!(
+        ids(repo.get_notes_by_tags(&[1, 1, 4], true).await.unwrap()),
+        vec![3]
+    );
+    assert!(repo.get_notes_by_tags(&[], true).await.unwrap().is_empty());
+
+    let mut per_tag: Vec<(i64, i64)> = repo
+        .get_note_counts()
+        .await
+        .unwrap()
+        .per_tag
+        .into_iter()
+        .map(|item| (item.tag_id, item.count))
+        .collect();
+    per_tag.sort();
+    assert_eq!(per_tag, vec![(1, 3), (2, 2), (3, 1), (4, 1), (5, 2)]);
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn merge_tags_moves_notes_and_children_into_target() {
+    let data_dir = TempDir::new("tags-merge");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    let todo = repo.create_tag("TODO", None).await.unwrap();
+    let todo_docs = repo.create_tag("Alpha docs", Some(todo)).await.unwrap();
+    let todo_misc = repo.create_tag("Misc", Some(todo)).await.unwrap();
+    repo.add_note_tag(4, todo).await.unwrap();
+    repo.add_note_tag(1, todo).await.unwrap();
+    repo.add_note_tag(3, todo_docs).await.unwrap();
+    let search = repo
+        .create_saved_search(
+            "Todo plans",
+            "tag:todo -tag:Home plan",
+            None,
+            SearchSort::Relevance,
+        )
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        repo.merge_tags(1, 3).await,
+        Err(RepoError::Invalid(_))
+    ));
+    assert!(matches!(
+        repo.merge_tags(2, 2).await,
+        Err(RepoError::Invalid(_))
+    ));
+    repo.merge_tags(todo, 2).await.unwrap();
+
+    let mut tags: Vec<(i64, String, Option<i64>)> = repo
+        .get_tags()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|tag| (tag.id, tag.name, tag.parent_id))
+        .collect();
+    tags.sort();
+    assert_eq!(
+        tags,
+        vec![
+            (1, "Projects".to_string(), None),
+            (2, "Alpha".to_string(), Some(1)),
+            (3, "Alpha docs".to_string(), Some(2)),
+            (4, "Beta".to_string(), Some(1)),
+            (5, "Home".to_string(), None),
+            (todo_misc, "Misc".to_string(), Some(2)),
+        ]
+    );
+    assert!(!tags.iter().any(|tag| tag.0 == todo_docs));
+    assert_eq!(
+        ids(repo.get_notes_by_tag(2).await.unwrap()),
+        vec![1, 2, 3, 4]
+    );
+    assert_eq!(ids(repo.get_notes_by_tag(3).await.unwrap()), vec![2, 3]);
+    assert_eq!(
+        repo.get_saved_search(search).await.unwrap().unwrap().query,
+        "tag:Alpha -tag:Home plan"
+    );
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn bulk_retag_and_rename_update_saved_searches() {
+    let data_dir = TempDir::new("tags-bulk");
+    let repo = seeded_repo(&data_dir, SEED).await;
+
+    assert_eq!(repo.add_tag_to_notes(4, &[1, 2, 3, 4]).await.unwrap(), 3);
+    assert_eq!(
+        ids(repo.get_notes_by_tag(4).await.unwrap()),
+        vec![1, 2, 3, 4]
+    );
+    assert_eq!(repo.remove_tag_from_notes(4, &[2, 3, 99]).await.unwrap(), 2);
+    assert_eq!(ids(repo.get_notes_by_tag(4).await.unwrap()), vec![1, 4]);
+
+    let search = repo
+        .create_saved_search("Home", "-tag:home groceries", None, SearchSort::Relevance)
+        .await
+        .unwrap();
+    repo.rename_tag(5, "Home life").await.unwrap();
+    assert_eq!(
+        repo.get_saved_search(search).await.unwrap().unwrap().query,
+        "-tag:\"Home life\" groceries"
+    );
+    let results = repo.run_saved_search(search).await.unwrap();
+    assert!(results.is_empty());
+
+    repo.pool.close().await;
+}
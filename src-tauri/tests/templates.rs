@@ -0,0 +1,115 @@
+mod common;
+
+use common::{seeded_repo, TempDir};
+
+#[tokio::test]
+async fn notes_from_templates_expand_placeholders_and_take_defaults() {
+    let data_dir = TempDir::new("templates");
+    let repo = seeded_repo(
+        &data_dir,
+        &[
+            "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+             VALUES (1, 'Meetings', 0, NULL, 'notebook', 0), (2, 'Bugs', 0, NULL, 'notebook', 1)",
+            "INSERT INTO tags (id, name, parent_id, created_at, updated_at)
+             VALUES (1, 'Minutes', NULL, 0, 0), (2, 'Weekly', NULL, 0, 0)",
+        ],
+    )
+    .await;
+
+    let template = repo
+        .create_note_template(
+            "Meeting",
+            "{{notebook}} {{ date }}: {{clipboard}}",
+            "<p>Notes for {{clipboard}}</p><p>{{unknown}}</p>",
+            Some(1),
+            &[1, 2],
+        )
+        .await
+        .unwrap();
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+
+    let note_id = repo
+        .create_note_from_template(template, None, "Q3 <plan>\nreview", &data_dir)
+        .await
+        .unwrap();
+    let note = repo.get_note(note_id).await.unwrap().unwrap();
+    assert_eq!(note.notebook_id, Some(1));
+    assert_eq!(note.title, format!("Meetings {}: Q3 <plan> review", today));
+    assert_eq!(
+        note.content,
+        "<p>Notes for Q3 &lt;plan&gt;<br>review</p><p>{{unknown}}</p>"
+    );
+    let tags: Vec<String> = repo
+        .get_note_tags(note_id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|tag| tag.name)
+        .collect();
+    assert_eq!(tags, vec!["Minutes", "Weekly"]);
+    let (indexed,): (String,) =
+        sqlx::query_as("SELECT plain_text FROM notes_text WHERE note_id = ?")
+            .bind(note_id)
+            .fetch_one(&repo.pool)
+            .await
+            .unwrap();
+    assert!(indexed.contains("Notes for Q3"));
+
+    let other = repo
+        .create_note_from_template(template, Some(2), "", &data_dir)
+        .await
+        .unwrap();
+    let other = repo.get_note(other).await.unwrap().unwrap();
+    assert_eq!(other.notebook_id, Some(2));
+    assert_eq!(other.title, format!("Bugs {}:", today));
+
+    // A deleted default notebook leaves new notes unfiled.
+    repo.update_note_template(template, "Meeting", "{{notebook}}", "", Some(99), &[2])
+        .await
+        .unwrap();
+    let unfiled = repo
+        .create_note_from_template(template, None, "", &data_dir)
+        .await
+        .unwrap();
+    let unfiled = repo.get_note(unfiled).await.unwrap().unwrap();
+    assert_eq!(unfiled.notebook_id, None);
+    assert_eq!(unfiled.title, "");
+    assert!(repo
+        .create_note_from_template(template, Some(99), "", &data_dir)
+        .await
+        .is_err());
+
+    // A tag that cannot be added rolls the whole note back.
+    sqlx::query(
+        "CREATE TRIGGER refuse_weekly BEFORE INSERT ON note_tags WHEN NEW.tag_id = 2
+         BEGIN SELECT RAISE(ABORT, 'refused'); END",
+    )
+    .execute(&repo.pool)
+    .await
+    .unwrap();
+    let (before,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM notes")
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+    assert!(repo
+        .create_note_from_template(template, None, "", &data_dir)
+        .await
+        .is_err());
+    let (after,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM notes")
+        .fetch_one(&repo.pool)
+        .await
+        .unwrap();
+    assert_eq!(after, before);
+
+    let templates = repo.get_note_templates().await.unwrap();
+    assert_eq!(templates.len(), 1);
+    assert_eq!(templates[0].tag_ids, vec![2]);
+    repo.delete_note_template(template).await.unwrap();
+    assert!(repo.get_note_template(template).await.unwrap().is_none());
+    assert!(repo
+        .create_note_from_template(template, None, "", &data_dir)
+        .await
+        .is_err());
+
+    repo.pool.close().await;
+}
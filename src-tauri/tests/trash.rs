@@ -0,0 +1,329 @@
+mod common;
+
+use common::{seeded_repo, write_file, TempDir};
+use notes_classic_lib::db::{RepoError, SqliteRepository};
+use notes_classic_lib::services::purge_expired_trash;
+
+/// Notes 1-250 trashed at times 1-250, note 300 in Work (1) and not trashed.
+/// Note 2 has an attachment stored at `files/aa/old.pdf`.
+const SEED: &[&str] = &[
+    "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+     VALUES (1, 'Work', 0, NULL, 'notebook', 0)",
+    "WITH RECURSIVE seq(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM seq WHERE n < 250)
+     INSERT INTO notes (id, title, content, created_at, updated_at, deleted_at)
+     SELECT n, 'Trashed ' || n, '', 0, 0, n FROM seq",
+    "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+     VALUES (300, 'Kept', '', 0, 0, 1)",
+    "INSERT INTO attachments (id, note_id, filename, mime, size, local_path)
+     VALUES (1, 2, 'old.pdf', 'application/pdf', 3, 'files/aa/old.pdf')",
+];
+
+#[tokio::test]
+async fn purge_deletes_notes_trashed_before_cutoff() {
+    let data_dir = TempDir::new("trash-purge");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    write_file(&data_dir, "files/aa/old.pdf", b"pdf");
+
+    let preview: Vec<i64> = repo
+        .get_trashed_notes_before(4)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|note| note.id)
+        .collect();
+    assert_eq!(preview, vec![1, 2, 3]);
+
+    assert_eq!(repo.purge_trashed_notes(201, &data_dir).await.unwrap(), 200);
+    assert!(!data_dir.join("files/aa/old.pdf").exists());
+    assert_eq!(repo.get_trashed_notes().await.unwrap().len(), 50);
+    assert!(repo.get_note(201).await.unwrap().is_some());
+
+    assert_eq!(repo.delete_all_trashed_notes(&data_dir).await.unwrap(), 50);
+    assert!(repo.get_trashed_notes().await.unwrap().is_empty());
+    assert!(repo.get_note(300).await.unwrap().is_some());
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn retention_setting_purges_expired_trash() {
+    let data_dir = TempDir::new("trash-retention");
+    let settings_dir = TempDir::new("trash-retention-settings");
+    let repo = seeded_repo(&data_dir, SEED).await;
+    let retain = |days: i64| {
+        write_file(
+            &settings_dir,
+            "app.json",
+            format!(r#"{{"trashRetentionDays":{}}}"#, days).as_bytes(),
+        )
+    };
+
+    retain(i64::MAX);
+    assert_eq!(
+        purge_expired_trash(&repo.pool, &settings_dir, &data_dir)
+            .await
+            .unwrap(),
+        0
+    );
+    retain(1);
+    assert_eq!(
+        purge_expired_trash(&repo.pool, &settings_dir, &data_dir)
+            .await
+            .unwrap(),
+        250
+    );
+    assert!(repo.get_note(300).await.unwrap().is_some());
+
+    repo.pool.close().await;
+}
+
+async fn children(repo: &SqliteRepository, parent_id: Option<i64>) -> Vec<(i64, String)> {
+    repo.get_notebooks()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|notebook| notebook.parent_id == parent_id)
+        .map(|notebook| (notebook.id, notebook.name))
+        .collect()
+}
+
+#[tokio::test]
+async fn trashed_notebooks_restore_with_their_stack_and_notes() {
+    let data_dir = TempDir::new("trash-notebooks");
+    let repo = seeded_repo(
+        &data_dir,
+        &[
+            "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+             VALUES (10, 'Work', 0, NULL, 'stack', 0), (11, 'Home', 0, NULL, 'stack', 1),
+                    (12, 'Projects', 0, 10, 'notebook', 0), (13, 'Meetings', 0, 10, 'notebook', 1),
+                    (14, 'Inbox', 0, 10, 'notebook', 2), (15, 'Recipes', 0, 11, 'notebook', 0)",
+            "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+             VALUES (20, 'Roadmap', '', 0, 0, 12), (21, 'Standup', '', 0, 0, 13),
+                    (22, 'Old idea', '', 0, 0, NULL), (23, 'Soup', '', 0, 0, 15)",
+            "UPDATE notes SET deleted_at = 5, deleted_from_notebook_id = 14 WHERE id = 22",
+        ],
+    )
+    .await;
+
+    repo.delete_notebook(13).await.unwrap();
+    repo.delete_notebook(10).await.unwrap();
+    assert_eq!(children(&repo, None).await, vec![(11, "Home".to_string())]);
+    assert_eq!(repo.get_trashed_notes().await.unwrap().len(), 3);
+    let mut trashed: Vec<(i64, i64, i64)> = repo
+        .get_trashed_notebooks()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|notebook| (notebook.id, notebook.notebook_count, notebook.note_count))
+        .collect();
+    trashed.sort();
+    assert_eq!(trashed, vec![(10, 2, 1), (13, 0, 1)]);
+
+    // Meetings brings back its stack; the stack keeps its place first.
+    repo.restore_notebook(13).await.unwrap();
+    assert!(repo.get_trashed_notebooks().await.unwrap().is_empty());
+    assert_eq!(
+        children(&repo, None).await,
+        vec![(10, "Work".to_string()), (11, "Home".to_string())]
+    );
+    assert_eq!(
+        children(&repo, Some(10)).await,
+        vec![
+            (12, "Projects".to_string()),
+            (13, "Meetings".to_string()),
+            (14, "Inbox".to_string())
+        ]
+    );
+    assert_eq!(
+        repo.get_note(20).await.unwrap().unwrap().notebook_id,
+        Some(12)
+    );
+    assert_eq!(
+        repo.get_note(21).await.unwrap().unwrap().notebook_id,
+        Some(13)
+    );
+    assert_eq!(repo.get_trashed_notes().await.unwrap().len(), 1);
+    repo.restore_note(22).await.unwrap();
+    assert_eq!(
+        repo.get_note(22).await.unwrap().unwrap().notebook_id,
+        Some(14)
+    );
+
+    // An id taken while the notebook was in the trash is not reused.
+    repo.delete_notebook(12).await.unwrap();
+    sqlx::query(
+        "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+         VALUES (12, 'Imported', 0, 11, 'notebook', 1)",
+    )
+    .execute(&repo.pool)
+    .await
+    .unwrap();
+    repo.restore_notebook(12).await.unwrap();
+    let restored = children(&repo, Some(10)).await;
+    assert_eq!(restored[0].1, "Projects");
+    assert_ne!(restored[0].0, 12);
+    assert_eq!(
+        repo.get_note(20).await.unwrap().unwrap().notebook_id,
+        Some(restored[0].0)
+    );
+
+    repo.delete_notebook(11).await.unwrap();
+    repo.delete_trashed_notebook(11, &data_dir).await.unwrap();
+    assert!(repo.get_trashed_notebooks().await.unwrap().is_empty());
+    assert!(repo.get_note(23).await.unwrap().is_none());
+    assert!(matches!(
+        repo.restore_notebook(11).await,
+        Err(RepoError::Database(sqlx::Error::RowNotFound))
+    ));
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn notes_trashed_before_their_notebook_follow_it_back() {
+    let data_dir = TempDir::new("trash-earlier-notes");
+    let repo = seeded_repo(
+        &data_dir,
+        &[
+            "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+             VALUES (10, 'Work', 0, NULL, 'notebook', 0)",
+            "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+             VALUES (20, 'Plan', '', 0, 0, 10), (21, 'Draft', '', 0, 0, 10),
+                    (22, 'Imported draft', '', 0, 0, 10)",
+            // Trashed notes imported from elsewhere may still carry their notebook.
+            "UPDATE notes SET deleted_at = 5 WHERE id = 22",
+        ],
+    )
+    .await;
+    repo.trash_note(21).await.unwrap();
+
+    repo.delete_notebook(10).await.unwrap();
+    repo.restore_notebook(10).await.unwrap();
+    assert_eq!(
+        repo.get_note(20).await.unwrap().unwrap().notebook_id,
+        Some(10)
+    );
+    // Only the notes trashed with the notebook come back with it.
+    assert_eq!(repo.get_trashed_notes().await.unwrap().len(), 2);
+
+    repo.restore_note(21).await.unwrap();
+    repo.restore_note(22).await.unwrap();
+    assert_eq!(
+        repo.get_note(21).await.unwrap().unwrap().notebook_id,
+        Some(10)
+    );
+    assert_eq!(
+        repo.get_note(22).await.unwrap().unwrap().notebook_id,
+        Some(10)
+    );
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn restore_all_skips_notebooks_without_a_stack_to_return_to() {
+    let data_dir = TempDir::new("trash-restore-all");
+    let repo = seeded_repo(
+        &data_dir,
+        &[
+            "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+             VALUES (10, 'Work', 0, NULL, 'stack', 0), (11, 'Home', 0, NULL, 'stack', 1),
+                    (12, 'Projects', 0, 10, 'notebook', 0), (13, 'Meetings', 0, 10, 'notebook', 1),
+                    (15, 'Recipes', 0, 11, 'notebook', 0)",
+            "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+             VALUES (20, 'Roadmap', '', 0, 0, 12), (21, 'Standup', '', 0, 0, 13),
+                    (23, 'Soup', '', 0, 0, 15)",
+        ],
+    )
+    .await;
+
+    repo.delete_notebook(13).await.unwrap();
+    repo.delete_notebook(10).await.unwrap();
+    repo.delete_notebook(15).await.unwrap();
+    repo.delete_notebook(11).await.unwrap();
+    repo.delete_trashed_notebook(11, &data_dir).await.unwrap();
+    // Meetings comes first and brings Work back before Work's own turn.
+    sqlx::query("UPDATE trashed_notebooks SET deleted_at = 1 WHERE id = 13")
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+
+    repo.restore_all_notes().await.unwrap();
+    assert_eq!(children(&repo, None).await, vec![(10, "Work".to_string())]);
+    assert_eq!(
+        children(&repo, Some(10)).await,
+        vec![(12, "Projects".to_string()), (13, "Meetings".to_string())]
+    );
+    assert_eq!(
+        repo.get_note(20).await.unwrap().unwrap().notebook_id,
+        Some(12)
+    );
+    assert_eq!(
+        repo.get_note(21).await.unwrap().unwrap().notebook_id,
+        Some(13)
+    );
+    let soup = repo.get_note(23).await.unwrap().unwrap();
+    assert_eq!(soup.notebook_id, None);
+    assert!(repo.get_trashed_notes().await.unwrap().is_empty());
+
+    repo.pool.close().await;
+}
+
+#[tokio::test]
+async fn purge_removes_trashed_notebooks_together_with_their_notes() {
+    let data_dir = TempDir::new("trash-purge-notebooks");
+    let repo = seeded_repo(
+        &data_dir,
+        &[
+            "INSERT INTO notebooks (id, name, created_at, parent_id, notebook_type, sort_order)
+             VALUES (10, 'Work', 0, NULL, 'stack', 0), (11, 'Home', 0, NULL, 'stack', 1),
+                    (12, 'Projects', 0, 10, 'notebook', 0), (15, 'Recipes', 0, 11, 'notebook', 0)",
+            "INSERT INTO notes (id, title, content, created_at, updated_at, notebook_id)
+             VALUES (20, 'Roadmap', '', 0, 0, 12), (23, 'Soup', '', 0, 0, 15)",
+        ],
+    )
+    .await;
+    repo.delete_notebook(10).await.unwrap();
+    repo.delete_notebook(11).await.unwrap();
+    // Work expired long ago; Home's notes look old but Home itself does not.
+    sqlx::query("UPDATE trashed_notebooks SET deleted_at = 5 WHERE trash_root_id = 10")
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE notes SET deleted_at = 5 WHERE id = 23")
+        .execute(&repo.pool)
+        .await
+        .unwrap();
+
+    let preview: Vec<i64> = repo
+        .get_trashed_notes_before(100)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|note| note.id)
+        .collect();
+    assert_eq!(preview, vec![20]);
+
+    assert_eq!(repo.purge_trashed_notes(100, &data_dir).await.unwrap(), 1);
+    assert!(repo.get_note(20).await.unwrap().is_none());
+    let trashed: Vec<i64> = repo
+        .get_trashed_notebooks()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|notebook| notebook.id)
+        .collect();
+    assert_eq!(trashed, vec![11]);
+
+    repo.restore_notebook(11).await.unwrap();
+    assert_eq!(
+        children(&repo, Some(11)).await,
+        vec![(15, "Recipes".to_string())]
+    );
+    assert_eq!(
+        repo.get_note(23).await.unwrap().unwrap().notebook_id,
+        Some(15)
+    );
+
+    repo.pool.close().await;
+}
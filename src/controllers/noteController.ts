@@ -1,83 +1,11 @@
 import { appStore } from "../state/store";
 import { logError } from "../services/logger";
 import { openConfirmDialog, openRenameNoteDialog } from "../ui/dialogs";
-import { createNote, deleteAllTrashedNotes, deleteNote, getNote, moveNote, restoreAllNotes, restoreNote, trashNote, updateNote } from "../services/notes";
+import { createNote, deleteAllTrashedNotes, deleteNote, duplicateNote, getNote, moveNote, restoreAllNotes, restoreNote, trashNote, updateNote } from "../services/notes";
 import { loadSelectedNote } from "./noteLoader";
 import { t, tCount } from "../services/i18n";
-import { getDataDir } from "../services/storage";
-import { importAttachmentBytes, readAttachmentBytes } from "../services/attachments";
-import { storeNoteFileFromPath } from "../services/noteFiles";
 import { toStorageContent } from "../services/content";
 
-const normalizeFilePath = (value: string) => value.replace(/\\/g, "/");
-
-const resolveImagePath = (src: string, dataDir: string) => {
-  if (!src) return null;
-  const normalizedDataDir = normalizeFilePath(dataDir).replace(/\/$/, "");
-  const normalizedSrc = src.trim();
-  if (normalizedSrc.startsWith("files/")) {
-    return `${normalizedDataDir}/files/${normalizedSrc.slice("files/".length)}`;
-  }
-  if (normalizedSrc.startsWith("./files/")) {
-    return `${normalizedDataDir}/files/${normalizedSrc.slice("./files/".length)}`;
-  }
-  try {
-    const url = new URL(normalizedSrc);
-    if (url.hostname === "asset.localhost") {
-      const decoded = decodeURIComponent(url.pathname.replace(/^\/+/, ""));
-      return decoded.replace(/^\/\/\?\//, "");
-    }
-  } catch {
-    return null;
-  }
-  return null;
-};
-
-const duplicateContent = async (content: string, newNoteId: number) => {
-  const parser = new DOMParser();
-  const doc = parser.parseFromString(content, "text/html");
-  const dataDir = await getDataDir();
-
-  const images = Array.from(doc.querySelectorAll<HTMLImageElement>("img"));
-  for (const img of images) {
-    const src = img.getAttribute("src") || "";
-    const localPath = resolveImagePath(src, dataDir);
-    if (!localPath) continue;
-    try {
-      const stored = await storeNoteFileFromPath(localPath);
-      img.setAttribute("src", `files/${stored.rel_path}`);
-      img.setAttribute("data-en-hash", stored.hash);
-    } catch (e) {
-      logError("[note] duplicate image failed", e);
-    }
-  }
-
-  const attachments = Array.from(doc.querySelectorAll<HTMLElement>(".note-attachment"));
-  for (const attachment of attachments) {
-    if (attachment.dataset.attachmentEmbedded === "1") continue;
-    const idRaw = attachment.dataset.attachmentId;
-    if (!idRaw) continue;
-    const id = Number(idRaw);
-    if (!Number.isFinite(id)) continue;
-    const name = attachment.dataset.attachmentName || t("attachments.default_name");
-    const mime = attachment.dataset.attachmentMime || "application/octet-stream";
-    try {
-      const bytes = await readAttachmentBytes(id);
-      const info = await importAttachmentBytes(newNoteId, name, mime, bytes);
-      attachment.dataset.attachmentId = String(info.id);
-      attachment.dataset.attachmentName = info.filename;
-      attachment.dataset.attachmentSize = String(info.size);
-      attachment.dataset.attachmentMime = info.mime;
-      const nameEl = attachment.querySelector<HTMLElement>(".note-attachment__name");
-      if (nameEl) nameEl.textContent = info.filename;
-    } catch (e) {
-      logError("[note] duplicate attachment failed", e);
-    }
-  }
-
-  return doc.body.innerHTML;
-};
-
 export const createNoteActions = (fetchData: () => Promise<void>, selectNote: (id: number) => Promise<void>) => {
   const deleteNotesInternal = async (ids: number[]) => {
     const unique = Array.from(new Set(ids)).filter((id) => Number.isFinite(id));
@@ -206,10 +134,7 @@ export const createNoteActions = (fetchData: () => Promise<void>, selectNote: (i
       const suffix = t("note.duplicate_suffix");
       const newTitle = `${baseTitle} ${suffix}`.trim();
       try {
-        const initialContent = toStorageContent(note.content);
-        const newId = await createNote(newTitle, initialContent, note.notebookId ?? null);
-        const duplicated = await duplicateContent(note.content, newId);
-        await updateNote(newId, newTitle, toStorageContent(duplicated), note.notebookId ?? null);
+        const newId = await duplicateNote(id, newTitle);
         await fetchData();
         await selectNote(newId);
       } catch (e) {
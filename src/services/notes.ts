@@ -1,5 +1,12 @@
 import { invoke } from "@tauri-apps/api/core";
-import type { NoteCounts, NoteDetail, NoteLinkItem, NoteListItem, Notebook } from "../state/types";
+import type {
+  NoteCounts,
+  NoteDetail,
+  NoteLinkItem,
+  NoteListItem,
+  Notebook,
+  TrashedNotebook,
+} from "../state/types";
 
 export const getNotebooks = () => invoke<Notebook[]>("get_notebooks");
 
@@ -31,6 +38,9 @@ export const createNote = (title: string, content: string, notebookId: number |
 export const updateNote = (id: number, title: string, content: string, notebookId: number | null) =>
   invoke("upsert_note", { id, title, content, notebookId });
 
+export const duplicateNote = (id: number, title: string | null = null) =>
+  invoke<number>("duplicate_note", { id, title });
+
 export const setNotesListView = (view: "compact" | "detailed") =>
   invoke("set_notes_list_view", { view });
 
@@ -52,6 +62,12 @@ export const createNotebook = (name: string, parentId: number | null) =>
 
 export const deleteNotebook = (id: number) => invoke("delete_notebook", { id });
 
+export const getTrashedNotebooks = () => invoke<TrashedNotebook[]>("get_trashed_notebooks");
+
+export const restoreNotebook = (id: number) => invoke("restore_notebook", { id });
+
+export const deleteTrashedNotebook = (id: number) => invoke("delete_trashed_notebook", { id });
+
 export const renameNotebook = (id: number, name: string) =>
   invoke("rename_notebook", { id, name });
 
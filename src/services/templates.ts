@@ -0,0 +1,20 @@
+import { invoke } from "@tauri-apps/api/core";
+import type { NoteTemplate } from "../state/types";
+
+export type NoteTemplateInput = Pick<
+  NoteTemplate,
+  "name" | "title" | "content" | "notebookId" | "tagIds"
+>;
+
+export const getNoteTemplates = () => invoke<NoteTemplate[]>("get_note_templates");
+
+export const createNoteTemplate = (template: NoteTemplateInput) =>
+  invoke<number>("create_note_template", { ...template });
+
+export const updateNoteTemplate = (id: number, template: NoteTemplateInput) =>
+  invoke("update_note_template", { id, ...template });
+
+export const deleteNoteTemplate = (id: number) => invoke("delete_note_template", { id });
+
+export const createNoteFromTemplate = (templateId: number, notebookId: number | null = null) =>
+  invoke<number>("create_note_from_template", { templateId, notebookId });
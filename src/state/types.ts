@@ -7,6 +7,16 @@ export interface Notebook {
   externalId?: string | null;
 }
 
+export interface TrashedNotebook {
+  id: number;
+  name: string;
+  parentId: number | null;
+  notebookType: "stack" | "notebook";
+  deletedAt: number;
+  notebookCount: number;
+  noteCount: number;
+}
+
 export interface NoteListItem {
   id: number;
   title: string;
@@ -15,6 +25,7 @@ export interface NoteListItem {
   updatedAt: number;
   notebookId: number | null;
   ocrMatch?: boolean;
+  attachmentMatch?: boolean;
 }
 
 export interface NoteDetail {
@@ -46,9 +57,28 @@ export interface Tag {
 export interface NoteCounts {
   total: number;
   perNotebook: { notebookId: number; count: number }[];
+  perTag?: { tagId: number; count: number }[];
   trashed: number;
 }
 
+export interface BulkNoteResult {
+  noteId: number;
+  ok: boolean;
+  error?: string | null;
+  newNoteId?: number | null;
+}
+
+export interface NoteTemplate {
+  id: number;
+  name: string;
+  title: string;
+  content: string;
+  notebookId: number | null;
+  tagIds: number[];
+  createdAt: number;
+  updatedAt: number;
+}
+
 export type NotesListView = "detailed" | "compact";
 export type NotesSortBy = "updated" | "title";
 export type NotesSortDir = "asc" | "desc";
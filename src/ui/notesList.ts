@@ -10,6 +10,7 @@ export interface NotesListItem {
   updatedAt: number;
   notebookId: number | null;
   ocrMatch?: boolean;
+  attachmentMatch?: boolean;
 }
 
 export interface NotesListNotebook {